@@ -15,8 +15,9 @@
 
 @define STYLE 0x0F
 
-/// math = https://github.com/commonkestrel/fateful_math
-@include <math/mul.asm>
+/// os = os
+@include <os/math.asm>
+@extern mul16
 
 @cseg
 @org 0x0000
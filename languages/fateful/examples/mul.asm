@@ -3,8 +3,9 @@
 /// h: 0x02
 /// l: 0x58
 
-/// math = https://github.com/commonkestrel/fateful_math
-@include <math/mul.asm>
+/// os = os
+@include <os/math.asm>
+@extern mul16
 
 @cseg
 @org 0x0000
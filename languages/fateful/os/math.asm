@@ -1,6 +1,4 @@
-@include <arithmetic>
-@include <flow>
-@include <util>
+@global mul
 
 /// Arguments: A, B
 /// Return: A
@@ -8,11 +6,35 @@ mul:
     push B, C
     mv C, B
     mv B, A
+    mv A, 0
     jz C, [.done]
-.loop
+.loop:
     add A, B
     dec C
     jnz C, [.loop]
 .done:
     pop C, B
     ret
+
+@global mul16
+
+/// Arguments: (stack) A low, A high, B low, B high
+/// Return: (stack) low, high
+/// Clobbers: A, B, C, D, E, F
+mul16:
+    pop H, L ; save return address
+    pop D, C, B, A ; B high, B low, A high, A low
+    push L, H ; restore return address
+    mv E, 0 ; product low
+    mv F, 0 ; product high
+.loop:
+    jnz C, [.add]
+    jnz D, [.add]
+    pop H, L ; save return address
+    push E, F ; push product
+    push L, H ; restore return address
+    ret
+.add:
+    add16 F, E, B, A
+    dec D, C
+    jmp [.loop]
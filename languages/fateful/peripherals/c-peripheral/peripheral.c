@@ -25,3 +25,9 @@ __declspec(dllexport) void reset(void) {
 __declspec(dllexport) const char* name(void) {
     return "C Example";
 }
+
+__declspec(dllexport) const char* debug_info(void) {
+    static char info[16];
+    snprintf(info, sizeof(info), "STATE: 0x%02X", STATE);
+    return info;
+}
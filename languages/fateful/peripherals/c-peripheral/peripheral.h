@@ -5,3 +5,4 @@ __declspec(dllexport) uint8_t read(uint8_t);
 __declspec(dllexport) void write(uint8_t, uint8_t);
 __declspec(dllexport) void reset(void);
 __declspec(dllexport) const char* name(void);
+__declspec(dllexport) const char* debug_info(void);
@@ -1,4 +1,5 @@
 use std::{
+    ffi::{c_char, c_void, CString},
     sync::mpsc::{channel, Receiver, Sender},
     thread::{self, JoinHandle},
 };
@@ -18,6 +19,8 @@ struct State {
     command: Option<u8>,
     addr_low: Option<u8>,
     addr_high: Option<u8>,
+    /// The last string returned by `stateful_debug_info`, kept alive until the next call.
+    debug_info: CString,
     tx: Sender<Event>,
     handle: JoinHandle<()>,
 }
@@ -38,6 +41,7 @@ impl Peripheral for State {
             command: None,
             addr_low: None,
             addr_high: None,
+            debug_info: CString::default(),
         })
     }
 
@@ -71,6 +75,35 @@ impl Peripheral for State {
     }
 }
 
+impl State {
+    fn debug_info(&self) -> String {
+        let byte = |byte: Option<u8>| match byte {
+            Some(byte) => format!("{byte:#04X}"),
+            None => "--".to_owned(),
+        };
+
+        format!(
+            "COMMAND: {}, ADDRESS: {} {}",
+            byte(self.command),
+            byte(self.addr_high),
+            byte(self.addr_low),
+        )
+    }
+}
+
+/// Describes the pending command and address bytes, shown when the emulator is `DUMP`ed.
+///
+/// # Safety
+///
+/// `state` must be the pointer returned by `stateful_init`.
+#[no_mangle]
+pub unsafe extern "C" fn stateful_debug_info(state: *mut c_void) -> *const c_char {
+    let state = &mut *(state as *mut State);
+    // The formatted string never contains a null byte
+    state.debug_info = CString::new(state.debug_info()).unwrap_or_default();
+    state.debug_info.as_ptr()
+}
+
 fn window(rx: Receiver<Event>) {
     let mut window = Window::new(
         "F8ful Screen",
@@ -4,13 +4,18 @@
 
 mod ascii;
 mod eval;
+pub mod format;
 pub mod generator;
 mod include;
 pub mod lex;
+mod linker;
+mod listing;
+pub mod object;
 pub mod parse;
 mod token;
 pub use crate::diagnostic::Diagnostic;
 use crate::error;
+pub use linker::{link, LinkerArgs, LinkerError};
 
 pub mod tests {
     pub use super::{generator, lex, parse};
@@ -21,6 +26,7 @@ use std::time::Instant;
 use clap::Args;
 use clio::{Input, Output};
 use colored::Colorize;
+use format::Format;
 use thiserror::Error;
 
 #[derive(Debug, Args)]
@@ -35,6 +41,24 @@ pub struct AssemblerArgs {
     input: Input,
     #[clap(short, long, value_parser, default_value = "-")]
     output: Output,
+    /// Assemble into a relocatable object file instead of a program ROM.
+    ///
+    /// Object files can be linked into a program with `fateful link`.
+    #[clap(short = 'c', long = "object", conflicts_with_all = ["format", "symbols", "listing"])]
+    object: bool,
+    /// Format of the assembled program.
+    ///
+    /// `ihex`, `srec`, and `trimmed` only include the addresses occupied by code segments.
+    #[clap(long, value_enum, default_value_t = Format::Bin)]
+    format: Format,
+    /// Write a symbol file mapping labels, variables, and source lines to addresses.
+    ///
+    /// Can be loaded by the emulator with `--symbols`.
+    #[clap(long, value_parser)]
+    symbols: Option<Output>,
+    /// Write a listing of the address, bytes, source line, and macro expansions of every instruction.
+    #[clap(long, value_parser)]
+    listing: Option<Output>,
 }
 
 #[derive(Debug, Error)]
@@ -60,16 +84,53 @@ pub fn assemble(mut args: AssemblerArgs) -> Result<(), AssemblerError> {
 
     let lexed = lex::lex(args.input)?;
     let parsed = parse::parse(lexed)?;
+
+    if args.object {
+        let object = generator::generate_object(parsed)?;
+        write!(args.output.lock(), "{object}")
+            .map_err(|err| error!("failed to write to output: {err}"))?;
+        args.output
+            .finish()
+            .map_err(|err| error!("failed to finalize output: {err}"))?;
+        finished(&input, start);
+        return Ok(());
+    }
+
     let assembled = generator::generate(parsed)?;
 
-    args.output
-        .lock()
-        .write_all(&assembled)
-        .map_err(|err| error!("failed to write to output: {err}"))?;
+    format::write(
+        args.format,
+        &assembled.program,
+        &assembled.ranges,
+        args.output.lock(),
+    )
+    .map_err(|err| error!("failed to write to output: {err}"))?;
     args.output
         .finish()
         .map_err(|err| error!("failed to finalize output: {err}"))?;
 
+    if let Some(mut symbols) = args.symbols {
+        write!(symbols.lock(), "{}", assembled.symbols)
+            .map_err(|err| error!("failed to write symbol file: {err}"))?;
+        symbols
+            .finish()
+            .map_err(|err| error!("failed to finalize symbol file: {err}"))?;
+    }
+
+    if let Some(mut listing) = args.listing {
+        write!(listing.lock(), "{}", assembled.listing)
+            .map_err(|err| error!("failed to write listing: {err}"))?;
+        listing
+            .finish()
+            .map_err(|err| error!("failed to finalize listing: {err}"))?;
+    }
+
+    finished(&input, start);
+
+    Ok(())
+}
+
+fn finished(input: &str, start: Instant) {
     let elapsed = start.elapsed().as_millis();
     let seconds = elapsed / 1000;
     let millis = elapsed % 1000;
@@ -78,6 +139,4 @@ pub fn assemble(mut args: AssemblerArgs) -> Result<(), AssemblerError> {
         "Finished".green().bold(),
         input.trim_matches('"')
     );
-
-    Ok(())
 }
@@ -105,6 +105,16 @@ pub fn eval_bracketed(
     })
 }
 
+/// Evaluates an expression using both labels and variables,
+/// such as the relocation expressions in object files.
+pub fn eval_tokens(
+    tokens: &[Token],
+    labels: &mut HashMap<String, Usable>,
+    variables: &mut HashMap<String, Usable>,
+) -> Result<i128, Diagnostic> {
+    Tree::parse(tokens, &HashMap::new(), labels, variables).map(|tree| tree.eval())
+}
+
 pub fn eval_preproc(
     tokens: &[Token],
     defines: &HashMap<String, TokenStream>,
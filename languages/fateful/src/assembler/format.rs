@@ -0,0 +1,193 @@
+//! Output formats for assembled programs.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    ops::Range,
+};
+
+use clap::ValueEnum;
+
+/// The most data bytes written in a single HEX or S-record.
+const RECORD_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// The full 64 KiB program ROM
+    Bin,
+    /// Intel HEX records for each occupied range
+    Ihex,
+    /// Motorola S-records for each occupied range
+    Srec,
+    /// The program ROM up to the end of the last occupied range
+    Trimmed,
+}
+
+/// Sorts the given ranges, merging any that overlap or are adjacent.
+pub fn merge(mut ranges: Vec<Range<u16>>) -> Vec<Range<u16>> {
+    ranges.sort_by_key(|range| range.start);
+    ranges
+        .into_iter()
+        .fold(Vec::new(), |mut merged: Vec<Range<u16>>, range| {
+            match merged.last_mut() {
+                Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+            merged
+        })
+}
+
+/// Writes `program` in the given format.
+/// `ranges` are the occupied addresses, which must be sorted and not overlap.
+pub fn write(
+    format: Format,
+    program: &[u8; 1 << 16],
+    ranges: &[Range<u16>],
+    mut writer: impl Write,
+) -> io::Result<()> {
+    match format {
+        Format::Bin => writer.write_all(program),
+        Format::Trimmed => {
+            // Raw images don't have addresses, so any gaps between segments are kept
+            let end = ranges.last().map(|range| range.end).unwrap_or(0);
+            writer.write_all(&program[..end as usize])
+        }
+        Format::Ihex => {
+            for (addr, data) in records(program, ranges) {
+                writeln!(writer, "{}", ihex_record(0x00, addr, data))?;
+            }
+            writeln!(writer, "{}", ihex_record(0x01, 0x0000, &[]))
+        }
+        Format::Srec => {
+            writeln!(writer, "{}", srec_record(0, 0x0000, b"fateful"))?;
+            let mut count = 0u16;
+            for (addr, data) in records(program, ranges) {
+                writeln!(writer, "{}", srec_record(1, addr, data))?;
+                count = count.wrapping_add(1);
+            }
+            writeln!(writer, "{}", srec_record(5, count, &[]))?;
+            writeln!(writer, "{}", srec_record(9, 0x0000, &[]))
+        }
+    }
+}
+
+/// Splits each range into chunks small enough for a single record.
+fn records<'a>(
+    program: &'a [u8; 1 << 16],
+    ranges: &'a [Range<u16>],
+) -> impl Iterator<Item = (u16, &'a [u8])> {
+    ranges.iter().flat_map(move |range| {
+        program[range.start as usize..range.end as usize]
+            .chunks(RECORD_SIZE)
+            .enumerate()
+            .map(move |(i, chunk)| (range.start + (i * RECORD_SIZE) as u16, chunk))
+    })
+}
+
+fn ihex_record(kind: u8, addr: u16, data: &[u8]) -> String {
+    let [high, low] = addr.to_be_bytes();
+    let header = [data.len() as u8, high, low, kind];
+    let sum = header
+        .iter()
+        .chain(data)
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+    let mut record = ":".to_owned();
+    for byte in header.iter().chain(data) {
+        // Writing to a `String` is infallible
+        let _ = write!(record, "{byte:02X}");
+    }
+    let _ = write!(record, "{:02X}", sum.wrapping_neg());
+    record
+}
+
+fn srec_record(kind: u8, addr: u16, data: &[u8]) -> String {
+    let [high, low] = addr.to_be_bytes();
+    // The count includes the address and checksum
+    let header = [data.len() as u8 + 3, high, low];
+    let sum = header
+        .iter()
+        .chain(data)
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+    let mut record = format!("S{kind}");
+    for byte in header.iter().chain(data) {
+        // Writing to a `String` is infallible
+        let _ = write!(record, "{byte:02X}");
+    }
+    let _ = write!(record, "{:02X}", !sum);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(ranges: &[(u16, &[u8])]) -> Box<[u8; 1 << 16]> {
+        let mut program = Box::new([0; 1 << 16]);
+        for (start, data) in ranges {
+            program[*start as usize..*start as usize + data.len()].copy_from_slice(data);
+        }
+        program
+    }
+
+    fn output(format: Format, program: &[u8; 1 << 16], ranges: &[Range<u16>]) -> String {
+        let mut out = Vec::new();
+        write(format, program, ranges, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn ihex() {
+        let data = [
+            0x21, 0x46, 0x01, 0x36, 0x01, 0x21, 0x47, 0x01, 0x36, 0x00, 0x7E, 0xFE, 0x09, 0xD2,
+            0x19, 0x01, 0x21,
+        ];
+        let program = program(&[(0x0100, &data), (0x2000, &[0xF0])]);
+
+        assert_eq!(
+            output(Format::Ihex, &program, &[0x0100..0x0111, 0x2000..0x2001]),
+            "\
+                :10010000214601360121470136007EFE09D2190140\n\
+                :0101100021CD\n\
+                :01200000F0EF\n\
+                :00000001FF\n\
+            "
+        );
+    }
+
+    #[test]
+    fn srec() {
+        let data = [0x0A, 0x0A, 0x0D, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let program = program(&[(0x7AF0, &data)]);
+
+        assert_eq!(
+            output(Format::Srec, &program, &[0x7AF0..0x7B00]),
+            "\
+                S00A00006661746566756C0E\n\
+                S1137AF00A0A0D0000000000000000000000000061\n\
+                S5030001FB\n\
+                S9030000FC\n\
+            "
+        );
+    }
+
+    #[test]
+    fn trimmed() {
+        let program = program(&[(0x0000, &[0x71, 0x02]), (0x0010, &[0xF0])]);
+        let mut out = Vec::new();
+        write(
+            Format::Trimmed,
+            &program,
+            &[0x0000..0x0002, 0x0010..0x0011],
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(out.len(), 0x11);
+        assert_eq!((out[1], out[0x10]), (0x02, 0xF0));
+
+        let mut out = Vec::new();
+        write(Format::Trimmed, &program, &[], &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+}
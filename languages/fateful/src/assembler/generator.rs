@@ -5,13 +5,17 @@ use std::sync::Arc;
 use crate::spanned_warn;
 use crate::{
     assembler::{
-        eval,
+        eval, format,
         lex::{Ident, Span, Token, TokenInner},
+        listing::{Invocation, Listing},
+        object::{self, Kind, Local, Object, Relocation, Section, Symbol},
         parse::DSeg,
-        token::Immediate,
+        token::{self, Immediate},
     },
     diagnostic::Reference,
-    spanned_error, Token,
+    spanned_error,
+    symbols::Symbols,
+    Token,
 };
 
 use super::{
@@ -47,6 +51,169 @@ pub struct Usable {
     pub uses: usize,
 }
 
+/// An assembled program, along with the symbols used to create it.
+pub struct Generated {
+    pub program: [u8; 1 << 16],
+    /// The addresses occupied by code segments, sorted and with adjacent ranges merged.
+    pub ranges: Vec<Range<u16>>,
+    pub symbols: Symbols,
+    pub listing: Listing,
+}
+
+/// The labels visible from each file.
+///
+/// Labels are only visible in the file that defines them, unless they're exported with `@global`,
+/// in which case other files can use them after declaring them with `@extern`.
+/// Each label is stored under a key: its name if it's global,
+/// or its name followed by `@` and the scope of its file otherwise,
+/// so that labels with the same name in different files don't collide.
+#[derive(Default)]
+struct Scopes {
+    /// The source of each file, indexed by scope.
+    files: Vec<String>,
+    globals: HashMap<(usize, String), Arc<Span>>,
+    externs: HashMap<(usize, String), Arc<Span>>,
+    /// The key and span of every label, by the scope it was defined in.
+    defined: HashMap<(usize, String), (String, Arc<Span>)>,
+}
+
+impl Scopes {
+    fn new(globals: Vec<token::Ident>, externs: Vec<token::Ident>) -> Self {
+        let mut scopes = Scopes::default();
+
+        for global in globals {
+            let scope = scopes.scope(&global.span);
+            scopes.globals.insert((scope, global.value), global.span);
+        }
+        for external in externs {
+            let scope = scopes.scope(&external.span);
+            scopes
+                .externs
+                .insert((scope, external.value), external.span);
+        }
+
+        scopes
+    }
+
+    /// The scope of the file containing `span`.
+    fn scope(&mut self, span: &Span) -> usize {
+        let file = span.source.to_string();
+        match self.files.iter().position(|f| *f == file) {
+            Some(scope) => scope,
+            None => {
+                self.files.push(file);
+                self.files.len() - 1
+            }
+        }
+    }
+
+    /// Defines a label in the file containing `span`, returning its key.
+    fn define(&mut self, name: String, span: Arc<Span>) -> String {
+        let scope = self.scope(&span);
+        let key = if self.globals.contains_key(&(scope, name.clone())) {
+            name.clone()
+        } else {
+            format!("{name}@{scope}")
+        };
+
+        self.defined.insert((scope, name), (key.clone(), span));
+        key
+    }
+
+    /// Checks that every `@global` label is defined,
+    /// and, if `resolve_externs` is set, that every `@extern` label is exported by another file.
+    fn check(&self, resolve_externs: bool) -> Errors {
+        let mut errors = Errors::new();
+
+        for ((scope, name), span) in self.globals.iter() {
+            if !self.defined.contains_key(&(*scope, name.to_owned())) {
+                errors.push(spanned_error!(
+                    span.clone(),
+                    "`@global` label `{name}` is not defined in this file"
+                ));
+            }
+        }
+
+        for ((scope, name), span) in self.externs.iter() {
+            if let Some((key, def)) = self.defined.get(&(*scope, name.to_owned())) {
+                // Declaring a label that's exported from the same file is harmless
+                if key != name {
+                    errors.push(
+                        Diagnostic::referencing_error(
+                            span.clone(),
+                            "`@extern` label is defined in the same file",
+                            Reference::new(def.clone(), "label defined here"),
+                        )
+                        .with_help(
+                            "labels can be used in the file that defines them without `@extern`",
+                        ),
+                    );
+                }
+            } else if resolve_externs && !self.globals.keys().any(|(_, global)| global == name) {
+                errors.push(match self.definition(name) {
+                    Some(def) => Diagnostic::referencing_error(
+                        span.clone(),
+                        format!("label `{name}` is not exported by the file that defines it"),
+                        Reference::new(def, "label defined here"),
+                    )
+                    .with_help(format!("export it from its file with `@global {name}`")),
+                    None => spanned_error!(span.clone(), "no `@global` definition of `{name}`"),
+                });
+            }
+        }
+
+        errors
+    }
+
+    /// A definition of `name` in any file.
+    fn definition(&self, name: &str) -> Option<Arc<Span>> {
+        self.defined
+            .iter()
+            .filter(|((_, defined), _)| defined == name)
+            .map(|(_, (_, span))| span.clone())
+            .min_by_key(|span| (span.source.to_string(), span.line, span.start()))
+    }
+
+    /// Replaces each label in `tokens` with the key of the label it refers to,
+    /// prefixing local labels with `parent`.
+    fn resolve(&mut self, tokens: &mut [Token], parent: &str) -> Result<(), Diagnostic> {
+        for tok in tokens.iter_mut() {
+            let TokenInner::Ident(Ident::Ident(ref mut name)) = tok.inner else {
+                continue;
+            };
+            if name.starts_with('.') {
+                *name = parent.to_owned() + name;
+            }
+
+            let scope = self.scope(&tok.span);
+            if let Some((key, _)) = self.defined.get(&(scope, name.to_owned())) {
+                *name = key.to_owned();
+            } else if !self.externs.contains_key(&(scope, name.to_owned())) {
+                if let Some(def) = self.definition(name) {
+                    return Err(Diagnostic::referencing_error(
+                        tok.span.clone(),
+                        format!("label `{name}` is not visible in this file"),
+                        Reference::new(def, "label defined here"),
+                    )
+                    .with_help(format!(
+                        "export it with `@global {name}`, and declare it in this file with `@extern {name}`"
+                    )));
+                }
+
+                return Err(
+                    spanned_error!(tok.span.clone(), "identifier `{name}` not defined").with_help(
+                        format!(
+                            "labels from other files need to be declared with `@extern {name}`"
+                        ),
+                    ),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
 enum Instruction {
     Add(Register, RegImm),
     Sub(Register, RegImm),
@@ -202,12 +369,104 @@ impl Instruction {
         }
     }
 
+    /// The operand expression of the instruction, if it has one, along with how it's encoded.
+    fn expression_mut(&mut self) -> Option<(&mut TokenStream, Kind)> {
+        match self {
+            Instruction::Add(_, RegImm::Expr(expr))
+            | Instruction::Sub(_, RegImm::Expr(expr))
+            | Instruction::Adc(_, RegImm::Expr(expr))
+            | Instruction::Sbb(_, RegImm::Expr(expr))
+            | Instruction::Nand(_, RegImm::Expr(expr))
+            | Instruction::Or(_, RegImm::Expr(expr))
+            | Instruction::Cmp(_, RegImm::Expr(expr))
+            | Instruction::Mv(_, RegImm::Expr(expr))
+            | Instruction::Push(RegImm::Expr(expr))
+            | Instruction::Jnz(RegImm::Expr(expr)) => Some((&mut expr.inner, Kind::Imm8)),
+            Instruction::LdAddr(_, expr)
+            | Instruction::StAddr(expr, _)
+            | Instruction::Lda(expr)
+            | Instruction::LpmAddr(_, expr) => Some((&mut expr.inner, Kind::Addr16)),
+            _ => None,
+        }
+    }
+
+    /// Compiles the instruction into an object file.
+    ///
+    /// Operands that reference labels, variables, or `$` can't be evaluated until the program is linked,
+    /// so they are left as zero and returned as a relocation expression instead.
+    fn relocate(mut self, parent: &str) -> Result<(Bytes, Option<(Kind, String)>), Diagnostic> {
+        let (mem, prog) = match &self {
+            Instruction::LdAddr(_, expr) | Instruction::StAddr(expr, _) => (Some(expr), None),
+            Instruction::LpmAddr(_, expr) => (None, Some(expr)),
+            _ => (None, None),
+        };
+        for tok in mem.iter().flat_map(|expr| expr.inner.iter()) {
+            match tok.inner {
+                TokenInner::Location => {
+                    return Err(spanned_error!(
+                        tok.span.clone(),
+                        "unexpected program location in memory address"
+                    ))
+                }
+                TokenInner::Ident(Ident::Ident(_)) => {
+                    return Err(spanned_error!(
+                        tok.span.clone(),
+                        "unexpected label in memory address"
+                    ))
+                }
+                _ => {}
+            }
+        }
+        for tok in prog.iter().flat_map(|expr| expr.inner.iter()) {
+            if let TokenInner::Ident(Ident::Variable(_)) = tok.inner {
+                return Err(spanned_error!(
+                    tok.span.clone(),
+                    "unexpected variable in program address"
+                ));
+            }
+        }
+
+        let relocation = match self.expression_mut() {
+            Some((tokens, kind))
+                if tokens.iter().any(|tok| {
+                    matches!(
+                        tok.inner,
+                        TokenInner::Location
+                            | TokenInner::Ident(Ident::Ident(_) | Ident::Variable(_))
+                    )
+                }) =>
+            {
+                for tok in tokens.iter_mut() {
+                    if let TokenInner::Ident(Ident::Ident(ref mut name)) = tok.inner {
+                        if name.starts_with('.') {
+                            *name = parent.to_owned() + name;
+                        }
+                    }
+                }
+
+                let expression = object::expression(tokens)?;
+                let span = tokens[0].span.clone();
+                *tokens = vec![Token {
+                    inner: TokenInner::Immediate(0),
+                    span,
+                }];
+                Some((kind, expression))
+            }
+            _ => None,
+        };
+
+        self.compile(0, parent, &mut HashMap::new(), &mut HashMap::new())
+            .map(|bytes| (bytes, relocation))
+    }
+
     fn expand_expr(expr: &mut [Token], pc: u16, parent: &str) {
         for tok in expr.iter_mut() {
             if let TokenInner::Location = tok.inner {
                 tok.inner = TokenInner::Immediate(pc as i128);
             } else if let TokenInner::Ident(Ident::Ident(ref mut name)) = tok.inner {
-                *name = parent.to_owned() + name;
+                if name.starts_with('.') {
+                    *name = parent.to_owned() + name;
+                }
             }
         }
     }
@@ -746,7 +1005,8 @@ impl Deref for Bytes {
 fn compile(
     mut stream: Vec<ExpSeg>,
     mut data: HashMap<String, Usable>,
-) -> Result<[u8; 1 << 16], Errors> {
+    mut scopes: Scopes,
+) -> Result<Generated, Errors> {
     // Pre-sort the segment stream to avoid segments placed physically
     // above segments in the source from mistakenly coliding
     stream.sort_by(|lhs, rhs| match (lhs.org.as_ref(), rhs.org.as_ref()) {
@@ -774,7 +1034,7 @@ fn compile(
 
         for expr in segment.instructions.iter() {
             match expr {
-                ExpTok::Instruction(inst) => pc += inst.size(),
+                ExpTok::Instruction(inst, _) => pc += inst.size(),
                 ExpTok::Label(label) => {
                     let name = if label.name.value.starts_with('.') {
                         parent.to_owned() + &label.name.value
@@ -784,19 +1044,16 @@ fn compile(
                     };
 
                     let span = label.name.span.clone();
+                    let key = scopes.define(name, span.clone());
                     if let Some(prev) = labels.insert(
-                        name,
+                        key,
                         Usable {
                             address: pc,
                             span: label.name.span.clone(),
                             uses: 0,
                         },
                     ) {
-                        errors.push(Diagnostic::referencing_error(
-                            span,
-                            "duplicate label definitions",
-                            Reference::new(prev.span, "previous definition found here"),
-                        ));
+                        errors.push(duplicate_label(span, prev.span));
                     }
                 }
                 ExpTok::Bytes(bytes) => pc += bytes.len() as u16,
@@ -835,9 +1092,14 @@ fn compile(
         ranges.push(segment_range);
     }
 
+    errors.append(&mut scopes.check(true));
+
     parent.clear();
     pc = 0;
     let mut program = [0; 1 << 16];
+    let mut symbols = Symbols::new();
+    let mut listing = Listing::new();
+    let mut ranges: Vec<Range<u16>> = Vec::new();
 
     for segment in stream {
         pc = match segment.origin(pc) {
@@ -847,10 +1109,34 @@ fn compile(
                 pc
             }
         };
+        let start = pc;
+
+        // The current `call` or `ret` expansion, along with its starting address
+        let mut expansion: Option<((usize, &str), u16)> = None;
 
         for expr in segment.instructions {
             match expr {
-                ExpTok::Instruction(inst) => {
+                ExpTok::Instruction(mut inst, origin) => {
+                    let span = origin.span;
+                    let source = span.source.to_string();
+                    if !source.is_empty() {
+                        symbols.insert_line(pc, source, span.line_number());
+                    }
+
+                    if expansion.map(|(boundary, _)| boundary) != origin.boundary {
+                        if let Some(((_, name), start)) = expansion {
+                            symbols.insert_macro(name, start..pc);
+                        }
+                        expansion = origin.boundary.map(|boundary| (boundary, pc));
+                    }
+
+                    if let Some((tokens, _)) = inst.expression_mut() {
+                        if let Err(err) = scopes.resolve(tokens, &parent) {
+                            errors.push(err);
+                            continue;
+                        }
+                    }
+
                     let inst = match inst.compile(pc, &parent, &mut data, &mut labels) {
                         Ok(inst) => inst,
                         Err(err) => {
@@ -858,6 +1144,7 @@ fn compile(
                             continue;
                         }
                     };
+                    listing.insert_instruction(pc, &inst, span, origin.chain);
 
                     for byte in inst.into_iter() {
                         program[pc as usize] = *byte;
@@ -865,11 +1152,13 @@ fn compile(
                     }
                 }
                 ExpTok::Label(label) => {
+                    listing.insert_label(pc, &label.name.value);
                     if !label.name.value.contains('.') {
                         parent = label.name.value;
                     }
                 }
                 ExpTok::Bytes(bytes) => {
+                    listing.insert_bytes(pc, &bytes);
                     for byte in bytes {
                         program[pc as usize] = byte;
                         pc += 1;
@@ -877,6 +1166,29 @@ fn compile(
                 }
             }
         }
+
+        if let Some(((_, name), start)) = expansion {
+            symbols.insert_macro(name, start..pc);
+        }
+
+        if start < pc {
+            ranges.push(start..pc);
+        }
+    }
+
+    let ranges = format::merge(ranges);
+
+    // Sorted so that the symbol file stays stable between assemblies
+    let mut sorted: Vec<(&String, &Usable)> = labels.iter().collect();
+    sorted.sort_by_key(|(name, label)| (label.address, *name));
+    for (key, label) in sorted {
+        symbols.insert_label(object::scoped(key).0, label.address);
+    }
+
+    let mut sorted: Vec<(&String, &Usable)> = data.iter().collect();
+    sorted.sort_by_key(|(name, var)| (var.address, *name));
+    for (name, var) in sorted {
+        symbols.insert_variable(name, var.address);
     }
 
     for (_, label) in labels {
@@ -892,7 +1204,12 @@ fn compile(
     }
 
     if errors.is_empty() {
-        Ok(program)
+        Ok(Generated {
+            program,
+            ranges,
+            symbols,
+            listing,
+        })
     } else {
         Err(errors)
     }
@@ -988,7 +1305,7 @@ pub fn assemble_data(mut stream: Vec<DSeg>) -> Result<HashMap<String, Usable>, E
     }
 }
 
-fn expand_macro(inst: Inst, def: &Macro) -> Result<Vec<ParseTok>, Diagnostic> {
+fn expand_macro(inst: Inst, def: &Macro, expansion: usize) -> Result<Vec<ParseTok>, Diagnostic> {
     let span = inst
         .args
         .fl()
@@ -1002,14 +1319,18 @@ fn expand_macro(inst: Inst, def: &Macro) -> Result<Vec<ParseTok>, Diagnostic> {
         })
         .unwrap_or(inst.name.span.clone());
 
-    def.expand(span, &inst.args.into_values())
+    def.expand(span, &inst.args.into_values(), expansion)
 }
 
 fn expand_macros(code: Vec<CSeg>, macros: HashMap<String, Macro>) -> Result<Vec<ExpSeg>, Errors> {
     let mut errors = Errors::new();
     let mut segments = Vec::new();
+    let mut boundaries = 0;
+    let mut expansions = 0;
 
     for mut segment in code {
+        // The origin of each token that was expanded from a macro.
+        let mut origins: Vec<Option<Origin>> = vec![None; segment.tokens.len()];
         let mut position = 0;
         let mut exp = ExpSeg {
             cseg: segment.cseg,
@@ -1020,10 +1341,44 @@ fn expand_macros(code: Vec<CSeg>, macros: HashMap<String, Macro>) -> Result<Vec<
         while let Some(expr) = segment.tokens.get(position) {
             match expr {
                 ParseTok::Instruction(inst) => match Instruction::try_from(inst.clone()) {
-                    Ok(instruction) => exp.instructions.push(ExpTok::Instruction(instruction)),
+                    Ok(instruction) => exp.instructions.push(ExpTok::Instruction(
+                        instruction,
+                        origins[position].clone().unwrap_or_else(|| Origin {
+                            span: inst.name.span.clone(),
+                            chain: Vec::new(),
+                            boundary: None,
+                        }),
+                    )),
                     Err(err) => match macros.get(&inst.name.value) {
-                        Some(def) => match expand_macro(inst.clone(), def) {
+                        Some(def) => match expand_macro(inst.clone(), def, expansions) {
                             Ok(expanded) => {
+                                expansions += 1;
+                                let parent = origins[position].clone();
+                                let boundary = BOUNDARIES
+                                    .iter()
+                                    .find(|name| **name == inst.name.value)
+                                    .map(|name| {
+                                        boundaries += 1;
+                                        (boundaries, *name)
+                                    })
+                                    .or_else(|| parent.as_ref().and_then(|origin| origin.boundary));
+                                let (span, mut chain) = match parent {
+                                    Some(origin) => (origin.span, origin.chain),
+                                    None => (inst.name.span.clone(), Vec::new()),
+                                };
+                                chain.push(Invocation {
+                                    name: inst.name.value.clone(),
+                                    span: inst.name.span.clone(),
+                                });
+                                let origin = Origin {
+                                    span,
+                                    chain,
+                                    boundary,
+                                };
+                                origins.splice(
+                                    position..=position,
+                                    vec![Some(origin); expanded.len()],
+                                );
                                 segment.tokens.splice(position..=position, expanded);
                                 continue;
                             }
@@ -1048,8 +1403,25 @@ fn expand_macros(code: Vec<CSeg>, macros: HashMap<String, Macro>) -> Result<Vec<
     }
 }
 
+/// Macros whose expansions are recorded in the symbol file,
+/// marking function boundaries for the profiler.
+const BOUNDARIES: [&str; 2] = ["call", "ret"];
+
+/// Where an expanded instruction originated from.
+#[derive(Clone)]
+struct Origin {
+    /// The span of the outermost macro invocation, or of the instruction itself.
+    span: Arc<Span>,
+    /// The macro invocations the instruction was expanded from, outermost first.
+    chain: Vec<Invocation>,
+    /// The innermost `call` or `ret` expansion containing the instruction,
+    /// identified by a unique index.
+    boundary: Option<(usize, &'static str)>,
+}
+
 enum ExpTok {
-    Instruction(Instruction),
+    /// An instruction, along with where it originated from.
+    Instruction(Instruction, Origin),
     Label(Label),
     Bytes(Vec<u8>),
 }
@@ -1073,8 +1445,295 @@ impl ExpSeg {
     }
 }
 
-pub fn generate(ctx: ParseStream) -> Result<[u8; 1 << 16], Errors> {
+/// Reports two definitions of the same label.
+fn duplicate_label(span: Arc<Span>, prev: Arc<Span>) -> Diagnostic {
+    let diagnostic = Diagnostic::referencing_error(
+        span.clone(),
+        "duplicate label definitions",
+        Reference::new(prev.clone(), "previous definition found here"),
+    );
+
+    if span.source.to_string() == prev.source.to_string() {
+        diagnostic
+    } else {
+        diagnostic.with_help("labels exported with `@global` must be unique across files")
+    }
+}
+
+pub fn generate(ctx: ParseStream) -> Result<Generated, Errors> {
     let data = assemble_data(ctx.data)?;
     let expanded = expand_macros(ctx.code, ctx.macros)?;
-    compile(expanded, data)
+    compile(expanded, data, Scopes::new(ctx.globals, ctx.externs))
+}
+
+/// Assembles a relocatable object file,
+/// leaving the placement of each segment and the resolution of symbols to the linker.
+pub fn generate_object(ctx: ParseStream) -> Result<Object, Errors> {
+    let expanded = expand_macros(ctx.code, ctx.macros)?;
+    let mut errors = Errors::new();
+    let mut object = Object::default();
+    let mut scopes = Scopes::new(ctx.globals, ctx.externs);
+    let mut labels: HashMap<String, Arc<Span>> = HashMap::new();
+    let mut variables: HashMap<String, Arc<Span>> = HashMap::new();
+
+    // Every label needs to be defined before any references to it can be resolved
+    let mut keys = Vec::new();
+    for segment in expanded.iter() {
+        let mut parent = String::new();
+        for expr in segment.instructions.iter() {
+            if let ExpTok::Label(label) = expr {
+                let name = if label.name.value.starts_with('.') {
+                    parent.to_owned() + &label.name.value
+                } else {
+                    parent = label.name.value.to_owned();
+                    label.name.value.to_owned()
+                };
+                keys.push(scopes.define(name, label.name.span.clone()));
+            }
+        }
+    }
+    errors.append(&mut scopes.check(false));
+    let mut keys = keys.into_iter();
+
+    for segment in expanded {
+        // Skips the initial segment when everything is in an explicit `@cseg`
+        if segment.org.is_none() && segment.instructions.is_empty() {
+            continue;
+        }
+
+        let org = match segment.org {
+            Some(_) => match segment.origin(0) {
+                Ok(org) => Some(org),
+                Err(err) => {
+                    errors.push(err);
+                    None
+                }
+            },
+            None => None,
+        };
+        let section = object.sections.len();
+        let mut parent = String::new();
+        let mut bytes: Vec<u8> = Vec::new();
+
+        for expr in segment.instructions {
+            match expr {
+                ExpTok::Instruction(mut inst, _) => {
+                    let offset = bytes.len() as u16;
+                    if let Some((tokens, _)) = inst.expression_mut() {
+                        if let Err(err) = scopes.resolve(tokens, &parent) {
+                            errors.push(err);
+                            continue;
+                        }
+                    }
+
+                    match inst.relocate(&parent) {
+                        Ok((inst, relocation)) => {
+                            bytes.extend_from_slice(&inst);
+                            if let Some((kind, expression)) = relocation {
+                                object.relocations.push(Relocation {
+                                    section,
+                                    offset,
+                                    kind,
+                                    expression,
+                                });
+                            }
+                        }
+                        Err(err) => errors.push(err),
+                    }
+                }
+                ExpTok::Label(label) => {
+                    if !label.name.value.starts_with('.') {
+                        parent = label.name.value.to_owned();
+                    }
+
+                    // Labels are yielded in the same order as the first pass
+                    let key = keys.next().unwrap_or_default();
+                    if let Some(prev) = labels.insert(key.clone(), label.name.span.clone()) {
+                        errors.push(duplicate_label(label.name.span, prev));
+                    }
+
+                    let offset = bytes.len() as u16;
+                    match object::scoped(&key) {
+                        (name, Some(scope)) => object.locals.push(Local {
+                            scope,
+                            symbol: Symbol {
+                                section,
+                                offset,
+                                name: name.to_owned(),
+                            },
+                        }),
+                        (name, None) => object.labels.push(Symbol {
+                            section,
+                            offset,
+                            name: name.to_owned(),
+                        }),
+                    }
+                }
+                ExpTok::Bytes(data) => bytes.extend(data),
+            }
+        }
+
+        if bytes.len() > u16::MAX as usize {
+            errors.push(Diagnostic::error("code segment out of range"));
+        }
+        object.sections.push(Section::Code { org, bytes });
+    }
+
+    for segment in ctx.data {
+        let size = match segment.size() {
+            Ok(size) => size,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+        let org = match segment.org {
+            Some(ref imm) => match imm.value.try_into() {
+                Ok(org) => Some(org),
+                Err(_) => {
+                    errors.push(spanned_error!(
+                        imm.span.clone(),
+                        "segment origin out of range"
+                    ));
+                    None
+                }
+            },
+            None => None,
+        };
+        let section = object.sections.len();
+
+        // Sorted by definition, so that the object file stays stable between assemblies
+        let mut sorted: Vec<(&String, &(u16, Arc<Span>))> = segment.variables.iter().collect();
+        sorted.sort_by_key(|(_, (_, span))| (span.line, span.start()));
+
+        let mut offset = 0;
+        for (name, (size, span)) in sorted {
+            if let Some(prev) = variables.insert(name.to_owned(), span.clone()) {
+                errors.push(Diagnostic::referencing_error(
+                    span.clone(),
+                    "duplicate variable definition",
+                    Reference::new(prev, "variable previously defined here"),
+                ));
+            }
+            object.variables.push(Symbol {
+                section,
+                offset,
+                name: name.to_owned(),
+            });
+            offset += size;
+        }
+
+        object.sections.push(Section::Data { org, size });
+    }
+
+    if errors.is_empty() {
+        Ok(object)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{lex, parse},
+        emulator::test_emulate,
+        Verbosity, VERBOSITY,
+    };
+
+    /// Assembles each `(file, source)` pair as if they had been included into one program.
+    fn assemble(files: &[(&'static str, &str)]) -> Result<Generated, Errors> {
+        let mut tokens = Vec::new();
+        for (name, source) in files {
+            tokens.append(&mut lex::lex_string(Some(name), *source).unwrap());
+        }
+        generate(parse::parse(tokens).unwrap())
+    }
+
+    const MAIN: &str = "\
+        @org 0x0000\n\
+        @extern twice\n\
+        main:\n\
+        mv A, 3\n\
+        call [twice]\n\
+        jmp [done]\n\
+        mv A, 0\n\
+        done:\n\
+        halt\n\
+    ";
+    const LIB: &str = "\
+        @global twice\n\
+        twice:\n\
+        add A, A\n\
+        jmp [done]\n\
+        mv A, 0\n\
+        done:\n\
+        ret\n\
+    ";
+
+    #[test]
+    fn scopes() {
+        VERBOSITY.get_or_init(|| Verbosity::Error);
+
+        // Both files define `done`, and each `jmp` goes to its own
+        let generated = assemble(&[("main.asm", MAIN), ("lib.asm", LIB)]).unwrap();
+        let (bank, _) = test_emulate(Box::new(generated.program), &[], 10_000).unwrap();
+        assert_eq!(bank.a, 6);
+
+        // `twice` isn't visible without `@extern`...
+        let main = MAIN.replace("@extern twice\n", "");
+        assert!(assemble(&[("main.asm", &main), ("lib.asm", LIB)]).is_err());
+
+        // ...or without `@global`
+        let lib = LIB.replace("@global twice\n", "");
+        assert!(assemble(&[("main.asm", MAIN), ("lib.asm", &lib)]).is_err());
+
+        // Global labels must be unique across files
+        let other = "@global twice\ntwice:\nret\n";
+        assert!(assemble(&[("main.asm", MAIN), ("lib.asm", LIB), ("other.asm", other)]).is_err());
+    }
+
+    #[test]
+    fn macro_labels() {
+        VERBOSITY.get_or_init(|| Verbosity::Error);
+        let source = "\
+            @macro shln (%r:reg, %n:imm) {\n\
+                mv F, %n\n\
+            %%loop:\n\
+                add %r, %r\n\
+                dec F\n\
+                jnz F, [%%loop]\n\
+            }\n\
+            @cseg\n\
+            @org 0x0000\n\
+            main:\n\
+                mv A, 1\n\
+                shln A, 3\n\
+                mv B, 1\n\
+                shln B, 2\n\
+                halt\n\
+        ";
+
+        // Each expansion gets its own `%%loop`,
+        // numbered by an expansion counter that built-in macros advance too
+        let generated = assemble(&[("main.asm", source)]).unwrap();
+        let loops: Vec<&str> = (0..=u16::MAX)
+            .filter_map(|addr| generated.symbols.label_at(addr))
+            .filter(|label| label.starts_with("main.loop%"))
+            .collect();
+        assert_eq!(loops.len(), 2);
+        assert_ne!(loops[0], loops[1]);
+        assert_eq!(generated.symbols.label(loops[0]), Some(0x0004));
+
+        // Renamed labels can't collide with labels written in the source
+        assert!(lex::lex_string(None, loops[1]).is_err());
+
+        let (bank, _) = test_emulate(Box::new(generated.program), &[], 10_000).unwrap();
+        assert_eq!((bank.a, bank.b), (8, 4));
+
+        let outside = "main:\n%%loop:\nhalt\n";
+        let tokens = lex::lex_string(Some("main.asm"), outside).unwrap();
+        assert!(parse::parse(tokens).is_err());
+    }
 }
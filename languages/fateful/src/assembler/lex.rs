@@ -213,6 +213,7 @@ pub enum TokenInner {
     #[regex(r"[._a-zA-Z][._a-zA-Z0-9]*", Ident::any)]
     #[regex(r"@[_a-zA-Z][_a-zA-Z0-9]*", Ident::pre_proc)]
     #[regex(r"%[_a-zA-Z][_a-zA-Z0-9]*", Ident::macro_variable)]
+    #[regex(r"%%[_a-zA-Z][_a-zA-Z0-9]*", Ident::macro_label)]
     #[regex(r"\$[_a-zA-Z][_a-zA-Z0-9]*", Ident::variable)]
     Ident(Ident),
 
@@ -372,6 +373,7 @@ pub enum Ident {
     PreProc(PreProc),
     Variable(String),
     MacroVariable(String),
+    MacroLabel(String),
     Ty(Ty),
     Ident(String),
 }
@@ -383,6 +385,7 @@ impl Ident {
             Ident::PreProc(pp) => pp.description(),
             Ident::Variable(_) => "variable",
             Ident::MacroVariable(_) => "macro variable",
+            Ident::MacroLabel(_) => "macro label",
             Ident::Ty(_) => "type",
             Ident::Ident(_) => "identifier",
         }
@@ -404,6 +407,14 @@ impl Ident {
         Ok(Ident::MacroVariable(slice.to_owned()))
     }
 
+    fn macro_label(lex: &mut Lexer<TokenInner>) -> Result<Ident, Diagnostic> {
+        let slice = lex
+            .slice()
+            .strip_prefix("%%")
+            .ok_or_else(|| error!("macro label not prefixed by `%%`"))?;
+        Ok(Ident::MacroLabel(slice.to_owned()))
+    }
+
     fn pre_proc(lex: &mut Lexer<TokenInner>) -> Result<Ident, Diagnostic> {
         Ok(Ident::PreProc(PreProc::from_str(lex.slice())?))
     }
@@ -435,10 +446,10 @@ pub enum Register {
     E = 4,
     /// Status register
     F = 5,
-    /// Memory index low.
-    L = 6,
     /// Memory index high.
-    H = 7,
+    H = 6,
+    /// Memory index low.
+    L = 7,
 }
 
 impl FromStr for Register {
@@ -452,8 +463,8 @@ impl FromStr for Register {
             "r3" | "D" => Ok(Register::D),
             "r4" | "E" => Ok(Register::E),
             "r5" | "F" => Ok(Register::F),
-            "r6" | "H" => Ok(Register::L),
-            "r7" | "L" => Ok(Register::H),
+            "r6" | "H" => Ok(Register::H),
+            "r7" | "L" => Ok(Register::L),
             _ => Err(error!("unknown register")),
         }
     }
@@ -479,6 +490,8 @@ pub enum PreProc {
     Quad,
     Str,
     Var,
+    Global,
+    Extern,
     Error,
 }
 
@@ -504,6 +517,8 @@ impl PreProc {
             PP::Quad => "`@quad`",
             PP::Str => "`@str`",
             PP::Var => "`@var`",
+            PP::Global => "`@global`",
+            PP::Extern => "`@extern`",
             PP::Error => "`@error`",
         }
     }
@@ -538,6 +553,8 @@ impl FromStr for PreProc {
             "quad" => Ok(PP::Quad),
             "str" => Ok(PP::Str),
             "var" => Ok(PP::Var),
+            "global" => Ok(PP::Global),
+            "extern" => Ok(PP::Extern),
             "error" => Ok(PP::Error),
             _ => Err(error!("Unrecognized preprocessor argument")),
         }
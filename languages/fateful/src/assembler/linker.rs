@@ -0,0 +1,660 @@
+//! Links relocatable object files, produced by `fateful asm -c`, into a program ROM.
+//!
+//! Sections with an `@org` are placed at their origin.
+//! The rest are placed in the order they were given, each in the first gap after the previous one.
+//! Code sections are placed in the program ROM, and data sections in RAM,
+//! each within a region that can be restricted with a linker script:
+//!
+//! ```text
+//! ; program ROM
+//! code 0x0000..0x8000
+//! ; RAM, below the peripherals
+//! data 0xC000..0xFFC0
+//! ```
+//!
+//! Labels exported with `@global`, and every variable, are visible to every object,
+//! so relocations are resolved across all of the linked objects.
+//! Labels scoped to a file are only visible to relocations from the same object.
+
+use std::{
+    collections::HashMap, fs, io, ops::Range, path::PathBuf, str::FromStr, sync::Arc, time::Instant,
+};
+
+use clap::Args;
+use clio::Output;
+use colored::Colorize;
+use thiserror::Error;
+
+use super::{
+    eval,
+    format::{self, Format},
+    generator::Usable,
+    lex::{self, Delimeter, Ident, Source, Span, Token, TokenInner},
+    object::{self, Kind, Object, ObjectError, Section},
+    parse::EXPANSION_SEPARATOR,
+};
+use crate::symbols::Symbols;
+
+#[derive(Debug, Args)]
+pub struct LinkerArgs {
+    /// Object files to link, in order
+    #[clap(required = true)]
+    inputs: Vec<PathBuf>,
+    #[clap(short, long, value_parser, default_value = "-")]
+    output: Output,
+    /// Linker script restricting where code and data sections are placed
+    #[clap(short = 'T', long)]
+    script: Option<PathBuf>,
+    /// Format of the linked program.
+    ///
+    /// `ihex`, `srec`, and `trimmed` only include the addresses occupied by code sections.
+    #[clap(long, value_enum, default_value_t = Format::Bin)]
+    format: Format,
+    /// Write a symbol file mapping labels and variables to addresses.
+    ///
+    /// Can be loaded by the emulator with `--symbols`.
+    #[clap(long, value_parser)]
+    symbols: Option<Output>,
+}
+
+#[derive(Debug, Error)]
+pub enum LinkerError {
+    #[error("unable to read {0}: {1}")]
+    Input(String, io::Error),
+    #[error("{0}: {1}")]
+    Object(String, ObjectError),
+    #[error("malformed linker script (line {line}): {message}")]
+    Script { line: usize, message: &'static str },
+    #[error("unable to write to output: {0}")]
+    Output(io::Error),
+    #[error("{0} overlaps with {1}")]
+    Overlap(String, String),
+    #[error("{0} doesn't fit in the available address space")]
+    NoSpace(String),
+    #[error("duplicate definitions of `{name}` in {first} and {second}")]
+    Duplicate {
+        name: String,
+        first: String,
+        second: String,
+    },
+    #[error("undefined symbol `{name}` referenced in {object}")]
+    Undefined { name: String, object: String },
+    #[error("unable to relocate `{expression}` in {object}: {message}")]
+    Relocation {
+        expression: String,
+        object: String,
+        message: String,
+    },
+}
+
+/// The regions that relocatable sections can be placed in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    code: Range<u32>,
+    data: Range<u32>,
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Script {
+            code: 0x0000..0x10000,
+            data: 0x0000..0x10000,
+        }
+    }
+}
+
+impl FromStr for Script {
+    type Err = LinkerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut script = Script::default();
+
+        for (n, line) in s.lines().enumerate() {
+            let error = |message| LinkerError::Script {
+                line: n + 1,
+                message,
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+
+            let (kind, range) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| error("expected a `code` or `data` region"))?;
+            let (start, end) = range
+                .trim()
+                .split_once("..")
+                .and_then(|(start, end)| {
+                    let parse =
+                        |addr: &str| u32::from_str_radix(addr.trim().strip_prefix("0x")?, 16).ok();
+                    Some((parse(start)?, parse(end)?))
+                })
+                .filter(|(start, end)| start <= end && *end <= 0x10000)
+                .ok_or_else(|| error("expected a hexadecimal range, such as `0x0000..0x8000`"))?;
+
+            match kind {
+                "code" => script.code = start..end,
+                "data" => script.data = start..end,
+                _ => return Err(error("expected a `code` or `data` region")),
+            }
+        }
+
+        Ok(script)
+    }
+}
+
+/// A linked program, along with the symbols used to create it.
+pub struct Linked {
+    pub program: Box<[u8; 1 << 16]>,
+    /// The addresses occupied by code sections, sorted and with adjacent ranges merged.
+    pub ranges: Vec<Range<u16>>,
+    pub symbols: Symbols,
+}
+
+fn describe(name: &str, index: usize, section: &Section) -> String {
+    match section {
+        Section::Code { .. } => format!("code section {index} of {name}"),
+        Section::Data { .. } => format!("data section {index} of {name}"),
+    }
+}
+
+/// Finds the address of every section, indexed by object and then by section.
+fn place(objects: &[(String, Object)], script: &Script) -> Result<Vec<Vec<u16>>, LinkerError> {
+    let mut bases: Vec<Vec<u16>> = objects
+        .iter()
+        .map(|(_, object)| vec![0; object.sections.len()])
+        .collect();
+
+    for (code, region) in [(true, &script.code), (false, &script.data)] {
+        // Occupied ranges of the program ROM are 16-bit, so code can't reach its last address
+        let limit: u32 = if code { 0xFFFF } else { 0x10000 };
+        let sections = || {
+            objects
+                .iter()
+                .enumerate()
+                .flat_map(move |(o, (name, object))| {
+                    object
+                        .sections
+                        .iter()
+                        .enumerate()
+                        .filter(move |(_, section)| matches!(section, Section::Code { .. }) == code)
+                        .map(move |(s, section)| (o, s, name, section))
+                })
+        };
+        let mut placed: Vec<(Range<u32>, String)> = Vec::new();
+
+        for (o, s, name, section) in sections() {
+            if let Some(org) = section.org() {
+                let range = org as u32..org as u32 + section.size() as u32;
+                if range.end > limit {
+                    return Err(LinkerError::NoSpace(describe(name, s, section)));
+                }
+                if let Some((_, other)) = placed
+                    .iter()
+                    .find(|(other, _)| range.start < other.end && other.start < range.end)
+                {
+                    return Err(LinkerError::Overlap(
+                        describe(name, s, section),
+                        other.to_owned(),
+                    ));
+                }
+
+                placed.push((range, describe(name, s, section)));
+                bases[o][s] = org;
+            }
+        }
+
+        let mut next = region.start;
+        for (o, s, name, section) in sections() {
+            if section.org().is_some() {
+                continue;
+            }
+
+            let size = section.size() as u32;
+            let mut start = next;
+            // Skip past any sections with a fixed origin
+            while let Some((other, _)) = placed
+                .iter()
+                .find(|(other, _)| start < other.end && other.start < start + size)
+            {
+                start = other.end;
+            }
+            if start + size > region.end.min(limit) {
+                return Err(LinkerError::NoSpace(describe(name, s, section)));
+            }
+
+            placed.push((start..start + size, describe(name, s, section)));
+            bases[o][s] = start as u16;
+            next = start + size;
+        }
+    }
+
+    Ok(bases)
+}
+
+/// Places every section, then resolves each relocation.
+pub fn link_objects(objects: &[(String, Object)], script: &Script) -> Result<Linked, LinkerError> {
+    let bases = place(objects, script)?;
+
+    // The linker doesn't have any source to point to, so every symbol shares a blank span
+    let span = Arc::new(Span {
+        line: 0,
+        range: 0..0,
+        source: Source::String {
+            name: None,
+            source: Arc::new(String::new()),
+        },
+    });
+    let mut labels: HashMap<String, Usable> = HashMap::new();
+    let mut variables: HashMap<String, Usable> = HashMap::new();
+    let mut defined: HashMap<String, &str> = HashMap::new();
+    let mut symbols = Symbols::new();
+
+    for ((name, object), bases) in objects.iter().zip(bases.iter()) {
+        for (list, prefix) in [(&object.labels, ""), (&object.variables, "$")] {
+            for symbol in list {
+                let key = format!("{prefix}{}", symbol.name);
+                if let Some(first) = defined.insert(key.clone(), name) {
+                    return Err(LinkerError::Duplicate {
+                        name: key,
+                        first: first.to_owned(),
+                        second: name.to_owned(),
+                    });
+                }
+
+                let map = match prefix {
+                    "$" => &mut variables,
+                    _ => &mut labels,
+                };
+                map.insert(
+                    symbol.name.to_owned(),
+                    Usable {
+                        address: bases[symbol.section].wrapping_add(symbol.offset),
+                        span: span.clone(),
+                        uses: 0,
+                    },
+                );
+            }
+        }
+    }
+
+    // Local labels are keyed by their object and scope, so they can't collide
+    for (i, ((_, object), bases)) in objects.iter().zip(bases.iter()).enumerate() {
+        for local in object.locals.iter() {
+            labels.insert(
+                format!("{}@{i}.{}", local.symbol.name, local.scope),
+                Usable {
+                    address: bases[local.symbol.section].wrapping_add(local.symbol.offset),
+                    span: span.clone(),
+                    uses: 0,
+                },
+            );
+        }
+    }
+
+    let mut program = Box::new([0; 1 << 16]);
+    let mut ranges = Vec::new();
+
+    for ((_, object), bases) in objects.iter().zip(bases.iter()) {
+        for (section, base) in object.sections.iter().zip(bases.iter()) {
+            if let Section::Code { bytes, .. } = section {
+                let start = *base as usize;
+                program[start..start + bytes.len()].copy_from_slice(bytes);
+                if !bytes.is_empty() {
+                    ranges.push(*base..base + bytes.len() as u16);
+                }
+            }
+        }
+    }
+
+    for (i, ((name, object), bases)) in objects.iter().zip(bases.iter()).enumerate() {
+        for reloc in object.relocations.iter() {
+            let error = |message: String| LinkerError::Relocation {
+                expression: reloc.expression.clone(),
+                object: name.to_owned(),
+                message,
+            };
+
+            let pc = bases[reloc.section].wrapping_add(reloc.offset);
+            let mut tokens: Vec<Token> =
+                lex::lex_string(None, reloc.expression.as_str()).map_err(|errors| {
+                    error(
+                        errors
+                            .first()
+                            .map(|err| err.message().to_owned())
+                            .unwrap_or_default(),
+                    )
+                })?;
+            tokens.retain(|tok| tok.inner != TokenInner::NewLine);
+
+            // References to local labels are written as `name { scope }`,
+            // or `name { scope expansion }` for renamed macro labels
+            let mut j = 0;
+            while j < tokens.len() {
+                let reference = match &tokens[j..] {
+                    [Token {
+                        inner: TokenInner::Ident(Ident::Ident(label)),
+                        ..
+                    }, Token {
+                        inner: TokenInner::Delimeter(Delimeter::OpenBrace),
+                        ..
+                    }, Token {
+                        inner: TokenInner::Immediate(scope),
+                        ..
+                    }, Token {
+                        inner: TokenInner::Delimeter(Delimeter::ClosedBrace),
+                        ..
+                    }, ..] => Some((label.to_owned(), *scope, 4)),
+                    [Token {
+                        inner: TokenInner::Ident(Ident::Ident(label)),
+                        ..
+                    }, Token {
+                        inner: TokenInner::Delimeter(Delimeter::OpenBrace),
+                        ..
+                    }, Token {
+                        inner: TokenInner::Immediate(scope),
+                        ..
+                    }, Token {
+                        inner: TokenInner::Immediate(expansion),
+                        ..
+                    }, Token {
+                        inner: TokenInner::Delimeter(Delimeter::ClosedBrace),
+                        ..
+                    }, ..] => Some((
+                        format!("{label}{EXPANSION_SEPARATOR}{expansion}"),
+                        *scope,
+                        5,
+                    )),
+                    _ => None,
+                };
+
+                if let Some((label, scope, len)) = reference {
+                    let key = format!("{label}@{i}.{scope}");
+                    if !labels.contains_key(&key) {
+                        return Err(LinkerError::Undefined {
+                            name: label,
+                            object: name.to_owned(),
+                        });
+                    }
+                    tokens[j].inner = TokenInner::Ident(Ident::Ident(key));
+                    tokens.drain(j + 1..j + len);
+                }
+                j += 1;
+            }
+
+            for tok in tokens.iter_mut() {
+                match tok.inner {
+                    TokenInner::Location => tok.inner = TokenInner::Immediate(pc as i128),
+                    TokenInner::Ident(Ident::Ident(ref label)) if !labels.contains_key(label) => {
+                        return Err(LinkerError::Undefined {
+                            name: label.to_owned(),
+                            object: name.to_owned(),
+                        })
+                    }
+                    TokenInner::Ident(Ident::Variable(ref var)) if !variables.contains_key(var) => {
+                        return Err(LinkerError::Undefined {
+                            name: format!("${var}"),
+                            object: name.to_owned(),
+                        })
+                    }
+                    _ => {}
+                }
+            }
+
+            let value = eval::eval_tokens(&tokens, &mut labels, &mut variables)
+                .map_err(|err| error(err.message().to_owned()))?;
+            // The operand always follows the opcode
+            let site = bases[reloc.section] as usize + reloc.offset as usize + 1;
+            let width = match reloc.kind {
+                Kind::Addr16 => 2,
+                Kind::Imm8 => 1,
+            };
+            if site + width > program.len() {
+                return Err(error("operand past the end of the program ROM".to_owned()));
+            }
+            match reloc.kind {
+                Kind::Addr16 => {
+                    let addr: u16 = value
+                        .try_into()
+                        .map_err(|_| error(format!("address {value} not in range")))?;
+                    program[site..site + 2].copy_from_slice(&addr.to_be_bytes());
+                }
+                Kind::Imm8 => {
+                    program[site] = value
+                        .try_into()
+                        .map_err(|_| error(format!("immediate {value} out of range")))?;
+                }
+            }
+        }
+    }
+
+    // Sorted so that the symbol file stays stable between links
+    for (map, label) in [(&labels, true), (&variables, false)] {
+        let mut sorted: Vec<(&String, &Usable)> = map.iter().collect();
+        sorted.sort_by_key(|(name, symbol)| (symbol.address, *name));
+        for (name, symbol) in sorted {
+            if label {
+                symbols.insert_label(object::scoped(name).0, symbol.address);
+            } else {
+                symbols.insert_variable(name, symbol.address);
+            }
+        }
+    }
+
+    Ok(Linked {
+        program,
+        ranges: format::merge(ranges),
+        symbols,
+    })
+}
+
+pub fn link(mut args: LinkerArgs) -> Result<(), LinkerError> {
+    let start = Instant::now();
+
+    let script = match args.script {
+        Some(ref path) => fs::read_to_string(path)
+            .map_err(|err| LinkerError::Input(path.display().to_string(), err))?
+            .parse()?,
+        None => Script::default(),
+    };
+
+    let mut objects = Vec::new();
+    for path in args.inputs.iter() {
+        let name = path.display().to_string();
+        let object = fs::read_to_string(path)
+            .map_err(|err| LinkerError::Input(name.clone(), err))?
+            .parse()
+            .map_err(|err| LinkerError::Object(name.clone(), err))?;
+        objects.push((name, object));
+    }
+
+    let linked = link_objects(&objects, &script)?;
+
+    format::write(
+        args.format,
+        &linked.program,
+        &linked.ranges,
+        args.output.lock(),
+    )
+    .map_err(|err| LinkerError::Output(err))?;
+    args.output
+        .finish()
+        .map_err(|err| LinkerError::Output(err))?;
+
+    if let Some(mut symbols) = args.symbols {
+        write!(symbols.lock(), "{}", linked.symbols).map_err(|err| LinkerError::Output(err))?;
+        symbols.finish().map_err(|err| LinkerError::Output(err))?;
+    }
+
+    let elapsed = start.elapsed().as_millis();
+    println!(
+        "    {} linking {} object(s) in {}.{:03}s",
+        "Finished".green().bold(),
+        objects.len(),
+        elapsed / 1000,
+        elapsed % 1000
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{generator, parse},
+        emulator::test_emulate,
+        Verbosity, VERBOSITY,
+    };
+
+    const MAIN: &str = "\
+        @dseg\n\
+        @byte result\n\
+        @cseg\n\
+        @org 0x0000\n\
+        @extern double\n\
+        main:\n\
+        mv A, 5\n\
+        call [double]\n\
+        st [$result], A\n\
+        halt\n\
+    ";
+    const LIB: &str = "\
+        @dseg\n\
+        @byte scratch\n\
+        @cseg\n\
+        @global double\n\
+        double:\n\
+        st [$scratch], A\n\
+        ld B, [$scratch]\n\
+        add A, B\n\
+        ret\n\
+    ";
+
+    fn object(source: &str) -> Object {
+        let tokens = lex::lex_string(None, source).unwrap();
+        generator::generate_object(parse::parse(tokens).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn matches_assembler() {
+        VERBOSITY.get_or_init(|| Verbosity::Error);
+        let objects = vec![
+            ("main.o".to_owned(), object(MAIN)),
+            ("lib.o".to_owned(), object(LIB)),
+        ];
+        let linked = link_objects(&objects, &Script::default()).unwrap();
+
+        // Linking should give the same program as assembling everything at once
+        let tokens = lex::lex_string(None, format!("{MAIN}{LIB}")).unwrap();
+        let assembled = generator::generate(parse::parse(tokens).unwrap()).unwrap();
+        assert_eq!(linked.program[..], assembled.program[..]);
+        assert_eq!(linked.ranges, assembled.ranges);
+        assert_eq!(
+            linked.symbols.label("double"),
+            assembled.symbols.label("double")
+        );
+    }
+
+    #[test]
+    fn placement() {
+        let objects = vec![
+            ("main.o".to_owned(), object(MAIN)),
+            ("lib.o".to_owned(), object(LIB)),
+        ];
+        let script: Script = "code 0x0100..0x0200\ndata 0xC000..0xFFC0".parse().unwrap();
+        let linked = link_objects(&objects, &script).unwrap();
+
+        assert_eq!(linked.symbols.label("main"), Some(0x0000));
+        assert_eq!(linked.symbols.label("double"), Some(0x0100));
+        assert_eq!(linked.symbols.variable("result"), Some(0xC000));
+        assert_eq!(linked.symbols.variable("scratch"), Some(0xC001));
+
+        let small: Script = "code 0x0100..0x0104".parse().unwrap();
+        assert!(matches!(
+            link_objects(&objects, &small),
+            Err(LinkerError::NoSpace(_))
+        ));
+        assert!(matches!(
+            link_objects(&objects[..1], &Script::default()),
+            Err(LinkerError::Undefined { .. })
+        ));
+        assert!(matches!(
+            link_objects(
+                &[objects[1].clone(), objects[1].clone()],
+                &Script::default()
+            ),
+            Err(LinkerError::Duplicate { .. })
+        ));
+        assert!("code 0x0000..0x20000".parse::<Script>().is_err());
+    }
+
+    #[test]
+    fn rom_end() {
+        VERBOSITY.get_or_init(|| Verbosity::Error);
+        let source =
+            |org: u16| format!("@dseg\n@byte value\n@cseg\n@org {org:#06X}\nld A, [$value]\n");
+
+        let script: Script = "data 0xC000..0xE000".parse().unwrap();
+
+        // A section ending at 0xFFFF still has its operand patched
+        let objects = vec![("main.o".to_owned(), object(&source(0xFFFC)))];
+        let linked = link_objects(&objects, &script).unwrap();
+        assert_eq!(linked.ranges, vec![0xFFFC..0xFFFF]);
+        assert_eq!(linked.program[0xFFFD..], [0xC0, 0x00, 0x00]);
+
+        // ...but one reaching the end of the program ROM is rejected rather than overflowing
+        let objects = vec![("main.o".to_owned(), object(&source(0xFFFD)))];
+        assert!(matches!(
+            link_objects(&objects, &script),
+            Err(LinkerError::NoSpace(_))
+        ));
+    }
+
+    #[test]
+    fn macro_labels() {
+        VERBOSITY.get_or_init(|| Verbosity::Error);
+        let source = "\
+            @macro wait (%n:imm) {\n\
+                mv F, %n\n\
+            %%loop:\n\
+                dec F\n\
+                jnz F, [%%loop]\n\
+            }\n\
+            @cseg\n\
+            @org 0x0000\n\
+            main:\n\
+            wait 2\n\
+            wait 3\n\
+            halt\n\
+        ";
+        let object: Object = object(source).to_string().parse().unwrap();
+        assert!(object
+            .relocations
+            .iter()
+            .any(|reloc| reloc.expression.starts_with("main.loop { 0 ")));
+        let linked = link_objects(&[("main.o".to_owned(), object)], &Script::default()).unwrap();
+
+        let tokens = lex::lex_string(None, source).unwrap();
+        let assembled = generator::generate(parse::parse(tokens).unwrap()).unwrap();
+        assert_eq!(linked.program[..], assembled.program[..]);
+    }
+
+    #[test]
+    fn library() {
+        VERBOSITY.get_or_init(|| Verbosity::Error);
+        // The example from the README, with objects read back from their text format
+        let main = "@cseg\n@org 0x0000\n@extern mul\nmv A, 6\nmv B, 7\ncall [mul]\nhalt\n";
+        let math = include_str!("../../os/math.asm");
+        let objects: Vec<(String, Object)> = [("main.o", main), ("math.o", math)]
+            .into_iter()
+            .map(|(name, source)| (name.to_owned(), object(source).to_string().parse().unwrap()))
+            .collect();
+        let linked = link_objects(&objects, &Script::default()).unwrap();
+
+        let (bank, _) = test_emulate(linked.program, &[], 1000).unwrap();
+        assert_eq!(bank.a, 42);
+    }
+}
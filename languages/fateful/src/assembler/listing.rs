@@ -0,0 +1,202 @@
+//! Listing files, showing what each line of source was assembled into.
+//!
+//! Listings are emitted by the assembler with `--listing`.
+//! Each emitted instruction is written on its own line, along with its address,
+//! encoded bytes, and the source line it came from.
+//! Instructions expanded from macros also list the chain of macro invocations that produced them,
+//! with the location of each invocation after the first:
+//!
+//! ```text
+//! 0x0006  A8 00 0C  fib.asm:13  call [fib]  ; call > jmp (builtin macros:245)
+//! 0x0009  E8 01     fib.asm:13  call [fib]  ; call > jmp (builtin macros:245) > jmp (builtin macros:73)
+//! ```
+
+use std::{collections::HashMap, fmt, fs, sync::Arc};
+
+use super::lex::{Source, Span};
+
+/// The most data bytes written on a single line.
+const DATA_LINE: usize = 4;
+
+/// A macro invocation that an instruction was expanded from.
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub name: String,
+    pub span: Arc<Span>,
+}
+
+#[derive(Debug)]
+enum Entry {
+    Label(u16, String),
+    Instruction {
+        address: u16,
+        bytes: Vec<u8>,
+        /// The span of the outermost macro invocation, or of the instruction itself.
+        span: Arc<Span>,
+        chain: Vec<Invocation>,
+    },
+    Bytes(u16, Vec<u8>),
+}
+
+/// Every label, instruction, and data byte emitted by the assembler, in the order they were emitted.
+#[derive(Debug, Default)]
+pub struct Listing {
+    entries: Vec<Entry>,
+}
+
+impl Listing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert_label<T: Into<String>>(&mut self, address: u16, name: T) {
+        self.entries.push(Entry::Label(address, name.into()));
+    }
+
+    pub fn insert_instruction(
+        &mut self,
+        address: u16,
+        bytes: &[u8],
+        span: Arc<Span>,
+        chain: Vec<Invocation>,
+    ) {
+        self.entries.push(Entry::Instruction {
+            address,
+            bytes: bytes.to_vec(),
+            span,
+            chain,
+        });
+    }
+
+    pub fn insert_bytes(&mut self, address: u16, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            self.entries.push(Entry::Bytes(address, bytes.to_vec()));
+        }
+    }
+}
+
+/// The `file:line` location of a span.
+fn location(span: &Span) -> String {
+    format!("{}:{}", span.source, span.line_number())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Source lines, read once per file.
+#[derive(Default)]
+struct Lines(HashMap<String, Vec<String>>);
+
+impl Lines {
+    fn get(&mut self, span: &Span) -> &str {
+        let lines = self
+            .0
+            .entry(span.source.to_string())
+            .or_insert_with(|| match &span.source {
+                // Sources that can't be read again (such as `stdin`) are left blank
+                Source::File(path) => fs::read_to_string(path.path())
+                    .map(|source| source.lines().map(str::to_owned).collect())
+                    .unwrap_or_default(),
+                Source::String { name: _, source } => source.lines().map(str::to_owned).collect(),
+            });
+
+        lines.get(span.line).map(|line| line.trim()).unwrap_or("")
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = Lines::default();
+        let width = self
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::Instruction { span, .. } => location(span).len(),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0);
+
+        for entry in self.entries.iter() {
+            match entry {
+                Entry::Label(address, name) => {
+                    writeln!(f, "{address:#06X}  {:8}  {:width$}  {name}:", "", "")?
+                }
+                Entry::Instruction {
+                    address,
+                    bytes,
+                    span,
+                    chain,
+                } => {
+                    write!(
+                        f,
+                        "{address:#06X}  {:8}  {:width$}  {}",
+                        hex(bytes),
+                        location(span),
+                        lines.get(span)
+                    )?;
+
+                    if let Some((first, rest)) = chain.split_first() {
+                        write!(f, "  ; {}", first.name)?;
+                        for invocation in rest {
+                            write!(f, " > {} ({})", invocation.name, location(&invocation.span))?;
+                        }
+                    }
+                    writeln!(f)?;
+                }
+                Entry::Bytes(address, bytes) => {
+                    for (i, chunk) in bytes.chunks(DATA_LINE).enumerate() {
+                        let address = address.wrapping_add((i * DATA_LINE) as u16);
+                        writeln!(f, "{address:#06X}  {}", hex(chunk))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::{generator, lex, parse};
+
+    #[test]
+    fn expansions() {
+        let source = "\
+            @org 0x0000\n\
+            main:\n\
+            mv A, 2\n\
+            call [main]\n\
+            @str \"hello\"\n\
+        ";
+        let tokens = lex::lex_string(Some("main.asm"), source).unwrap();
+        let generated = generator::generate(parse::parse(tokens).unwrap()).unwrap();
+        let listing = generated.listing.to_string();
+        let lines: Vec<&str> = listing.lines().collect();
+
+        assert_eq!(
+            lines[0].split_whitespace().collect::<Vec<_>>(),
+            ["0x0000", "main:"]
+        );
+        assert_eq!(
+            lines[1].split_whitespace().collect::<Vec<_>>(),
+            ["0x0000", "78", "02", "main.asm:3", "mv", "A,", "2"]
+        );
+        assert_eq!(
+            lines[2],
+            "0x0002  C8 0B     main.asm:4  call [main]  ; call"
+        );
+        assert_eq!(
+            lines[5],
+            "0x0009  E8 01     main.asm:4  call [main]  ; call > jmp (builtin macros:245) > jmp (builtin macros:73)"
+        );
+        assert_eq!(lines[lines.len() - 2], "0x000B  68 65 6C 6C");
+        assert_eq!(lines[lines.len() - 1], "0x000F  6F 00");
+    }
+}
@@ -0,0 +1,438 @@
+//! Relocatable object files, linked into a program with `fateful link`.
+//!
+//! Object files are emitted by the assembler with `-c`.
+//! Every code and data segment becomes a section,
+//! which the linker places at its `@org` if it has one, or anywhere it fits otherwise.
+//! Each line contains a single entry of one of the following forms:
+//!
+//! ```text
+//! code - 7807C80BC800A8000CE801F0
+//! code 0x0100 F0
+//! data - 0x0004
+//! label 0 0x000C fib
+//! local 0 0x0012 1 fib.loop
+//! variable 2 0x0002 counter
+//! reloc 0 0x0006 addr16 fib
+//! reloc 0 0x0015 addr16 fib.loop { 1 }
+//! reloc 0 0x001A addr16 fib.wait%3 { 1 3 }
+//! ```
+//!
+//! Sections are numbered in the order they appear, starting at 0.
+//! `label` and `variable` entries give the section and offset of each symbol.
+//! `local` entries are labels that weren't exported with `@global`,
+//! along with the scope of the file that defined them.
+//! They can only be referenced from the same object, as `name { scope }`,
+//! or `name { scope expansion }` for a macro label renamed in the given expansion
+//! (which can't be written directly, since the separator doesn't lex).
+//! `reloc` entries mark an instruction, by its section and offset,
+//! whose operand depends on where the sections are placed.
+//! The operand is left as zero, and is filled in by the linker once the expression has been evaluated,
+//! with `$` being the address of the instruction.
+
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+use super::{
+    lex::{Delimeter, Ident, Punctuation, Token, TokenInner},
+    parse::EXPANSION_SEPARATOR,
+};
+use crate::{diagnostic::Diagnostic, spanned_error};
+
+#[derive(Debug, Error)]
+#[error("malformed object file (line {line}): {message}")]
+pub struct ObjectError {
+    line: usize,
+    message: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Section {
+    /// Assembled instructions and bytes, placed in the program ROM.
+    Code { org: Option<u16>, bytes: Vec<u8> },
+    /// Space reserved for variables, placed in RAM.
+    Data { org: Option<u16>, size: u16 },
+}
+
+impl Section {
+    /// The address given with `@org`,
+    /// or `None` if the section can be placed anywhere.
+    pub fn org(&self) -> Option<u16> {
+        match self {
+            Section::Code { org, .. } | Section::Data { org, .. } => *org,
+        }
+    }
+
+    pub fn size(&self) -> u16 {
+        match self {
+            Section::Code { bytes, .. } => bytes.len() as u16,
+            Section::Data { size, .. } => *size,
+        }
+    }
+}
+
+/// A label or variable, relative to the start of its section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub section: usize,
+    pub offset: u16,
+    pub name: String,
+}
+
+/// A label that is only visible in the file that defined it,
+/// identified by the scope of that file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Local {
+    pub scope: usize,
+    pub symbol: Symbol,
+}
+
+/// Splits a label key into its name,
+/// and the scope of the file it belongs to if it isn't global.
+pub fn scoped(key: &str) -> (&str, Option<usize>) {
+    match key.split_once('@') {
+        Some((name, scope)) => (name, scope.parse().ok()),
+        None => (key, None),
+    }
+}
+
+/// How an operand is encoded after an instruction's opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A 16-bit address, high byte first.
+    Addr16,
+    /// An 8-bit immediate.
+    Imm8,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Addr16 => write!(f, "addr16"),
+            Kind::Imm8 => write!(f, "imm8"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub section: usize,
+    /// Offset of the instruction in its section.
+    pub offset: u16,
+    pub kind: Kind,
+    pub expression: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub labels: Vec<Symbol>,
+    pub locals: Vec<Local>,
+    pub variables: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// Writes an expression in a form that lexes back into the same tokens.
+pub fn expression(tokens: &[Token]) -> Result<String, Diagnostic> {
+    let mut expression = Vec::with_capacity(tokens.len());
+
+    for tok in tokens {
+        expression.push(match &tok.inner {
+            TokenInner::Immediate(imm) => imm.to_string(),
+            TokenInner::Ident(Ident::Ident(key)) => match scoped(key) {
+                (name, Some(scope)) => match name.rsplit_once(EXPANSION_SEPARATOR) {
+                    Some((name, expansion)) => format!("{name} {{ {scope} {expansion} }}"),
+                    None => format!("{name} {{ {scope} }}"),
+                },
+                (name, None) => name.to_owned(),
+            },
+            TokenInner::Ident(Ident::Variable(name)) => format!("${name}"),
+            TokenInner::Location => "$".to_owned(),
+            TokenInner::Delimeter(Delimeter::OpenParen) => "(".to_owned(),
+            TokenInner::Delimeter(Delimeter::ClosedParen) => ")".to_owned(),
+            TokenInner::Punctuation(punct) => match punct {
+                Punctuation::EqEq => "==",
+                Punctuation::Ne => "!=",
+                Punctuation::Lt => "<",
+                Punctuation::Le => "<=",
+                Punctuation::Gt => ">",
+                Punctuation::Ge => ">=",
+                Punctuation::And => "&",
+                Punctuation::AndAnd => "&&",
+                Punctuation::Or => "|",
+                Punctuation::OrOr => "||",
+                Punctuation::Caret => "^",
+                Punctuation::Not => "!",
+                Punctuation::Slash => "/",
+                Punctuation::Plus => "+",
+                Punctuation::Minus => "-",
+                Punctuation::Star => "*",
+                Punctuation::Shl => "<<",
+                Punctuation::Shr => ">>",
+                Punctuation::Eq | Punctuation::Comma | Punctuation::Colon => {
+                    return Err(spanned_error!(
+                        tok.span.clone(),
+                        "unexpected {} in expression",
+                        tok.inner.description()
+                    ))
+                }
+            }
+            .to_owned(),
+            inner => {
+                return Err(spanned_error!(
+                    tok.span.clone(),
+                    "unexpected {} in expression",
+                    inner.description()
+                ))
+            }
+        });
+    }
+
+    Ok(expression.join(" "))
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for section in self.sections.iter() {
+            let org = match section.org() {
+                Some(org) => format!("{org:#06X}"),
+                None => "-".to_owned(),
+            };
+
+            match section {
+                Section::Code { bytes, .. } => {
+                    write!(f, "code {org} ")?;
+                    for byte in bytes {
+                        write!(f, "{byte:02X}")?;
+                    }
+                    writeln!(f)?;
+                }
+                Section::Data { size, .. } => writeln!(f, "data {org} {size:#06X}")?,
+            }
+        }
+
+        for label in self.labels.iter() {
+            writeln!(
+                f,
+                "label {} {:#06X} {}",
+                label.section, label.offset, label.name
+            )?;
+        }
+
+        for local in self.locals.iter() {
+            writeln!(
+                f,
+                "local {} {:#06X} {} {}",
+                local.symbol.section, local.symbol.offset, local.scope, local.symbol.name
+            )?;
+        }
+
+        for var in self.variables.iter() {
+            writeln!(
+                f,
+                "variable {} {:#06X} {}",
+                var.section, var.offset, var.name
+            )?;
+        }
+
+        for reloc in self.relocations.iter() {
+            writeln!(
+                f,
+                "reloc {} {:#06X} {} {}",
+                reloc.section, reloc.offset, reloc.kind, reloc.expression
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn hex(value: &str) -> Option<u16> {
+    u16::from_str_radix(value.strip_prefix("0x")?, 16).ok()
+}
+
+impl FromStr for Object {
+    type Err = ObjectError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut object = Object::default();
+
+        for (n, line) in s.lines().enumerate() {
+            let error = |message| ObjectError {
+                line: n + 1,
+                message,
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let mut parts = line.split(' ');
+            match parts.next().ok_or_else(|| error("missing entry kind"))? {
+                kind @ ("code" | "data") => {
+                    let org = match parts.next() {
+                        Some("-") => None,
+                        Some(org) => Some(hex(org).ok_or_else(|| {
+                            error("expected a hexadecimal origin, or `-` for none")
+                        })?),
+                        None => return Err(error("missing section origin")),
+                    };
+                    let value = parts.next().unwrap_or("");
+
+                    object.sections.push(if kind == "code" {
+                        if value.len() % 2 != 0 {
+                            return Err(error("expected section contents as hexadecimal bytes"));
+                        }
+                        let bytes = (0..value.len())
+                            .step_by(2)
+                            .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+                            .collect::<Option<Vec<u8>>>()
+                            .ok_or_else(|| {
+                                error("expected section contents as hexadecimal bytes")
+                            })?;
+                        if bytes.len() > u16::MAX as usize {
+                            return Err(error("section is larger than 64 KiB"));
+                        }
+                        Section::Code { org, bytes }
+                    } else {
+                        let size =
+                            hex(value).ok_or_else(|| error("expected a hexadecimal size"))?;
+                        Section::Data { org, size }
+                    });
+                }
+                kind @ ("label" | "local" | "variable" | "reloc") => {
+                    let section = parts
+                        .next()
+                        .and_then(|section| section.parse().ok())
+                        .filter(|section| *section < object.sections.len())
+                        .ok_or_else(|| error("expected the index of a previous section"))?;
+                    let offset = parts
+                        .next()
+                        .and_then(hex)
+                        .ok_or_else(|| error("expected a hexadecimal offset"))?;
+
+                    if kind == "reloc" {
+                        let kind = match parts.next() {
+                            Some("addr16") => Kind::Addr16,
+                            Some("imm8") => Kind::Imm8,
+                            _ => return Err(error("expected `addr16` or `imm8`")),
+                        };
+                        let expression = parts.collect::<Vec<_>>().join(" ");
+                        if expression.is_empty() {
+                            return Err(error("missing relocation expression"));
+                        }
+
+                        object.relocations.push(Relocation {
+                            section,
+                            offset,
+                            kind,
+                            expression,
+                        });
+                    } else {
+                        let scope = match kind {
+                            "local" => Some(
+                                parts
+                                    .next()
+                                    .and_then(|scope| scope.parse().ok())
+                                    .ok_or_else(|| error("expected the scope of a local label"))?,
+                            ),
+                            _ => None,
+                        };
+                        let name = parts
+                            .next()
+                            .filter(|name| !name.is_empty())
+                            .ok_or_else(|| error("missing symbol name"))?;
+                        let symbol = Symbol {
+                            section,
+                            offset,
+                            name: name.to_owned(),
+                        };
+
+                        match (kind, scope) {
+                            (_, Some(scope)) => object.locals.push(Local { scope, symbol }),
+                            ("label", _) => object.labels.push(symbol),
+                            _ => object.variables.push(symbol),
+                        }
+                    }
+                }
+                _ => return Err(error("unknown entry kind")),
+            }
+        }
+
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{generator, lex, parse};
+
+    #[test]
+    fn round_trip() {
+        let source = "\
+            @dseg\n\
+            @double counter\n\
+            @cseg\n\
+            @global main\n\
+            @extern external\n\
+            main:\n\
+            ld A, [$counter]\n\
+            call [external]\n\
+            .loop:\n\
+            jmp [.loop]\n\
+            @cseg\n\
+            @org 0x0100\n\
+            halt\n\
+        ";
+        let tokens = lex::lex_string(Some("main.asm"), source).unwrap();
+        let object = generator::generate_object(parse::parse(tokens).unwrap()).unwrap();
+
+        assert_eq!(object.sections.len(), 3);
+        assert_eq!(object.sections[1].org(), Some(0x0100));
+        assert_eq!(object.sections[2], Section::Data { org: None, size: 2 });
+        assert_eq!(object.labels[0].name, "main");
+        assert_eq!(
+            object.locals[0],
+            Local {
+                scope: 0,
+                symbol: Symbol {
+                    section: 0,
+                    offset: 0x000C,
+                    name: "main.loop".to_owned(),
+                },
+            }
+        );
+        assert_eq!(
+            object.relocations[0],
+            Relocation {
+                section: 0,
+                offset: 0x0000,
+                kind: Kind::Addr16,
+                expression: "$counter".to_owned(),
+            }
+        );
+        assert!(object
+            .relocations
+            .iter()
+            .any(|reloc| reloc.kind == Kind::Imm8 && reloc.expression == "( $ + 9 ) & 255"));
+        assert!(object
+            .relocations
+            .iter()
+            .any(|reloc| reloc.expression == "main.loop { 0 }"));
+
+        let parsed: Object = object.to_string().parse().unwrap();
+        assert_eq!(parsed, object);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!("code 0x10000 F0".parse::<Object>().is_err());
+        assert!("code - F".parse::<Object>().is_err());
+        assert!("label 0 0x0000 main".parse::<Object>().is_err());
+        assert!("code - F0\nreloc 0 0x0000 imm16 main"
+            .parse::<Object>()
+            .is_err());
+    }
+}
@@ -28,6 +28,10 @@ use std::{collections::HashMap, iter, str::FromStr, sync::Arc};
 use bitflags::bitflags;
 use lazy_regex::regex_captures;
 
+/// Separates a macro label from the number of the expansion it was renamed in (e.g. `main.loop%0`).
+/// Identifiers can't contain it, so renamed macro labels never collide with labels in the source.
+pub const EXPANSION_SEPARATOR: char = '%';
+
 #[derive(Debug, Clone)]
 pub struct Punctuated<T, S> {
     list: Vec<(T, S)>,
@@ -442,6 +446,10 @@ pub struct ParseStream {
     pub code: Vec<CSeg>,
     pub data: Vec<DSeg>,
     pub macros: HashMap<String, Macro>,
+    /// Labels exported from their file with `@global`.
+    pub globals: Vec<Ident>,
+    /// Labels declared with `@extern`, defined with `@global` in another file.
+    pub externs: Vec<Ident>,
 }
 
 pub fn parse(mut stream: TokenStream) -> Result<ParseStream, Errors> {
@@ -482,6 +490,9 @@ pub fn parse(mut stream: TokenStream) -> Result<ParseStream, Errors> {
     ctx.cursor.position = 0;
     ctx.cursor.skip_ignored();
 
+    let mut globals = Vec::new();
+    let mut externs = Vec::new();
+
     while let Some(tok) = ctx.cursor.peek() {
         if let TokenInner::Ident(lex::Ident::PreProc(PreProc::Cseg)) = tok.inner {
             let mut segment = Segment::CSeg(CSeg {
@@ -536,6 +547,32 @@ pub fn parse(mut stream: TokenStream) -> Result<ParseStream, Errors> {
             } else {
                 *ctx.current_segment.org() = Some(origin.address);
             }
+        } else if let TokenInner::Ident(lex::Ident::PreProc(PreProc::Global)) = tok.inner {
+            let global: Global = ctx
+                .cursor
+                .parse()
+                .map_err(|err| Into::<Errors>::into(err))?;
+            globals.push(global.name);
+        } else if let TokenInner::Ident(lex::Ident::PreProc(PreProc::Extern)) = tok.inner {
+            let external: Extern = ctx
+                .cursor
+                .parse()
+                .map_err(|err| Into::<Errors>::into(err))?;
+            externs.push(external.name);
+        } else if let TokenInner::Ident(lex::Ident::MacroLabel(_)) = tok.inner {
+            errors.push(
+                spanned_error!(tok.span.clone(), "macro label outside of a macro")
+                    .with_help("macro labels (`%%name`) can only be used within `@macro` rules"),
+            );
+            while !matches!(
+                ctx.cursor.peek(),
+                Some(Token {
+                    inner: TokenInner::NewLine,
+                    ..
+                }) | None
+            ) {
+                ctx.cursor.position += 1;
+            }
         } else {
             match ctx.current_segment {
                 Segment::CSeg(ref mut cseg) => match ctx.cursor.parse() {
@@ -581,6 +618,8 @@ pub fn parse(mut stream: TokenStream) -> Result<ParseStream, Errors> {
             code: ctx.code,
             data: ctx.data,
             macros: ctx.macros,
+            globals,
+            externs,
         })
     }
 }
@@ -987,6 +1026,36 @@ impl Parsable for Org {
     }
 }
 
+/// `@global name`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    name: Ident,
+}
+
+impl Parsable for Global {
+    fn parse(cursor: &mut Cursor) -> Result<Self, Diagnostic> {
+        let _: Token![@global] = cursor.parse()?;
+        Ok(Global {
+            name: cursor.parse()?,
+        })
+    }
+}
+
+/// `@extern name`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extern {
+    name: Ident,
+}
+
+impl Parsable for Extern {
+    fn parse(cursor: &mut Cursor) -> Result<Self, Diagnostic> {
+        let _: Token![@extern] = cursor.parse()?;
+        Ok(Extern {
+            name: cursor.parse()?,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Define {
     pub name: String,
@@ -1135,7 +1204,14 @@ impl MacroDef {
     }
 
     /// Must make sure that the provided parameters match this rule with [`MacroDef::fits`]
-    pub fn expand(&self, parameters: &[Argument]) -> Result<Vec<ParseTok>, Diagnostic> {
+    ///
+    /// `expansion` uniquely identifies this expansion,
+    /// and is used to rename macro labels (`%%name`) so that separate expansions don't collide.
+    pub fn expand(
+        &self,
+        parameters: &[Argument],
+        expansion: usize,
+    ) -> Result<Vec<ParseTok>, Diagnostic> {
         let mut expanded = Vec::new();
 
         let parameters: HashMap<String, &Argument> = HashMap::from_iter(
@@ -1150,6 +1226,15 @@ impl MacroDef {
             position: 0,
         };
 
+        // Macro labels become local labels under whichever label the macro was invoked in
+        for tok in cursor.stream.iter_mut() {
+            if let TokenInner::Ident(lex::Ident::MacroLabel(ref label)) = tok.inner {
+                tok.inner = TokenInner::Ident(lex::Ident::Ident(format!(
+                    ".{label}{EXPANSION_SEPARATOR}{expansion}"
+                )));
+            }
+        }
+
         cursor.skip_ignored();
 
         while let Some(tok) = cursor.peek() {
@@ -1508,13 +1593,14 @@ impl Macro {
         &self,
         span: Arc<Span>,
         parameters: &[Argument],
+        expansion: usize,
     ) -> Result<Vec<ParseTok>, Diagnostic> {
         let rule = self
             .rules
             .iter()
             .find(|def| def.fits(&parameters))
             .ok_or_else(|| spanned_error!(span, "no rules matched these arguments"))?;
-        rule.expand(&parameters)
+        rule.expand(&parameters, expansion)
     }
 }
 
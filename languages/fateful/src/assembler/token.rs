@@ -50,6 +50,8 @@ macro_rules! Token {
     [@quad] => {$crate::assembler::token::Quad};
     [@str] => {$crate::assembler::token::Str};
     [@var] => {$crate::assembler::token::Var};
+    [@global] => {$crate::assembler::token::Global};
+    [@extern] => {$crate::assembler::token::Extern};
     [@error] => {$crate::assembler::token::Error};
 }
 
@@ -157,6 +159,8 @@ parsable! {
     "@quad"   ; match Ident(lex::Ident::PreProc(PreProc::Quad)) => Quad,
     "@str"    ; match Ident(lex::Ident::PreProc(PreProc::Str)) => Str,
     "@var"    ; match Ident(lex::Ident::PreProc(PreProc::Var)) => Var,
+    "@global" ; match Ident(lex::Ident::PreProc(PreProc::Global)) => Global,
+    "@extern" ; match Ident(lex::Ident::PreProc(PreProc::Extern)) => Extern,
     "@error"  ; match Ident(lex::Ident::PreProc(PreProc::Error)) => Error,
 }
 
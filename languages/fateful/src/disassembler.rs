@@ -0,0 +1,265 @@
+//! Decodes assembled programs back into Fate assembly.
+
+use std::{
+    fmt,
+    io::{Read, Write},
+};
+
+use clap::Args;
+use clio::{Input, Output};
+use thiserror::Error;
+
+use crate::symbols::{SymbolError, Symbols};
+
+const REGISTERS: [&str; 8] = ["A", "B", "C", "D", "E", "F", "H", "L"];
+const MNEMONICS: [&str; 16] = [
+    "add", "sub", "adc", "sbb", "nand", "or", "cmp", "mv", "ld", "st", "lda", "lpm", "push", "pop",
+    "jnz", "halt",
+];
+
+#[derive(Debug, Args)]
+pub struct DisassemblerArgs {
+    /// Input program ROM
+    #[clap(value_parser, default_value = "-")]
+    input: Input,
+    #[clap(short, long, value_parser, default_value = "-")]
+    output: Output,
+    /// Symbol file produced by `fateful asm --symbols`
+    #[clap(long, value_parser)]
+    symbols: Option<Input>,
+}
+
+#[derive(Debug, Error)]
+pub enum DisassemblerError {
+    #[error("unable to read provided input: {0}")]
+    Input(std::io::Error),
+    #[error("unable to write to output: {0}")]
+    Output(std::io::Error),
+    #[error(transparent)]
+    Symbols(#[from] SymbolError),
+}
+
+/// A single decoded instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disassembled {
+    pub address: u16,
+    pub bytes: [u8; 3],
+    pub size: u16,
+}
+
+impl Disassembled {
+    /// Decodes the instruction located at `address` in `program`.
+    /// Bytes past the end of `program` are read as `0x00`.
+    pub fn decode(program: &[u8], address: u16) -> Disassembled {
+        let byte = |offset: u16| {
+            program
+                .get(address.wrapping_add(offset) as usize)
+                .copied()
+                .unwrap_or(0)
+        };
+
+        let head = byte(0);
+        let immediate = head & 0b0000_1000 != 0;
+        let size = match head >> 4 {
+            0x0..=0x7 => 2,
+            0xA => 3,
+            0x8 | 0x9 | 0xB if immediate => 3,
+            0xC | 0xE if immediate => 2,
+            _ => 1,
+        };
+
+        Disassembled {
+            address,
+            bytes: [head, byte(1), byte(2)],
+            size,
+        }
+    }
+
+    /// The 16-bit address operand of `ld`, `st`, `lda`, and `lpm`, if present.
+    pub fn operand_address(&self) -> Option<u16> {
+        if self.size == 3 {
+            Some(u16::from_be_bytes([self.bytes[1], self.bytes[2]]))
+        } else {
+            None
+        }
+    }
+
+    /// The address of the next instruction.
+    pub fn next(&self) -> u16 {
+        self.address.wrapping_add(self.size)
+    }
+
+    /// Formats the raw bytes of this instruction, padded to a consistent width.
+    pub fn hex(&self) -> String {
+        let bytes: Vec<String> = self.bytes[..self.size as usize]
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        format!("{:<8}", bytes.join(" "))
+    }
+}
+
+impl fmt::Display for Disassembled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [head, second, _] = self.bytes;
+        let opcode = head >> 4;
+        let immediate = head & 0b0000_1000 != 0;
+        let register = REGISTERS[(head & 0b0000_0111) as usize];
+        let mnemonic = MNEMONICS[opcode as usize];
+
+        match (opcode, self.operand_address()) {
+            (0x0..=0x7, _) => {
+                if immediate {
+                    write!(f, "{mnemonic} {register}, {second:#04X}")
+                } else {
+                    write!(
+                        f,
+                        "{mnemonic} {register}, {}",
+                        REGISTERS[(second & 0b0000_0111) as usize]
+                    )
+                }
+            }
+            (0x8 | 0xB, Some(addr)) => write!(f, "{mnemonic} {register}, [{addr:#06X}]"),
+            (0x9, Some(addr)) => write!(f, "{mnemonic} [{addr:#06X}], {register}"),
+            (0xA, Some(addr)) => write!(f, "{mnemonic} [{addr:#06X}]"),
+            (0xC | 0xE, _) if immediate => write!(f, "{mnemonic} {second:#04X}"),
+            (0xF, _) => write!(f, "{mnemonic}"),
+            _ => write!(f, "{mnemonic} {register}"),
+        }
+    }
+}
+
+/// Disassembles `count` instructions starting at `address`.
+pub fn disassemble_range(program: &[u8], address: u16, count: usize) -> Vec<Disassembled> {
+    let mut instructions = Vec::with_capacity(count);
+    let mut address = address;
+
+    for _ in 0..count {
+        let inst = Disassembled::decode(program, address);
+        address = inst.next();
+        instructions.push(inst);
+    }
+
+    instructions
+}
+
+/// Formats a single line of disassembly, annotated with any symbols.
+pub fn format_line(inst: &Disassembled, symbols: &Symbols) -> String {
+    let annotation = inst
+        .operand_address()
+        .and_then(|addr| match inst.bytes[0] >> 4 {
+            // `ld` and `st` reference RAM rather than ROM
+            0x8 | 0x9 => symbols.memory(addr).map(|var| format!("${var}")),
+            _ => symbols.program(addr),
+        });
+
+    match annotation {
+        Some(annotation) => format!(
+            "{:#06X}  {}  {:<24} ; {annotation}",
+            inst.address,
+            inst.hex(),
+            inst.to_string()
+        ),
+        None => format!("{:#06X}  {}  {inst}", inst.address, inst.hex()),
+    }
+}
+
+pub fn disassemble(mut args: DisassemblerArgs) -> Result<(), DisassemblerError> {
+    let mut program = vec![0; 1 << 16];
+    let mut read = 0;
+    loop {
+        match args
+            .input
+            .read(&mut program[read..])
+            .map_err(|err| DisassemblerError::Input(err))?
+        {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    let symbols = match args.symbols {
+        Some(mut input) => {
+            let mut source = String::new();
+            input
+                .read_to_string(&mut source)
+                .map_err(|err| DisassemblerError::Input(err))?;
+            source.parse()?
+        }
+        None => Symbols::new(),
+    };
+
+    // Programs are padded to the full address space, so skip the trailing zeros.
+    let end = program
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map(|pos| pos + 1)
+        .unwrap_or(0);
+
+    {
+        let mut output = args.output.lock();
+        let mut address: usize = 0;
+        while address < end {
+            let inst = Disassembled::decode(&program, address as u16);
+
+            if let Some(label) = symbols.label_at(inst.address) {
+                writeln!(output, "{label}:").map_err(|err| DisassemblerError::Output(err))?;
+            }
+            writeln!(output, "    {}", format_line(&inst, &symbols))
+                .map_err(|err| DisassemblerError::Output(err))?;
+
+            address += inst.size as usize;
+        }
+    }
+
+    args.output
+        .finish()
+        .map_err(|err| DisassemblerError::Output(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{generator, lex, parse};
+
+    #[test]
+    fn decode() {
+        let program = [
+            0x78, 0x10, 0x01, 0x00, 0x88, 0x12, 0x34, 0xA8, 0x00, 0x0C, 0xC8, 0x01, 0xD6, 0xF0,
+        ];
+        let lines: Vec<String> = disassemble_range(&program, 0, 7)
+            .iter()
+            .map(|inst| inst.to_string())
+            .collect();
+
+        assert_eq!(
+            lines,
+            [
+                "mv A, 0x10",
+                "add B, A",
+                "ld A, [0x1234]",
+                "lda [0x000C]",
+                "push 0x01",
+                "pop H",
+                "halt"
+            ]
+            .map(str::to_owned)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let source: String = REGISTERS
+            .iter()
+            .map(|reg| format!("mv {reg}, 0x05\nadd A, {reg}\nld {reg}, [0x1234]\n"))
+            .collect();
+        let tokens = lex::lex_string(None, source.as_str()).unwrap();
+        let assembled = generator::generate(parse::parse(tokens).unwrap()).unwrap();
+
+        let lines: Vec<String> = disassemble_range(&assembled.program[..], 0, REGISTERS.len() * 3)
+            .iter()
+            .map(|inst| inst.to_string())
+            .collect();
+        assert_eq!(lines, source.lines().collect::<Vec<&str>>());
+    }
+}
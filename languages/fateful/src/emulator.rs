@@ -1,13 +1,21 @@
+mod dap;
 mod display;
+mod gdb;
+mod history;
+mod machine;
+mod peripheral;
+mod profile;
+mod snapshot;
+mod trace;
 
 use std::{
     cmp::Ordering,
-    collections::HashMap,
-    ffi::{c_char, c_int, c_void, CStr},
+    collections::{HashMap, HashSet},
     fmt,
     io::{Read, Write},
+    path::PathBuf,
     str::FromStr,
-    sync::{Arc, OnceLock},
+    sync::{Arc, Mutex, OnceLock, PoisonError},
     time::{Duration, Instant},
 };
 
@@ -20,29 +28,29 @@ use bitflags::bitflags;
 use clap::Args;
 use clio::Input;
 use display::TextBuffer;
-use libloading::Library;
+use history::{History, Overwritten, Registers};
 use modular_bitfield::prelude::*;
+use peripheral::{Device, Port};
+use profile::Profile;
+use snapshot::{Snapshot, SnapshotError, TEXT_SIZE};
 use thiserror::Error;
+use trace::Trace;
+
+use crate::{
+    disassembler::{self, Disassembled},
+    symbols::{SymbolError, Symbols},
+};
+
+pub use dap::{debug_adapter, DapError};
+pub use machine::{Machine, MachineError};
+pub use peripheral::{mkdisk, DiskArgs, DiskError};
 
 const CTRL_LOW: &[u8; 1 << 8] = include_bytes!(concat!(env!("OUT_DIR"), "/ctrl_low.rom"));
 const CTRL_MID: &[u8; 1 << 8] = include_bytes!(concat!(env!("OUT_DIR"), "/ctrl_mid.rom"));
 const CTRL_HIGH: &[u8; 1 << 8] = include_bytes!(concat!(env!("OUT_DIR"), "/ctrl_high.rom"));
 
-type NameFn = unsafe extern "C" fn() -> *const c_char;
-type InitFn = unsafe extern "C" fn(u8) -> c_int;
-type StatefulInitFn = unsafe extern "C" fn(u8) -> *mut c_void;
-type ReadFn = unsafe extern "C" fn(u8) -> u8;
-type StatefulReadFn = unsafe extern "C" fn(*mut c_void, u8) -> u8;
-type WriteFn = unsafe extern "C" fn(u8, u8);
-type StatefulWriteFn = unsafe extern "C" fn(*mut c_void, u8, u8);
-type TickFn = unsafe extern "C" fn();
-type StatefulTickFn = unsafe extern "C" fn(*mut c_void);
-type DropFn = unsafe extern "C" fn();
-type StatefulDropFn = unsafe extern "C" fn(*mut c_void);
-type ResetFn = unsafe extern "C" fn();
-type StatefulResetFn = unsafe extern "C" fn(*mut c_void);
-type LastErrLen = unsafe extern "C" fn() -> c_int;
-type GetLastErr = unsafe extern "C" fn(*mut c_char, c_int) -> c_int;
+/// Address of the emulator status port.
+const STATUS: u16 = 0xFFFC;
 
 #[derive(Error, Debug)]
 pub enum EmulatorError {
@@ -56,6 +64,24 @@ pub enum EmulatorError {
     OnceFull,
     #[error("global state not initialized yet")]
     OnceEmpty,
+    #[error("unable to read provided symbol file")]
+    SymbolInput(std::io::Error),
+    #[error(transparent)]
+    Symbols(#[from] SymbolError),
+    #[error("unable to read provided script")]
+    ScriptInput(std::io::Error),
+    #[error("script failed on line {line}: `{command}`")]
+    Script { line: usize, command: String },
+    #[error("{0} expectation(s) failed")]
+    Expect(usize),
+    #[error("unable to write to trace file")]
+    Trace(std::io::Error),
+    #[error("GDB connection failed")]
+    Gdb(std::io::Error),
+    #[error(transparent)]
+    Machine(#[from] MachineError),
+    #[error("unable to load machine peripheral {0}")]
+    MachinePeripheral(String),
 }
 
 #[derive(Debug, Args)]
@@ -63,6 +89,54 @@ pub struct EmulatorArgs {
     /// Input program ROM
     #[clap(value_parser, default_value = "-")]
     input: Input,
+    /// Symbol file produced by `fateful asm --symbols`
+    #[clap(long, value_parser)]
+    symbols: Option<Input>,
+    /// Run without opening a window for the text buffer
+    #[clap(long)]
+    headless: bool,
+    /// File of REPL commands to run instead of reading from stdin
+    #[clap(long, value_parser)]
+    script: Option<Input>,
+    /// Machine file listing the peripherals to attach before starting
+    #[clap(long)]
+    machine: Option<PathBuf>,
+    /// Record every instruction retired by the CPU to the given file
+    #[clap(long)]
+    trace: Option<PathBuf>,
+    /// Number of clock cycles to keep a history of for `BACK`
+    #[clap(long, default_value_t = HISTORY)]
+    history: usize,
+    /// Wait for a GDB remote debugger to connect on the given port instead of reading commands
+    #[clap(long, conflicts_with = "script")]
+    gdb: Option<u16>,
+}
+
+/// Default number of clock cycles recorded for `BACK`.
+const HISTORY: usize = 1 << 16;
+
+/// Where REPL commands are read from.
+enum Source {
+    Stdin(Receiver<String>),
+    /// The remaining lines of a script, along with their line numbers.
+    Script(std::vec::IntoIter<(usize, String)>),
+}
+
+/// Wraps a writer, keeping track of whether anything was written to it.
+struct Tally<W> {
+    inner: W,
+    written: bool,
+}
+
+impl<W: Write> Write for Tally<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written |= !buf.is_empty();
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 enum Command {
@@ -73,12 +147,26 @@ enum Command {
     Drop,
     Run,
     Load,
+    Reload,
     Dump,
     Quit,
     Step,
     Reset,
     Help,
     Stop,
+    Break,
+    Watch,
+    Delete,
+    List,
+    Next,
+    Disasm,
+    Screen,
+    Expect,
+    Trace,
+    Back,
+    Save,
+    Restore,
+    Profile,
     Blank,
 }
 
@@ -123,12 +211,17 @@ impl Command {
                 If no ports are provided, all ports are disconnected, dropping all peripherals.\n\
             ",
             Command::Run => "\
-                RUN <speed>:\n\
+                RUN [speed]:\n\
+                RUN CYCLES <n>:\n\
                 \n\
                 Runs the CPU at the given speed.\n\
                 If no speed is supplied, or if the given speed is `0`,\n\
                 the CPU will run at maximum speed.\n\
                 \n\
+                With `CYCLES`, the CPU clock is pulsed exactly `n` times before the command returns,\n\
+                stopping early if the CPU halts or a breakpoint or watchpoint is hit.\n\
+                Cannot be used while the CPU is running.\n\
+                \n\
                 `speed` is measured in hz.\n\
             ",
             Command::Load => "\
@@ -138,8 +231,20 @@ impl Command {
                 If no ports are supplied, the module will not be initialized.\n\
                 Read more about peripheral modules in the README\n\
                 \n\
-                `module` must be a valid path to a shared library.\n\
-                Each `port` must be within the range 0xFFC0 through 0xFFFE (inclusive).\n\
+                `module` must be a valid path to a shared library,\n\
+                or `builtin:<name>[:<options>]` to use one of the built-in peripherals below.\n\
+                Options are given as comma-separated `key=value` pairs.\n\
+                Each `port` must be within the range 0xFFC0 through 0xFFFB (inclusive).\n\
+            ",
+            Command::Reload => "\
+                RELOAD <port>:\n\
+                \n\
+                Drops the peripheral connected to `port` and loads it again from the same path,\n\
+                reconnecting it to all of the ports it was connected to.\n\
+                The peripheral starts from its initial state, but the rest of the machine is left untouched,\n\
+                so a rebuilt peripheral can be tested without restarting the program.\n\
+                \n\
+                `port` must be within the range 0xFFC0 through 0xFFFB (inclusive).\n\
             ",
             Command::Dump => "\
                 DUMP:\n\
@@ -147,11 +252,13 @@ impl Command {
                 Prints the current machine state, including the:\n\
                 - Program Counter\n\
                 - Stack Pointer\n\
+                - Cycle Count\n\
                 - Bus\n\
                 - Register Bank\n\
                 - Control Word\n\
                 - Instruction Register\n\
                 - Attached Peripherals\n\
+                - Peripheral Debug Info\n\
             ",
             Command::Quit => "\
                 QUIT:\n\
@@ -183,6 +290,121 @@ impl Command {
                 Stops the CPU clock.\n\
                 Enables the use of `STEP`.\n\
             ",
+            Command::Break => "\
+                BREAK <address>:\n\
+                \n\
+                Sets a breakpoint at the given program address.\n\
+                The CPU clock is stopped before the instruction at `address` is executed.\n\
+                \n\
+                `address` must be in the range 0x0000 through 0xFFFF (inclusive).\n\
+            ",
+            Command::Watch => "\
+                WATCH <address> [r|w|rw]:\n\
+                \n\
+                Sets a watchpoint at the given memory address.\n\
+                The CPU clock is stopped whenever the CPU reads from (`r`), writes to (`w`),\n\
+                or accesses (`rw`) `address`. Defaults to `rw` if no access is supplied.\n\
+                \n\
+                `address` must be in the range 0x0000 through 0xFFFF (inclusive).\n\
+            ",
+            Command::Delete => "\
+                DELETE [address...]:\n\
+                \n\
+                If 1 or more addresses are provided, removes any breakpoints and watchpoints at each address.\n\
+                If no addresses are provided, all breakpoints and watchpoints are removed.\n\
+            ",
+            Command::List => "\
+                LIST:\n\
+                \n\
+                Lists all breakpoints and watchpoints.\n\
+            ",
+            Command::Next => "\
+                NEXT:\n\
+                \n\
+                Steps the CPU clock until the current instruction has finished executing.\n\
+                Prints the next instruction to be executed.\n\
+                Cannot be used while the CPU is running.\n\
+                \n\
+                `STEPI` is an alias for `NEXT`.\n\
+            ",
+            Command::Disasm => "\
+                DISASM [address] [count]:\n\
+                \n\
+                Disassembles `count` instructions starting at `address`.\n\
+                If no address is supplied, disassembly starts at the program counter.\n\
+                If no count is supplied, 8 instructions are disassembled.\n\
+                \n\
+                `address` must be in the range 0x0000 through 0xFFFF (inclusive).\n\
+            ",
+            Command::Screen => "\
+                SCREEN [file]:\n\
+                \n\
+                Renders the current contents of the text buffer.\n\
+                If no file is supplied, the text buffer is printed using ANSI escape codes.\n\
+                If a file is supplied, a PNG image of the text buffer is written to `file`.\n\
+                Available whether or not the emulator is headless.\n\
+            ",
+            Command::Expect => "\
+                EXPECT <register|address> <value>:\n\
+                \n\
+                Checks that the selected register or memory address contains `value`.\n\
+                Failed expectations are reported, and cause the emulator to exit with an error.\n\
+                \n\
+                `register` must be one of A, B, C, D, E, F, H, or L.\n\
+                `address` must be in the range 0x0000 through 0xFFFF (inclusive).\n\
+                `value` must be in the range 0 through 255 (inclusive).\n\
+            ",
+            Command::Trace => "\
+                TRACE START <file>:\n\
+                TRACE STOP:\n\
+                \n\
+                Starts or stops recording an execution trace to `file`.\n\
+                Each instruction retired by the CPU is written as a single line,\n\
+                including the program counter, the decoded instruction,\n\
+                the register bank, SREG, the bus, and any memory writes.\n\
+            ",
+            Command::Back => "\
+                BACK [n]:\n\
+                BACK CYCLES <n>:\n\
+                \n\
+                Rewinds the CPU by `n` instructions, or by `n` clock cycles with `CYCLES`.\n\
+                If no count is supplied, the CPU is rewound by a single instruction.\n\
+                Registers, memory, and the text buffer are restored, but peripherals are not.\n\
+                Cannot be used while the CPU is running.\n\
+            ",
+            Command::Save => "\
+                SAVE <file>:\n\
+                \n\
+                Saves a snapshot of the entire machine to `file`, including the:\n\
+                - CPU registers\n\
+                - Memory\n\
+                - Program ROM\n\
+                - Text Buffer\n\
+                - Attached Peripherals\n\
+                The internal state of each peripheral is not saved.\n\
+            ",
+            Command::Restore => "\
+                RESTORE <file>:\n\
+                \n\
+                Restores the machine to a snapshot saved by `SAVE`.\n\
+                Any attached peripherals are dropped,\n\
+                and the peripherals in the snapshot are reloaded from their paths.\n\
+                Cannot be used while the CPU is running.\n\
+            ",
+            Command::Profile => "\
+                PROFILE START:\n\
+                PROFILE STOP:\n\
+                PROFILE REPORT [n] [file]:\n\
+                PROFILE STACKS <file>:\n\
+                \n\
+                Starts or stops the profiler, which counts the executions and clock cycles spent at each program address.\n\
+                Starting the profiler discards any previous results.\n\
+                \n\
+                `REPORT` prints the `n` hottest addresses (10 by default),\n\
+                along with the total cycles spent under each label, to `file` or the terminal.\n\
+                `STACKS` writes the cycles spent in each call stack to `file` in the collapsed-stack format used by flamegraph tools.\n\
+                Labels and call stacks require a symbol file.\n\
+            ",
             Command::Blank => unreachable!(),
         }
     }
@@ -200,12 +422,26 @@ impl FromStr for Command {
             "DROP" => Ok(Command::Drop),
             "RUN" => Ok(Command::Run),
             "LOAD" => Ok(Command::Load),
+            "RELOAD" => Ok(Command::Reload),
             "DUMP" => Ok(Command::Dump),
             "QUIT" => Ok(Command::Quit),
             "STEP" => Ok(Command::Step),
             "RESET" => Ok(Command::Reset),
             "HELP" => Ok(Command::Help),
             "STOP" => Ok(Command::Stop),
+            "BREAK" => Ok(Command::Break),
+            "WATCH" => Ok(Command::Watch),
+            "DELETE" => Ok(Command::Delete),
+            "LIST" => Ok(Command::List),
+            "NEXT" | "STEPI" => Ok(Command::Next),
+            "DISASM" => Ok(Command::Disasm),
+            "SCREEN" => Ok(Command::Screen),
+            "EXPECT" => Ok(Command::Expect),
+            "TRACE" => Ok(Command::Trace),
+            "BACK" => Ok(Command::Back),
+            "SAVE" => Ok(Command::Save),
+            "RESTORE" => Ok(Command::Restore),
+            "PROFILE" => Ok(Command::Profile),
             "" => Ok(Command::Blank),
             _ => Err(()),
         }
@@ -287,6 +523,71 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Bits of the emulator status port
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    struct Status: u8 {
+        /// At least one peripheral needs attention
+        const ATTN = 1 << 0;
+    }
+}
+
+bitflags! {
+    /// Memory accesses that trigger a watchpoint
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    struct Access: u8 {
+        /// Read
+        const R = 1 << 0;
+        /// Write
+        const W = 1 << 1;
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.contains(Access::R) {
+            write!(f, "r")?;
+        }
+        if self.contains(Access::W) {
+            write!(f, "w")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Access {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "r" | "R" => Ok(Access::R),
+            "w" | "W" => Ok(Access::W),
+            "rw" | "RW" | "wr" | "WR" => Ok(Access::R | Access::W),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Reason the CPU clock was stopped by a breakpoint or watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Trap {
+    Break(u16),
+    Watch { addr: u16, access: Access },
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trap::Break(addr) => write!(f, "breakpoint hit at {addr:#06X}"),
+            Trap::Watch {
+                addr,
+                access: Access::R,
+            } => write!(f, "watchpoint hit (read from {addr:#06X})"),
+            Trap::Watch { addr, .. } => write!(f, "watchpoint hit (write to {addr:#06X})"),
+        }
+    }
+}
+
 trait Notified {
     fn notified_add(self, other: Self, sreg: &mut SReg, remove: bool) -> Self;
     fn notified_sub(self, other: Self, sreg: &mut SReg, remove: bool) -> Self;
@@ -565,7 +866,7 @@ mod __head {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 struct Control {
     head: InstructionHeader,
     clock: u8,
@@ -587,7 +888,6 @@ struct State {
     sp: u16,
     ctrl: Control,
     sreg: SReg,
-    timer: u16,
     alu: Alu,
     bus: u8,
     bank: RegBank,
@@ -596,18 +896,33 @@ struct State {
     quit: bool,
     mem: Box<[u8]>,
     program: Box<[u8]>,
-    peripherals: HashMap<u8, Peripheral>,
+    peripherals: HashMap<u8, Port>,
+    /// Every loaded peripheral, regardless of how many ports it's connected to.
+    devices: Vec<Arc<Mutex<Device>>>,
     text_buffer: TextBuffer,
+    breakpoints: HashSet<u16>,
+    /// The breakpoint the CPU last stopped at,
+    /// which is passed over when the clock resumes so it doesn't trap again.
+    resumed: Option<u16>,
+    watchpoints: HashMap<u16, Access>,
+    trap: Option<Trap>,
+    symbols: Symbols,
+    /// Number of failed `EXPECT` commands.
+    failures: usize,
+    /// Number of clock cycles since the last reset.
+    cycles: u64,
+    trace: Option<Trace>,
+    history: History,
+    profile: Option<Profile>,
 }
 
 impl State {
-    fn init(program: Box<[u8]>) -> Self {
+    fn init(program: Box<[u8]>, symbols: Symbols, text_buffer: TextBuffer) -> Self {
         State {
             pc: 0,
             sp: 0xEFFF,
             ctrl: Control::default(),
             sreg: SReg::empty(),
-            timer: 0,
             alu: Alu::default(),
             bus: 0,
             bank: RegBank::default(),
@@ -617,7 +932,18 @@ impl State {
             mem: vec![0; 1 << 16].into_boxed_slice(),
             program,
             peripherals: HashMap::new(),
-            text_buffer: TextBuffer::spawn(),
+            devices: Vec::new(),
+            text_buffer,
+            breakpoints: HashSet::new(),
+            resumed: None,
+            watchpoints: HashMap::new(),
+            trap: None,
+            symbols,
+            failures: 0,
+            cycles: 0,
+            trace: None,
+            history: History::new(HISTORY),
+            profile: None,
         }
     }
 
@@ -626,13 +952,43 @@ impl State {
             return true;
         }
 
+        // Breakpoints stop the CPU before the instruction at their address runs
+        if self.ctrl.clock == 0
+            && self.resumed.take() != Some(self.pc)
+            && self.trap.is_none()
+            && self.breakpoints.contains(&self.pc)
+        {
+            self.resumed = Some(self.pc);
+            self.trap = Some(Trap::Break(self.pc));
+            return false;
+        }
+
+        self.history.record(self.registers());
+
+        if self.ctrl.clock == 0 {
+            if let Some(trace) = &mut self.trace {
+                trace.begin(self.pc);
+            }
+        }
+
         let cw = self.cw();
 
         if cw.contains(ControlWord::SH) {
             self.sreg.insert(SReg::H);
+            self.retire();
             return true;
         }
 
+        // Halting doesn't take a full cycle, so it isn't profiled
+        if let Some(profile) = &mut self.profile {
+            if profile.running {
+                if self.ctrl.clock == 0 {
+                    profile.begin(self.pc, &self.symbols);
+                }
+                profile.tick();
+            }
+        }
+
         // rising edge
 
         if cw.contains(ControlWord::PCI) {
@@ -664,34 +1020,9 @@ impl State {
                 &mut self.sreg,
             )
         } else if cw.contains(ControlWord::LA) {
-            match self.addr {
-                0x0000..=0xEFFF => self.mem[self.addr as usize],
-                0xF000..=0xFFCF => self.text_buffer.get(self.addr - 0xF000),
-                0xFFD0..=0xFFFC => match self.peripherals.get(&((self.addr - 0xFFC0) as u8)) {
-                    Some(periph) => unsafe {
-                        if let Ok(stateful_read) =
-                            periph.lib.library.get::<StatefulReadFn>(b"stateful_read")
-                        {
-                            match periph.lib.state {
-                                Some(state) => stateful_read(state, periph.n),
-                                None => {
-                                    eprintln!("PERIPHERAL ERROR: unable to call `stateful_read` (state was not initialized)");
-                                    return true;
-                                }
-                            }
-                        } else if let Ok(read) = periph.lib.library.get::<ReadFn>(b"read") {
-                            read(periph.n)
-                        } else {
-                            eprintln!("PERIPHERAL ERROR: `read` and `stateful_write` not present in peripheral (peripherals must implement one of these)");
-                            return true;
-                        }
-                    },
-                    None => 0x00,
-                },
-                0xFFFD => (self.pc >> 8) as u8,
-                0xFFFE => (self.pc & 0xFF) as u8,
-                0xFFFF => self.sreg.bits(),
-            }
+            self.watch(Access::R);
+
+            self.read_bus(self.addr)
         } else if cw.contains(ControlWord::PO) {
             program_byte
         } else if cw.contains(ControlWord::LPM) {
@@ -707,44 +1038,24 @@ impl State {
         }
 
         if cw.contains(ControlWord::SA) {
+            self.watch(Access::W);
+            if let Some(trace) = &mut self.trace {
+                trace.write(self.addr, bus);
+            }
+
             match self.addr {
-                0x0000..=0xEFFF => {
-                    self.mem[self.addr as usize] = bus;
-                }
-                0xF000..=0xFFCF => {
-                    self.text_buffer.set(self.addr - 0xF000, bus);
-                }
-                0xFFD0..=0xFFFC => match self.peripherals.get(&((self.addr - 0xFFC0) as u8)) {
-                    Some(periph) => unsafe {
-                        if let Ok(stateful_write) =
-                            periph.lib.library.get::<StatefulWriteFn>(b"stateful_write")
-                        {
-                            match periph.lib.state {
-                                Some(state) => stateful_write(state, periph.n, bus),
-                                None => {
-                                    eprintln!("PERIPHERAL ERROR: unable to call `stateful_write` (state was not initialized)");
-                                    return true;
-                                }
-                            }
-                        } else if let Ok(write) = periph.lib.library.get::<WriteFn>(b"write") {
-                            write(periph.n, bus);
-                        } else {
-                            eprintln!("PERIPHERAL ERROR: `read` and `stateful_read` not present in peripheral (peripherals must implement one of these)");
-                            return true;
-                        }
-                    },
-                    None => {}
-                },
-                0xFFFD => {
-                    self.pc = (self.pc & 0xFF00) | (bus as u16);
-                }
-                0xFFFE => {
-                    self.pc = (self.pc & 0x00FF) | ((bus as u16) << 8);
-                }
-                0xFFFF => {
-                    self.sreg = SReg::from_bits_retain(bus);
-                }
+                0x0000..=0xEFFF => self.history.overwrite(Overwritten::Memory {
+                    addr: self.addr,
+                    data: self.mem[self.addr as usize],
+                }),
+                0xF000..=0xFFCF => self.history.overwrite(Overwritten::Text {
+                    addr: self.addr - 0xF000,
+                    data: self.text_buffer.get(self.addr - 0xF000),
+                }),
+                _ => {}
             }
+
+            self.write_bus(self.addr, bus);
         }
 
         if cw.contains(ControlWord::ALI) {
@@ -798,29 +1109,18 @@ impl State {
         }
 
         // falling edge
-        for (_, peripheral) in self.peripherals.iter() {
-            unsafe {
-                if let Ok(stateful_tick) = peripheral
-                    .lib
-                    .library
-                    .get::<StatefulTickFn>(b"stateful_tick")
-                {
-                    match peripheral.lib.state {
-                        Some(state) => stateful_tick(state),
-                        None => {
-                            eprintln!("PERIPHERAL ERROR: unable to call `stateful_tick` (state was not initialized)");
-                        }
-                    }
-                } else if let Ok(stateless_tick) = peripheral.lib.library.get::<TickFn>(b"tick") {
-                    stateless_tick();
-                }
-            }
+        for device in self.devices.iter() {
+            device
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .tick();
         }
 
-        self.timer = self.timer.wrapping_add(1);
+        self.cycles += 1;
 
         if cw.contains(ControlWord::CR) {
             self.ctrl.clock = 0;
+            self.retire();
         } else {
             self.ctrl.clock = self.ctrl.clock.wrapping_add(1);
         }
@@ -828,21 +1128,296 @@ impl State {
         false
     }
 
+    /// Reads `addr` the same way the CPU does, including the text buffer and memory-mapped I/O.
+    fn read_bus(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0xEFFF => self.mem[addr as usize],
+            0xF000..=0xFFCF => self.text_buffer.get(addr - 0xF000),
+            0xFFD0..=0xFFFB => match self.peripherals.get(&((addr - 0xFFC0) as u8)) {
+                Some(port) => port.read(),
+                None => 0x00,
+            },
+            STATUS => self.status().bits(),
+            0xFFFD => (self.pc >> 8) as u8,
+            0xFFFE => (self.pc & 0xFF) as u8,
+            0xFFFF => self.sreg.bits(),
+        }
+    }
+
+    /// Writes `data` to `addr` the same way the CPU does, including the text buffer and memory-mapped I/O.
+    fn write_bus(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0xEFFF => self.mem[addr as usize] = data,
+            0xF000..=0xFFCF => self.text_buffer.set(addr - 0xF000, data),
+            0xFFD0..=0xFFFB => {
+                if let Some(port) = self.peripherals.get(&((addr - 0xFFC0) as u8)) {
+                    port.write(data);
+                }
+            }
+            // The status port is read-only
+            STATUS => {}
+            0xFFFD => {
+                self.pc = (self.pc & 0xFF00) | (data as u16);
+            }
+            0xFFFE => {
+                self.pc = (self.pc & 0x00FF) | ((data as u16) << 8);
+            }
+            0xFFFF => {
+                self.sreg = SReg::from_bits_retain(data);
+            }
+        }
+    }
+
+    /// Reads the emulator status port.
+    fn status(&self) -> Status {
+        let mut status = Status::empty();
+        if self.devices.iter().any(|device| {
+            device
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .peripheral
+                .pending()
+        }) {
+            status |= Status::ATTN;
+        }
+
+        status
+    }
+
+    fn registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            sp: self.sp,
+            ctrl: self.ctrl,
+            sreg: self.sreg,
+            alu: self.alu,
+            bus: self.bus,
+            bank: self.bank,
+            addr: self.addr,
+            cycles: self.cycles,
+        }
+    }
+
+    fn restore(&mut self, registers: Registers) {
+        self.pc = registers.pc;
+        self.sp = registers.sp;
+        self.ctrl = registers.ctrl;
+        self.sreg = registers.sreg;
+        self.alu = registers.alu;
+        self.bus = registers.bus;
+        self.bank = registers.bank;
+        self.addr = registers.addr;
+        self.cycles = registers.cycles;
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        // Group ports by the device they're connected to, ordered by their index in the device
+        let mut peripherals: Vec<(&Port, Vec<(u8, u8)>)> = Vec::new();
+        let mut ports: Vec<(&u8, &Port)> = self.peripherals.iter().collect();
+        ports.sort_by_key(|(port, _)| **port);
+
+        for (port, periph) in ports {
+            match peripherals
+                .iter_mut()
+                .find(|(device, _)| Arc::ptr_eq(&device.device, &periph.device))
+            {
+                Some((_, ports)) => ports.push((periph.n, *port)),
+                None => peripherals.push((periph, vec![(periph.n, *port)])),
+            }
+        }
+
+        Snapshot {
+            registers: self.registers(),
+            mem: self.mem.clone(),
+            program: self.program.clone(),
+            text: (0..TEXT_SIZE as u16)
+                .map(|addr| self.text_buffer.get(addr))
+                .collect(),
+            peripherals: peripherals
+                .into_iter()
+                .map(|(periph, mut ports)| {
+                    ports.sort();
+                    (
+                        periph.device().path.clone(),
+                        ports.into_iter().map(|(_, port)| port).collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns an error for each peripheral that couldn't be reloaded.
+    fn restore_snapshot(&mut self, snapshot: Snapshot) -> Vec<String> {
+        self.restore(snapshot.registers);
+        self.mem = snapshot.mem;
+        self.program = snapshot.program;
+        for (addr, data) in snapshot.text.iter().enumerate() {
+            self.text_buffer.set(addr as u16, *data);
+        }
+
+        self.history.clear();
+        self.drop_peripherals();
+        snapshot
+            .peripherals
+            .into_iter()
+            .filter_map(|(path, ports)| self.load(path, ports).err())
+            .collect()
+    }
+
+    /// Undoes the most recent clock cycle.
+    /// Returns `false` if there is no history left to rewind.
+    fn rewind(&mut self) -> bool {
+        let Some(delta) = self.history.pop() else {
+            return false;
+        };
+
+        self.restore(delta.registers);
+
+        match delta.overwritten {
+            Some(Overwritten::Memory { addr, data }) => self.mem[addr as usize] = data,
+            Some(Overwritten::Text { addr, data }) => self.text_buffer.set(addr, data),
+            None => {}
+        }
+
+        true
+    }
+
+    /// Undoes clock cycles until the start of the previous instruction.
+    /// Returns `false` if the history runs out first.
+    fn rewind_instruction(&mut self) -> bool {
+        loop {
+            if !self.rewind() {
+                return false;
+            }
+            if self.ctrl.clock == 0 {
+                return true;
+            }
+        }
+    }
+
+    /// Writes the instruction that just finished to the trace and profile, if either is being recorded.
+    fn retire(&mut self) {
+        if let Some(profile) = &mut self.profile {
+            if profile.running {
+                profile.retire(self.pc, &self.symbols);
+            }
+        }
+
+        if let Some(trace) = &mut self.trace {
+            if let Err(err) = trace.retire(&self.program, &self.bank, self.sreg.bits(), self.bus) {
+                eprintln!("TRACE ERROR: unable to write to trace, stopping trace: {err}");
+                self.trace = None;
+            }
+        }
+    }
+
+    /// Ticks the CPU at most `cycles` times,
+    /// stopping early if the CPU halts or a trap is hit.
+    /// Returns whether the CPU halted.
+    fn run_cycles(&mut self, cycles: u64) -> bool {
+        for _ in 0..cycles {
+            if self.tick() {
+                return true;
+            }
+            if self.trap.is_some() {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    /// Ticks the CPU until the current instruction finishes,
+    /// stopping early if the CPU halts or a watchpoint is hit.
+    fn step_instruction(&mut self) -> bool {
+        loop {
+            let end = self.cw().contains(ControlWord::CR);
+
+            if self.tick() {
+                return true;
+            }
+            if end || self.trap.is_some() {
+                return false;
+            }
+        }
+    }
+
+    fn watch(&mut self, access: Access) {
+        if self.trap.is_some() {
+            return;
+        }
+
+        if let Some(watched) = self.watchpoints.get(&self.addr) {
+            if watched.contains(access) {
+                self.trap = Some(Trap::Watch {
+                    addr: self.addr,
+                    access,
+                });
+            }
+        }
+    }
+
+    fn trap(&mut self, trap: Trap) {
+        self.speed = None;
+
+        print!(
+            "INFO: {}\n\
+            > ",
+            self.describe(trap)
+        );
+        std::io::stdout()
+            .flush()
+            .expect("should be able to write to `stdout`");
+    }
+
     fn halt(&mut self) {
         self.speed = None;
 
         print!(
-            "INFO: halt detected\n\
-            > "
+            "INFO: halt detected after {} cycles\n\
+            > ",
+            self.cycles
         );
         std::io::stdout()
             .flush()
             .expect("should be able to write to `stdout`");
     }
 
+    /// Describes a trap, annotated with any symbols that are loaded.
+    fn describe(&self, trap: Trap) -> String {
+        let symbol = match trap {
+            Trap::Break(addr) => self.symbols.program(addr),
+            Trap::Watch { addr, .. } => self.symbols.memory(addr).map(|var| var.to_owned()),
+        };
+
+        match symbol {
+            Some(symbol) => format!("{trap} {symbol}"),
+            None => trap.to_string(),
+        }
+    }
+
+    /// Appends the program symbol at `addr` to its hexadecimal representation, if one is loaded.
+    fn program_symbol(&self, addr: u16) -> String {
+        match self.symbols.program(addr) {
+            Some(symbol) => format!("{addr:#06X} {symbol}"),
+            None => format!("{addr:#06X}"),
+        }
+    }
+
+    /// Appends the variable at `addr` to its hexadecimal representation, if one is loaded.
+    fn memory_symbol(&self, addr: u16) -> String {
+        match self.symbols.memory(addr) {
+            Some(var) => format!("{addr:#06X} (${var})"),
+            None => format!("{addr:#06X}"),
+        }
+    }
+
     fn reset(&mut self) {
         self.pc = 0;
         self.ctrl.clock = 0;
+        self.resumed = None;
+        self.cycles = 0;
+        self.history.clear();
         self.mem.fill(0);
         self.sreg = SReg::from_bits_retain(0);
         self.sp = 0xEFFF;
@@ -850,21 +1425,8 @@ impl State {
         self.bank.clear();
         self.text_buffer.reset();
 
-        for periph in self.peripherals.values() {
-            unsafe {
-                if let Ok(stateful_reset) =
-                    periph.lib.library.get::<StatefulResetFn>(b"stateful_reset")
-                {
-                    match periph.lib.state {
-                        Some(state) => stateful_reset(state),
-                        None => {
-                            eprintln!("PERIPHERAL ERROR: unable to call `stateful_reset` (state was not initialized)");
-                        }
-                    }
-                } else if let Ok(reset) = periph.lib.library.get::<ResetFn>(b"reset") {
-                    reset();
-                }
-            }
+        for device in self.devices.iter() {
+            device.lock().unwrap_or_else(PoisonError::into_inner).reset();
         }
     }
 
@@ -880,105 +1442,152 @@ impl State {
         ControlWord::from_bits_retain(low | (mid << 8) | (high << 16))
     }
 
-    fn load(&mut self, path: String, ports: Vec<u8>) {
-        let (library, name, state) = unsafe {
-            let lib = match Library::new(&path) {
-                Ok(lib) => lib,
-                Err(err) => {
-                    eprintln!("Unable to load peripheral library: {err:?}");
-                    return;
-                }
-            };
-
-            let name = match lib.get::<NameFn>(b"name") {
-                Ok(name) => CStr::from_ptr(name()).to_str().ok().map(|s| s.to_owned()),
-                Err(_) => None,
-            }
-            .unwrap_or(path);
-
-            let (state, err) =
-                if let Ok(stateful_init) = lib.get::<StatefulInitFn>(b"stateful_init") {
-                    let state = stateful_init(ports.len() as u8);
-                    (Some(state), if state.is_null() { -1 } else { 0 })
-                } else if let Ok(init) = lib.get::<InitFn>(b"init") {
-                    let err = init(ports.len() as u8);
-                    (None, err)
-                } else {
-                    (None, 0)
-                };
-
-            if err != 0 {
-                let msg: Result<(Vec<u8>, c_int), libloading::Error> = lib
-                    .get::<LastErrLen>(b"last_error_length")
-                    .map(|lel| vec![0; lel() as usize])
-                    .and_then(|mut buf| {
-                        let written = lib.get::<GetLastErr>(b"get_last_error")?(
-                            buf.as_mut_ptr() as *mut c_char,
-                            buf.len() as c_int,
-                        );
-                        Ok((buf, written))
-                    });
-
-                match (msg, state.is_some()) {
-                    (Ok((buffer, 1..)), st) => match CStr::from_bytes_with_nul_unchecked(&buffer).to_str() {
-                        Ok(msg) => eprintln!("PERIPHERAL ERROR: {msg}"),
-                        Err(_) => match st {
-                            true => eprintln!("PERIPHRAL ERROR: initialization failed (`stateful_init` returned a null pointer)"),
-                            false => eprintln!("PERIPHERAL ERROR: initialization failed (`init` returned with exit code {err})"),
-                        }
-                    },
-                    (_, true) => eprintln!("PERIPHRAL ERROR: initialization failed (`stateful_init` returned a null pointer)"),
-                    (_, false) => eprintln!("PERIPHERAL ERROR: initialization failed (`init` returned with exit code {err})"),
-                }
-                return;
-            }
-
-            (lib, name, state)
-        };
-
-        let lib = Arc::new(Lib {
-            library,
-            name,
-            state,
-        });
+    /// Loads the peripheral at `path`, connecting it to each of `ports`.
+    fn load(&mut self, path: String, ports: Vec<u8>) -> Result<(), String> {
+        let peripheral = peripheral::load(&path, ports.len() as u8)?;
+        let device = Arc::new(Mutex::new(Device::new(peripheral, path)));
 
         for (n, port) in ports.into_iter().enumerate() {
-            let periph = Peripheral {
-                lib: lib.clone(),
+            let periph = Port {
+                device: device.clone(),
                 n: n as u8,
             };
             if let Some(periph) = self.peripherals.insert(port, periph) {
                 println!(
                     "WARNING: overwriting previous peripheral `{}`",
-                    periph.lib.name
+                    periph.device().peripheral.name()
                 );
             }
         }
+
+        self.devices.push(device);
+        self.prune_devices();
+
+        Ok(())
     }
-}
 
-impl fmt::Display for State {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let periph = if self.peripherals.is_empty() {
-            "[]".to_owned()
-        } else {
-            "[\n    ".to_owned()
-                + &self
-                    .peripherals
-                    .iter()
-                    .map(|(key, periph)| {
-                        format!("{:#06X}: \"{}\"", (*key as u16) + 0xFFC0, periph.lib.name)
-                    })
-                    .collect::<Vec<String>>()
-                    .join(",\n    ")
+    /// Drops the peripheral on `port` and loads it again from its path,
+    /// reconnecting it to the same ports in the same order.
+    /// Returns the path of the reloaded peripheral, or `None` if there wasn't one.
+    fn reload(&mut self, port: u8) -> Result<Option<String>, String> {
+        let Some(device) = self
+            .peripherals
+            .get(&port)
+            .map(|periph| periph.device.clone())
+        else {
+            return Ok(None);
+        };
+
+        let mut ports: Vec<(u8, u8)> = self
+            .peripherals
+            .iter()
+            .filter(|(_, periph)| Arc::ptr_eq(&periph.device, &device))
+            .map(|(port, periph)| (periph.n, *port))
+            .collect();
+        ports.sort();
+        let path = device
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .path
+            .clone();
+
+        // The old library has to be closed before it's opened again,
+        // otherwise the platform will hand back the copy that's already loaded
+        drop(device);
+        for (_, port) in ports.iter() {
+            self.peripherals.remove(port);
+        }
+        self.prune_devices();
+
+        self.load(
+            path.clone(),
+            ports.into_iter().map(|(_, port)| port).collect(),
+        )?;
+        Ok(Some(path))
+    }
+
+    /// Loads every peripheral listed in `machine`.
+    fn load_machine(&mut self, machine: &Machine) -> Result<(), String> {
+        for peripheral in machine.peripherals.iter() {
+            let path = peripheral.spec();
+            // Machine files only contain addresses in the memory-mapped I/O range
+            let ports = peripheral
+                .ports
+                .iter()
+                .map(|addr| (addr - 0xFFC0) as u8)
+                .collect();
+            self.load(path.clone(), ports)
+                .map_err(|err| format!("{path}: {err}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Disconnects the peripheral on `port`, returning `false` if there wasn't one.
+    fn drop_port(&mut self, port: u8) -> bool {
+        let dropped = self.peripherals.remove(&port).is_some();
+        self.prune_devices();
+        dropped
+    }
+
+    fn drop_peripherals(&mut self) {
+        self.peripherals.clear();
+        self.devices.clear();
+    }
+
+    /// Drops any peripherals that are no longer connected to a port.
+    fn prune_devices(&mut self) {
+        self.devices.retain(|device| Arc::strong_count(device) > 1);
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let periph = if self.peripherals.is_empty() {
+            "[]".to_owned()
+        } else {
+            "[\n    ".to_owned()
+                + &self
+                    .peripherals
+                    .iter()
+                    .map(|(key, periph)| {
+                        format!(
+                            "{:#06X}: \"{}\"",
+                            (*key as u16) + 0xFFC0,
+                            periph.device().peripheral.name()
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join(",\n    ")
                 + ",\n]"
         };
 
+        let info: Vec<String> = self
+            .devices
+            .iter()
+            .filter_map(|device| {
+                let device = device.lock().unwrap_or_else(PoisonError::into_inner);
+                device.peripheral.debug_info().map(|info| {
+                    format!(
+                        "\"{}\": {}",
+                        device.peripheral.name(),
+                        info.trim_end().replace('\n', "\n        ")
+                    )
+                })
+            })
+            .collect();
+        let info = if info.is_empty() {
+            "[]".to_owned()
+        } else {
+            "[\n    ".to_owned() + &info.join(",\n    ") + ",\n]"
+        };
+
         write!(
             f,
             "\
-                PROGRAM COUNTER: {:#06X}\n\
+                PROGRAM COUNTER: {}\n\
                 STACK POINTER: {:#06X}\n\
+                CYCLES: {}\n\
                 BUS: {:#04X}\n\
                 SREG: {:#04X}\n\
                 PROGRAM BYTE: {:#04X}\n\
@@ -987,10 +1596,13 @@ impl fmt::Display for State {
                 {}\
                 CONTROL WORD: {:?}\n\
                 INSTRUCTION: {:#010b}\n\
+                STATUS PORT: {:#04X}\n\
                 PERIPHERALS: {periph}\n\
+                PERIPHERAL INFO: {info}\n\
             ",
-            self.pc,
+            self.program_symbol(self.pc),
             self.sp,
+            self.cycles,
             self.bus,
             self.sreg.bits(),
             self.program[self.pc as usize],
@@ -998,7 +1610,8 @@ impl fmt::Display for State {
             self.alu.secondary,
             self.bank,
             self.cw(),
-            self.ctrl.head.bits()
+            self.ctrl.head.bits(),
+            self.status().bits(),
         )
     }
 }
@@ -1010,60 +1623,40 @@ enum ZeroCmd {
     Step,
     Reset,
     Stop,
+    List,
+    Next,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Hash)]
 enum SingleCmd {
     Get,
     Peek,
-    Run,
+    Reload,
+    Break,
+    Save,
+    Restore,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Hash)]
 enum DoubleCmd {
     Set,
     Poke,
+    Expect,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Hash)]
 enum VariadicCmd {
+    Run,
     Load,
     Drop,
     Help,
-}
-
-#[derive(Debug)]
-struct Lib {
-    name: String,
-    library: Library,
-    state: Option<*mut c_void>,
-}
-
-impl Drop for Lib {
-    fn drop(&mut self) {
-        unsafe {
-            if let Ok(stateful_drop) = self.library.get::<StatefulDropFn>(b"stateful_drop") {
-                match self.state {
-                    Some(state) => stateful_drop(state),
-                    None => {
-                        eprintln!("PERIPHERAL ERROR: unable to call `stateful_drop` (state was not initialized)");
-                    }
-                }
-            } else if let Ok(stateless_drop) = self.library.get::<DropFn>(b"drop") {
-                stateless_drop();
-            }
-        }
-    }
-}
-
-// We can do this since we are not using `state` across threads..
-unsafe impl Send for Lib {}
-unsafe impl Sync for Lib {}
-
-#[derive(Debug)]
-struct Peripheral {
-    lib: Arc<Lib>,
-    n: u8,
+    Watch,
+    Delete,
+    Disasm,
+    Screen,
+    Trace,
+    Back,
+    Profile,
 }
 
 static STATE: OnceLock<RwLock<State>> = OnceLock::new();
@@ -1075,28 +1668,115 @@ pub async fn emulate(mut args: EmulatorArgs) -> Result<(), EmulatorError> {
         .read(&mut program)
         .map_err(|err| EmulatorError::Input(err))?;
 
+    let symbols = match args.symbols {
+        Some(mut input) => {
+            let mut source = String::new();
+            input
+                .read_to_string(&mut source)
+                .map_err(|err| EmulatorError::SymbolInput(err))?;
+            source.parse()?
+        }
+        None => Symbols::new(),
+    };
+
+    let text_buffer = if args.headless {
+        TextBuffer::headless()
+    } else {
+        TextBuffer::spawn()
+    };
+
+    let machine = match args.machine {
+        Some(path) => Some(Machine::read(&path)?),
+        None => None,
+    };
+    // Commands are only read from stdin without a script or a debugger attached
+    let repl = args.script.is_none() && args.gdb.is_none();
+    let mut state = init_state(program, symbols, text_buffer, machine.as_ref(), repl)?;
+    state.history = History::new(args.history);
+    if let Some(path) = args.trace {
+        state.trace = Some(Trace::create(path).map_err(|err| EmulatorError::Trace(err))?);
+    }
+
     STATE
-        .set(RwLock::new(State::init(program)))
+        .set(RwLock::new(state))
         .map_err(|_| EmulatorError::OnceFull)?;
 
+    if let Some(port) = args.gdb {
+        let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+        gdb::serve(&mut state, port).map_err(|err| EmulatorError::Gdb(err))?;
+        return finish(&mut state);
+    }
+
+    let mut source = match args.script {
+        Some(mut input) => {
+            let mut script = String::new();
+            input
+                .read_to_string(&mut script)
+                .map_err(|err| EmulatorError::ScriptInput(err))?;
+
+            let lines: Vec<(usize, String)> = script
+                .lines()
+                .enumerate()
+                .map(|(n, line)| (n + 1, line.trim().to_owned()))
+                // Lines starting with `;` are comments
+                .filter(|(_, line)| !line.is_empty() && !line.starts_with(';'))
+                .collect();
+            Source::Script(lines.into_iter())
+        }
+        None => Source::Stdin(spawn_stdin_channel()),
+    };
+
     print!("> ");
     std::io::stdout()
         .flush()
         .map_err(|err| EmulatorError::StdOut(err))?;
-    let stdin = spawn_stdin_channel();
 
     let mut prev = Instant::now();
 
     loop {
-        match stdin.try_recv() {
-            Ok(s) => {
-                handle_input(s, &mut std::io::stdout(), &mut std::io::stderr()).await?;
-                std::io::stdout()
-                    .flush()
-                    .map_err(|err| EmulatorError::StdOut(err))?;
+        match &mut source {
+            Source::Stdin(stdin) => match stdin.try_recv() {
+                Ok(s) => {
+                    handle_input(s, &mut std::io::stdout(), &mut std::io::stderr()).await?;
+                    std::io::stdout()
+                        .flush()
+                        .map_err(|err| EmulatorError::StdOut(err))?;
+                }
+                Err(TryRecvError::Closed) => return Err(EmulatorError::StdIn),
+                Err(TryRecvError::Empty) => {}
+            },
+            // The next command is only run once the clock is stopped,
+            // so `RUN` waits until the CPU halts or hits a breakpoint.
+            Source::Script(lines) => {
+                if STATE
+                    .get()
+                    .ok_or(EmulatorError::OnceEmpty)?
+                    .read()
+                    .await
+                    .speed
+                    .is_none()
+                {
+                    let Some((line, command)) = lines.next() else {
+                        println!();
+                        break;
+                    };
+                    println!("{command}");
+
+                    let mut ewriter = Tally {
+                        inner: std::io::stderr(),
+                        written: false,
+                    };
+                    handle_input(command.clone(), &mut std::io::stdout(), &mut ewriter).await?;
+                    std::io::stdout()
+                        .flush()
+                        .map_err(|err| EmulatorError::StdOut(err))?;
+
+                    if ewriter.written {
+                        println!();
+                        return Err(EmulatorError::Script { line, command });
+                    }
+                }
             }
-            Err(TryRecvError::Closed) => return Err(EmulatorError::StdIn),
-            Err(TryRecvError::Empty) => {}
         }
 
         if STATE
@@ -1122,13 +1802,28 @@ pub async fn emulate(mut args: EmulatorArgs) -> Result<(), EmulatorError> {
             let halted = state.tick();
             if halted {
                 state.halt();
+            } else if let Some(trap) = state.trap.take() {
+                state.trap(trap);
             }
         } else {
             async_std::task::sleep(Duration::from_millis(10)).await;
         }
     }
 
-    Ok(())
+    let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+    finish(&mut state)
+}
+
+/// Flushes the trace once the emulator exits, then reports any failed `EXPECT` commands.
+fn finish(state: &mut State) -> Result<(), EmulatorError> {
+    if let Some(trace) = state.trace.take() {
+        trace.finish().map_err(|err| EmulatorError::Trace(err))?;
+    }
+
+    match state.failures {
+        0 => Ok(()),
+        failures => Err(EmulatorError::Expect(failures)),
+    }
 }
 
 async fn handle_input(
@@ -1147,13 +1842,27 @@ async fn handle_input(
         "PEEK" => single_arg(SingleCmd::Peek, &args, &mut writer, ewriter).await?,
         "POKE" => double_arg(DoubleCmd::Poke, &args, &mut writer, ewriter).await?,
         "DROP" => variadic_arg(VariadicCmd::Drop, &args, &mut writer, ewriter).await?,
-        "RUN" => single_arg(SingleCmd::Run, &args, &mut writer, ewriter).await?,
+        "RUN" => variadic_arg(VariadicCmd::Run, &args, &mut writer, ewriter).await?,
         "LOAD" => variadic_arg(VariadicCmd::Load, &args, &mut writer, ewriter).await?,
+        "RELOAD" => single_arg(SingleCmd::Reload, &args, &mut writer, ewriter).await?,
         "DUMP" => zero_arg(ZeroCmd::Dump, &args, &mut writer, ewriter).await?,
         "QUIT" => zero_arg(ZeroCmd::Quit, &args, &mut writer, ewriter).await?,
         "STEP" => zero_arg(ZeroCmd::Step, &args, &mut writer, ewriter).await?,
         "RESET" => zero_arg(ZeroCmd::Reset, &args, &mut writer, ewriter).await?,
         "STOP" => zero_arg(ZeroCmd::Stop, &args, &mut writer, ewriter).await?,
+        "BREAK" => single_arg(SingleCmd::Break, &args, &mut writer, ewriter).await?,
+        "WATCH" => variadic_arg(VariadicCmd::Watch, &args, &mut writer, ewriter).await?,
+        "DELETE" => variadic_arg(VariadicCmd::Delete, &args, &mut writer, ewriter).await?,
+        "LIST" => zero_arg(ZeroCmd::List, &args, &mut writer, ewriter).await?,
+        "NEXT" | "STEPI" => zero_arg(ZeroCmd::Next, &args, &mut writer, ewriter).await?,
+        "DISASM" => variadic_arg(VariadicCmd::Disasm, &args, &mut writer, ewriter).await?,
+        "SCREEN" => variadic_arg(VariadicCmd::Screen, &args, &mut writer, ewriter).await?,
+        "EXPECT" => double_arg(DoubleCmd::Expect, &args, &mut writer, ewriter).await?,
+        "TRACE" => variadic_arg(VariadicCmd::Trace, &args, &mut writer, ewriter).await?,
+        "BACK" => variadic_arg(VariadicCmd::Back, &args, &mut writer, ewriter).await?,
+        "SAVE" => single_arg(SingleCmd::Save, &args, &mut writer, ewriter).await?,
+        "RESTORE" => single_arg(SingleCmd::Restore, &args, &mut writer, ewriter).await?,
+        "PROFILE" => variadic_arg(VariadicCmd::Profile, &args, &mut writer, ewriter).await?,
         "HELP" => variadic_arg(VariadicCmd::Help, &args, &mut writer, &mut ewriter).await?,
         "" => {}
         cmd => writeln!(ewriter, "UNRECOGNIZED COMMAND: {cmd}")
@@ -1165,18 +1874,34 @@ async fn handle_input(
     Ok(())
 }
 
-pub fn test_emulate(program: Box<[u8]>, timeout: Duration) -> Result<RegBank, ()> {
-    let start = Instant::now();
-    let mut state = State::init(program);
-
-    while start.elapsed() <= timeout {
-        let halted = state.tick();
-        if halted {
-            return Ok(state.bank);
+/// Runs `program` until it halts, returning the register bank and the number of cycles it took.
+/// Each peripheral is given as a path (see `LOAD`) and the addresses it's connected to.
+/// Fails if a peripheral can't be loaded, or the program doesn't halt within `max_cycles` cycles.
+pub fn test_emulate(
+    program: Box<[u8]>,
+    peripherals: &[(&str, &[u16])],
+    max_cycles: u64,
+) -> Result<(RegBank, u64), String> {
+    let mut state = State::init(program, Symbols::new(), TextBuffer::headless());
+    // Tests can't be rewound, so there's no reason to record anything
+    state.history = History::new(0);
+
+    for (path, addrs) in peripherals {
+        let mut ports = Vec::with_capacity(addrs.len());
+        for addr in addrs.iter() {
+            match addr {
+                0xFFC0..=0xFFFB => ports.push((addr - 0xFFC0) as u8),
+                _ => return Err(format!("peripherals cannot be mapped to {addr:#06X}")),
+            }
         }
+        state.load((*path).to_owned(), ports)?;
     }
 
-    Err(())
+    if state.run_cycles(max_cycles) {
+        Ok((state.bank, state.cycles))
+    } else {
+        Err(format!("emulator did not halt within {max_cycles} cycles"))
+    }
 }
 
 fn help() {
@@ -1187,12 +1912,30 @@ fn help() {
         PEEK <addr>         : Gets the value at the memory address `addr`\n\
         POKE <addr>, <val>  : Sets the value at the memory address `addr` to `val`\n\
         RUN <speed>         : Starts running the CPU at the specified `speed` (in hertz)\n\
-        LOAD <path>, <port> : Loads the library at the given path as a peripheral.\n\
+        RUN CYCLES <n>      : Pulses the clock `n` times (only available if the CPU is stopped)\n\
+        LOAD <path>, <port> : Loads the library at the given path (or `builtin:<name>`) as a peripheral.\n\
         DROP <port>         : Disconnects the peripheral on the given port, unloading the module.\n\
+        RELOAD <port>       : Drops the peripheral on the given port and loads it again from the same path.\n\
         DUMP                : Dumps the current machine state\n\
         STEP                : Pulses the clock a single time (only available if the CPU is stopped)\n\
         RESET               : Resets the program counter to 0x0000\n\
         STOP                : Stops the CPU clock\n\
+        BREAK <addr>        : Stops the CPU clock before the instruction at `addr` is executed\n\
+        WATCH <addr> [r|w]  : Stops the CPU clock when the memory address `addr` is accessed\n\
+        DELETE [addr]       : Removes the breakpoints and watchpoints at `addr`, or all if none is given\n\
+        LIST                : Lists all breakpoints and watchpoints\n\
+        NEXT                : Steps the clock until the current instruction finishes (alias `STEPI`)\n\
+        DISASM [addr] [n]   : Disassembles `n` instructions starting at `addr`\n\
+        SCREEN [file]       : Prints the text buffer, or saves it as a PNG to `file`\n\
+        EXPECT <reg|addr> <val> : Checks that the register `reg` or the address `addr` contains `val`\n\
+        TRACE START <file>  : Starts recording each retired instruction to `file`\n\
+        TRACE STOP          : Stops recording the current trace\n\
+        BACK [n]            : Rewinds the CPU by `n` instructions (or `n` clock cycles with `BACK CYCLES <n>`)\n\
+        SAVE <file>         : Saves a snapshot of the machine to `file`\n\
+        RESTORE <file>      : Restores the machine from the snapshot in `file`\n\
+        PROFILE <START|STOP>: Starts or stops counting the cycles spent at each address\n\
+        PROFILE REPORT [n]  : Prints the `n` hottest addresses and labels\n\
+        PROFILE STACKS <file> : Writes the collapsed call stacks to `file`\n\
         QUIT                : Quits the program\n\
         HELP                : Prints this message\
     "
@@ -1240,12 +1983,13 @@ async fn zero_arg(
                 .speed
                 .is_none()
             {
-                let halted = STATE
-                    .get()
-                    .ok_or(EmulatorError::OnceEmpty)?
-                    .write()
-                    .await
-                    .tick();
+                let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+                let halted = state.tick();
+
+                if let Some(trap) = state.trap.take() {
+                    writeln!(writer, "INFO: {}", state.describe(trap))
+                        .map_err(|err| EmulatorError::StdOut(err))?;
+                }
 
                 if halted {
                     writeln!(
@@ -1276,6 +2020,59 @@ async fn zero_arg(
                 .await
                 .speed = None
         }
+        ZeroCmd::Next => {
+            let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+
+            if state.speed.is_some() {
+                writeln!(
+                    ewriter,
+                    "INVALID COMMAND: NEXT can only be used if the CPU is stopped"
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?;
+                return Ok(());
+            }
+
+            let halted = state.step_instruction();
+
+            if let Some(trap) = state.trap.take() {
+                writeln!(writer, "INFO: {}", state.describe(trap))
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+            }
+
+            if halted {
+                writeln!(
+                    ewriter,
+                    "INVALID COMMAND: CPU is halted, the clock cannot be stepped"
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?;
+            } else {
+                let inst = Disassembled::decode(&state.program, state.pc);
+                writeln!(writer, "{}", disassembler::format_line(&inst, &state.symbols))
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+            }
+        }
+        ZeroCmd::List => {
+            let state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.read().await;
+
+            let mut breakpoints: Vec<u16> = state.breakpoints.iter().copied().collect();
+            breakpoints.sort();
+            let mut watchpoints: Vec<(u16, Access)> =
+                state.watchpoints.iter().map(|(addr, access)| (*addr, *access)).collect();
+            watchpoints.sort_by_key(|(addr, _)| *addr);
+
+            if breakpoints.is_empty() && watchpoints.is_empty() {
+                writeln!(writer, "INFO: no breakpoints or watchpoints set")
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+            }
+            for addr in breakpoints {
+                writeln!(writer, "BREAKPOINT: {}", state.program_symbol(addr))
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+            }
+            for (addr, access) in watchpoints {
+                writeln!(writer, "WATCHPOINT: {} [{access}]", state.memory_symbol(addr))
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+            }
+        }
     }
 
     Ok(())
@@ -1302,16 +2099,9 @@ async fn single_arg(
         SingleCmd::Get => {
             let reg = match parse_u8(arg) {
                 Ok(reg) => reg,
-                Err(_) => match arg {
-                    "A" => 0,
-                    "B" => 1,
-                    "C" => 2,
-                    "D" => 3,
-                    "E" => 4,
-                    "H" => 5,
-                    "L" => 6,
-                    "F" => 7,
-                    _ => {
+                Err(_) => match parse_register(arg) {
+                    Some(reg) => reg,
+                    None => {
                         writeln!(ewriter, "INVALID ARGUMENT: unable to parse register")
                             .map_err(|err| EmulatorError::StdOut(err))?;
                         return Ok(());
@@ -1339,103 +2129,139 @@ async fn single_arg(
         SingleCmd::Peek => {
             let addr = match parse_u16(arg) {
                 Ok(addr) => addr,
-                Err(_) => {
-                    writeln!(ewriter, "INVALID ARGUMENT: unable to parse address")
-                        .map_err(|err| EmulatorError::StdOut(err))?;
-                    return Ok(());
-                }
-            };
-
-            let data: u8 = match addr {
-                0x0000..=0xFFBF => {
-                    STATE
-                        .get()
-                        .ok_or(EmulatorError::OnceEmpty)?
-                        .read()
-                        .await
-                        .mem[addr as usize]
-                }
-                0xFFC0..=0xFFFC => {
-                    match STATE
-                        .get()
-                        .ok_or(EmulatorError::OnceEmpty)?
-                        .read()
-                        .await
-                        .peripherals
-                        .get(&((addr - 0xFFC0) as u8))
-                    {
-                        Some(periph) => unsafe {
-                            if let Ok(stateful_read) =
-                                periph.lib.library.get::<StatefulReadFn>(b"stateful_read")
-                            {
-                                match periph.lib.state {
-                                    Some(state) => stateful_read(state, periph.n),
-                                    None => {
-                                        writeln!(ewriter, "PERIPHERAL ERROR: unable to call `stateful_read` (state was not initialized)").map_err(|err| EmulatorError::StdOut(err))?;
-                                        return Ok(());
-                                    }
-                                }
-                            } else if let Ok(read) = periph.lib.library.get::<ReadFn>(b"read") {
-                                read(periph.n)
-                            } else {
-                                writeln!(ewriter, "PERIPHERAL ERROR: `read` and `stateful_read` not present in peripheral (peripherals must implement one of these)").map_err(|err| EmulatorError::StdOut(err))?;
-                                return Ok(());
-                            }
-                        },
-                        None => 0x00,
-                    }
-                }
-                0xFFFD => {
-                    (STATE
-                        .get()
-                        .ok_or(EmulatorError::OnceEmpty)?
-                        .read()
-                        .await
-                        .timer
-                        >> 8) as u8
-                }
-                0xFFFE => {
-                    (STATE
-                        .get()
-                        .ok_or(EmulatorError::OnceEmpty)?
-                        .read()
-                        .await
-                        .timer
-                        & 0xFF) as u8
-                }
-                0xFFFF => STATE
+                Err(_) => match STATE
                     .get()
                     .ok_or(EmulatorError::OnceEmpty)?
                     .read()
                     .await
-                    .sreg
-                    .bits(),
+                    .symbols
+                    .variable(arg.trim_start_matches('$'))
+                {
+                    Some(addr) => addr,
+                    None => {
+                        writeln!(ewriter, "INVALID ARGUMENT: unable to parse address")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    }
+                },
             };
 
-            println!("{addr:#06X}: {data:#04X}");
+            let data = peek(addr).await?;
+
+            println!(
+                "{}: {data:#04X}",
+                STATE
+                    .get()
+                    .ok_or(EmulatorError::OnceEmpty)?
+                    .read()
+                    .await
+                    .memory_symbol(addr)
+            );
         }
-        SingleCmd::Run => {
-            let speed = match parse_u32(arg) {
-                Ok(speed) => speed,
+        SingleCmd::Reload => {
+            let port = match parse_u16(arg) {
+                Ok(port @ 0xFFC0..=0xFFFB) => (port - 0xFFC0) as u8,
+                Ok(_) => {
+                    writeln!(ewriter, "INVALID ARGUMENT: peripherals can only be connected to addresses between 0xFFC0-0xFFFB")
+                        .map_err(|err| EmulatorError::StdOut(err))?;
+                    return Ok(());
+                }
                 Err(_) => {
-                    writeln!(ewriter, "INVALID ARGUMENT: unable to parse speed")
+                    writeln!(ewriter, "INVALID ARGUMENT: unable to parse address {arg}")
                         .map_err(|err| EmulatorError::StdOut(err))?;
                     return Ok(());
                 }
             };
 
-            let duration = if speed == 0 {
-                Duration::ZERO
-            } else {
-                Duration::from_secs(1) / speed
-            };
-
-            STATE
+            let reloaded = STATE
                 .get()
                 .ok_or(EmulatorError::OnceEmpty)?
                 .write()
                 .await
-                .speed = Some((duration, speed));
+                .reload(port);
+            match reloaded {
+                Ok(Some(path)) => writeln!(writer, "INFO: reloaded {path}")
+                    .map_err(|err| EmulatorError::StdOut(err))?,
+                Ok(None) => writeln!(
+                    writer,
+                    "WARNING: no peripheral found at address {:#06X}",
+                    (port as u16) + 0xFFC0
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?,
+                Err(err) => writeln!(
+                    ewriter,
+                    "PERIPHERAL ERROR: {err} (the peripheral has been dropped)"
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?,
+            }
+        }
+        SingleCmd::Save => {
+            let path = parse_path(arg);
+            let snapshot = STATE
+                .get()
+                .ok_or(EmulatorError::OnceEmpty)?
+                .read()
+                .await
+                .snapshot();
+
+            let result = std::fs::File::create(&path)
+                .and_then(|file| snapshot.write(std::io::BufWriter::new(file)));
+            match result {
+                Ok(()) => writeln!(writer, "INFO: saved snapshot to {path}")
+                    .map_err(|err| EmulatorError::StdOut(err))?,
+                Err(err) => writeln!(ewriter, "IO ERROR: unable to write to {path}: {err}")
+                    .map_err(|err| EmulatorError::StdOut(err))?,
+            }
+        }
+        SingleCmd::Restore => {
+            let path = parse_path(arg);
+            let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+
+            if state.speed.is_some() {
+                writeln!(
+                    ewriter,
+                    "INVALID COMMAND: RESTORE can only be used if the CPU is stopped"
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?;
+                return Ok(());
+            }
+
+            let snapshot = std::fs::File::open(&path)
+                .map_err(|err| SnapshotError::from(err))
+                .and_then(|file| Snapshot::read(std::io::BufReader::new(file)));
+            match snapshot {
+                Ok(snapshot) => {
+                    let errors = state.restore_snapshot(snapshot);
+                    writeln!(writer, "INFO: restored snapshot from {path}")
+                        .map_err(|err| EmulatorError::StdOut(err))?;
+                    for err in errors {
+                        writeln!(ewriter, "PERIPHERAL ERROR: {err}")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                    }
+                }
+                Err(err) => writeln!(ewriter, "IO ERROR: unable to restore {path}: {err}")
+                    .map_err(|err| EmulatorError::StdOut(err))?,
+            }
+        }
+        SingleCmd::Break => {
+            let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+
+            let addr = match parse_u16(arg) {
+                Ok(addr) => addr,
+                Err(_) => match state.symbols.label(arg) {
+                    Some(addr) => addr,
+                    None => {
+                        writeln!(ewriter, "INVALID ARGUMENT: unable to parse address")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    }
+                },
+            };
+
+            if !state.breakpoints.insert(addr) {
+                writeln!(writer, "WARNING: breakpoint already set at {addr:#06X}")
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+            }
         }
     }
 
@@ -1445,7 +2271,7 @@ async fn single_arg(
 async fn double_arg(
     cmd: DoubleCmd,
     args: &[&str],
-    mut _writer: impl std::io::Write,
+    mut writer: impl std::io::Write,
     mut ewriter: impl std::io::Write,
 ) -> Result<(), EmulatorError> {
     let count = args.into_iter().filter(|arg| arg.trim().len() > 0).count();
@@ -1464,16 +2290,9 @@ async fn double_arg(
         DoubleCmd::Set => {
             let reg = match parse_u8(arg1.trim()) {
                 Ok(reg) => reg,
-                Err(_) => match arg1.trim() {
-                    "A" => 0,
-                    "B" => 1,
-                    "C" => 2,
-                    "D" => 3,
-                    "E" => 4,
-                    "H" => 5,
-                    "L" => 6,
-                    "F" => 7,
-                    _ => {
+                Err(_) => match parse_register(arg1.trim()) {
+                    Some(reg) => reg,
+                    None => {
                         writeln!(ewriter, "INVALID ARGUMENT: unable to parse register")
                             .map_err(|err| EmulatorError::StdOut(err))?;
                         return Ok(());
@@ -1531,7 +2350,7 @@ async fn double_arg(
                         .await
                         .mem[addr as usize] = value
                 }
-                0xFFC0..=0xFFFC => {
+                0xFFC0..=0xFFFB => {
                     match STATE
                         .get()
                         .ok_or(EmulatorError::OnceEmpty)?
@@ -1540,35 +2359,21 @@ async fn double_arg(
                         .peripherals
                         .get(&((addr - 0xFFC0) as u8))
                     {
-                        Some(periph) => unsafe {
-                            if let Ok(stateful_write) =
-                                periph.lib.library.get::<StatefulWriteFn>(b"stateful_write")
-                            {
-                                match periph.lib.state {
-                                    Some(state) => stateful_write(state, periph.n, value),
-                                    None => {
-                                        writeln!(ewriter, "PERIPHERAL ERROR: unable to call `stateful_read` (state was not initialized)").map_err(|err| EmulatorError::StdOut(err))?;
-                                        return Ok(());
-                                    }
-                                };
-                            } else if let Ok(write) = periph.lib.library.get::<WriteFn>(b"write") {
-                                write(periph.n, value);
-                            } else {
-                                writeln!(ewriter, "PERIPHERAL ERROR: `write` and `stateful_write` not present in peripheral (peripherals must implement one of these)").map_err(|err| EmulatorError::StdOut(err))?;
-                                return Ok(());
-                            }
-                        },
+                        Some(periph) => periph.write(value),
                         None => {}
                     }
                 }
-                0xFFFD => {
-                    let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
-                    state.timer = (state.timer & 0x00FF) | ((value as u16) << 8)
-                }
-                0xFFFE => {
-                    let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
-                    state.timer = (state.timer & 0xFF00) | ((value & 0xFF) as u16)
+                STATUS => {
+                    writeln!(ewriter, "INVALID ARGUMENT: the status port is read-only")
+                        .map_err(|err| EmulatorError::StdOut(err))?;
+                    return Ok(());
                 }
+                0xFFFD | 0xFFFE => STATE
+                    .get()
+                    .ok_or(EmulatorError::OnceEmpty)?
+                    .write()
+                    .await
+                    .write_bus(addr, value),
                 0xFFFF => {
                     STATE
                         .get()
@@ -1579,18 +2384,149 @@ async fn double_arg(
                 }
             };
         }
-    }
-
-    Ok(())
-}
+        DoubleCmd::Expect => {
+            let expected = match parse_u8(arg2) {
+                Ok(val) => val,
+                Err(_) => {
+                    writeln!(ewriter, "INVALID ARGUMENT: unable to parse value")
+                        .map_err(|err| EmulatorError::StdOut(err))?;
+                    return Ok(());
+                }
+            };
 
-async fn variadic_arg(
-    cmd: VariadicCmd,
+            let (name, actual) = match parse_register(arg1) {
+                Some(reg) => (
+                    format!("REGISTER {}", arg1.to_uppercase()),
+                    STATE
+                        .get()
+                        .ok_or(EmulatorError::OnceEmpty)?
+                        .read()
+                        .await
+                        .bank
+                        .get_reg(reg),
+                ),
+                None => {
+                    let state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.read().await;
+                    let addr = match parse_u16(arg1) {
+                        Ok(addr) => addr,
+                        Err(_) => match state.symbols.variable(arg1.trim_start_matches('$')) {
+                            Some(addr) => addr,
+                            None => {
+                                writeln!(
+                                    ewriter,
+                                    "INVALID ARGUMENT: unable to parse register or address"
+                                )
+                                .map_err(|err| EmulatorError::StdOut(err))?;
+                                return Ok(());
+                            }
+                        },
+                    };
+                    let name = state.memory_symbol(addr);
+                    drop(state);
+
+                    (name, peek(addr).await?)
+                }
+            };
+
+            if actual == expected {
+                writeln!(writer, "PASSED: {name} is {expected:#04X}")
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+            } else {
+                STATE
+                    .get()
+                    .ok_or(EmulatorError::OnceEmpty)?
+                    .write()
+                    .await
+                    .failures += 1;
+                writeln!(
+                    writer,
+                    "FAILED: expected {name} to be {expected:#04X}, found {actual:#04X}"
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn variadic_arg(
+    cmd: VariadicCmd,
     args: &[&str],
     mut writer: impl std::io::Write,
     mut ewriter: impl std::io::Write,
 ) -> Result<(), EmulatorError> {
     match cmd {
+        VariadicCmd::Run => {
+            let speed = match args {
+                [] => 0,
+                [speed] => match parse_u32(speed) {
+                    Ok(speed) => speed,
+                    Err(_) => {
+                        writeln!(ewriter, "INVALID ARGUMENT: unable to parse speed")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    }
+                },
+                [mode, cycles] if mode.eq_ignore_ascii_case("CYCLES") => {
+                    let cycles = match parse_u64(cycles) {
+                        Ok(cycles) => cycles,
+                        Err(_) => {
+                            writeln!(ewriter, "INVALID ARGUMENT: unable to parse cycles")
+                                .map_err(|err| EmulatorError::StdOut(err))?;
+                            return Ok(());
+                        }
+                    };
+
+                    let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+
+                    if state.speed.is_some() {
+                        writeln!(
+                            ewriter,
+                            "INVALID COMMAND: RUN CYCLES can only be used if the CPU is stopped"
+                        )
+                        .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    }
+
+                    let start = state.cycles;
+                    let halted = state.run_cycles(cycles);
+
+                    if halted {
+                        writeln!(writer, "INFO: halt detected after {} cycles", state.cycles)
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                    } else if let Some(trap) = state.trap.take() {
+                        writeln!(writer, "INFO: {}", state.describe(trap))
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                    } else {
+                        writeln!(writer, "INFO: ran {} cycles", state.cycles - start)
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                    }
+                    return Ok(());
+                }
+                _ => {
+                    writeln!(
+                        ewriter,
+                        "ARGUMENT ERROR: expected `RUN [speed]` or `RUN CYCLES <n>`"
+                    )
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+                    return Ok(());
+                }
+            };
+
+            let duration = if speed == 0 {
+                Duration::ZERO
+            } else {
+                Duration::from_secs(1) / speed
+            };
+
+            STATE
+                .get()
+                .ok_or(EmulatorError::OnceEmpty)?
+                .write()
+                .await
+                .speed = Some((duration, speed));
+        }
         VariadicCmd::Load => {
             if args.is_empty() {
                 writeln!(
@@ -1632,6 +2568,13 @@ async fn variadic_arg(
                                 "INVALID ARGUMENT: the stack pointer cannot be overwritten"
                             )
                             .map_err(|err| EmulatorError::StdOut(err))?;
+                        } else if p == STATUS {
+                            writeln!(
+                                ewriter,
+                                "INVALID ARGUMENT: the status port cannot be overwritten"
+                            )
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                            return Ok(());
                         } else {
                             ports.push((p - 0xFFC0) as u8)
                         }
@@ -1644,12 +2587,16 @@ async fn variadic_arg(
                 }
             }
 
-            STATE
+            let loaded = STATE
                 .get()
                 .ok_or(EmulatorError::OnceEmpty)?
                 .write()
                 .await
                 .load(path, ports);
+            if let Err(err) = loaded {
+                writeln!(ewriter, "PERIPHERAL ERROR: {err}")
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+            }
         }
         VariadicCmd::Drop => {
             if args.is_empty() {
@@ -1660,8 +2607,7 @@ async fn variadic_arg(
                     .ok_or(EmulatorError::OnceEmpty)?
                     .write()
                     .await
-                    .peripherals
-                    .clear();
+                    .drop_peripherals();
                 return Ok(());
             }
 
@@ -1685,6 +2631,13 @@ async fn variadic_arg(
                                 "INVALID ARGUMENT: the stack pointer cannot be overwritten"
                             )
                             .map_err(|err| EmulatorError::StdOut(err))?;
+                        } else if p == STATUS {
+                            writeln!(
+                                ewriter,
+                                "INVALID ARGUMENT: the status port cannot be overwritten"
+                            )
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                            return Ok(());
                         } else {
                             ports.push((p - 0xFFC0) as u8)
                         }
@@ -1700,7 +2653,7 @@ async fn variadic_arg(
             let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
 
             for port in ports {
-                if let None = state.peripherals.remove(&port) {
+                if !state.drop_port(port) {
                     writeln!(
                         writer,
                         "WARNING: no peripheral found at address {:#06X}",
@@ -1726,6 +2679,352 @@ async fn variadic_arg(
 
                     write!(writer, "\n{}\n", command.help())
                         .map_err(|err| EmulatorError::StdOut(err))?;
+                    if matches!(command, Command::Load) {
+                        writeln!(writer, "{}", peripheral::builtins())
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                    }
+                }
+            }
+        }
+        VariadicCmd::Watch => {
+            if args.is_empty() || args.len() > 2 {
+                writeln!(
+                    ewriter,
+                    "ARGUMENT ERROR: expected `1` or `2` arguments, found `{}`",
+                    args.len()
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?;
+                return Ok(());
+            }
+
+            let addr = match parse_u16(args[0]) {
+                Ok(addr) => addr,
+                Err(_) => match STATE
+                    .get()
+                    .ok_or(EmulatorError::OnceEmpty)?
+                    .read()
+                    .await
+                    .symbols
+                    .variable(args[0].trim_start_matches('$'))
+                {
+                    Some(addr) => addr,
+                    None => {
+                        writeln!(ewriter, "INVALID ARGUMENT: unable to parse address")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    }
+                },
+            };
+
+            let access = match args.get(1) {
+                Some(access) => match Access::from_str(access) {
+                    Ok(access) => access,
+                    Err(_) => {
+                        writeln!(
+                            ewriter,
+                            "INVALID ARGUMENT: expected one of `r`, `w`, or `rw`, found `{access}`"
+                        )
+                        .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    }
+                },
+                None => Access::R | Access::W,
+            };
+
+            STATE
+                .get()
+                .ok_or(EmulatorError::OnceEmpty)?
+                .write()
+                .await
+                .watchpoints
+                .insert(addr, access);
+        }
+        VariadicCmd::Disasm => {
+            if args.len() > 2 {
+                writeln!(
+                    ewriter,
+                    "ARGUMENT ERROR: expected at most `2` arguments, found `{}`",
+                    args.len()
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?;
+                return Ok(());
+            }
+
+            let state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.read().await;
+
+            let addr = match args.first() {
+                Some(arg) => match parse_u16(arg) {
+                    Ok(addr) => addr,
+                    Err(_) => match state.symbols.label(arg) {
+                        Some(addr) => addr,
+                        None => {
+                            writeln!(ewriter, "INVALID ARGUMENT: unable to parse address")
+                                .map_err(|err| EmulatorError::StdOut(err))?;
+                            return Ok(());
+                        }
+                    },
+                },
+                None => state.pc,
+            };
+
+            let count = match args.get(1) {
+                Some(arg) => match parse_u16(arg) {
+                    Ok(count) => count,
+                    Err(_) => {
+                        writeln!(ewriter, "INVALID ARGUMENT: unable to parse count")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    }
+                },
+                None => 8,
+            };
+
+            for inst in disassembler::disassemble_range(&state.program, addr, count as usize) {
+                if let Some(label) = state.symbols.label_at(inst.address) {
+                    writeln!(writer, "{label}:").map_err(|err| EmulatorError::StdOut(err))?;
+                }
+
+                let marker = if inst.address == state.pc { "->" } else { "  " };
+                writeln!(
+                    writer,
+                    "{marker}  {}",
+                    disassembler::format_line(&inst, &state.symbols)
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?;
+            }
+        }
+        VariadicCmd::Profile => {
+            let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+            let mode = args.first().map(|mode| mode.to_uppercase());
+
+            match (mode.as_deref(), &args[args.len().min(1)..]) {
+                (Some("START"), []) => state.profile = Some(Profile::new()),
+                (Some("STOP"), []) => match state.profile {
+                    Some(ref mut profile) => profile.running = false,
+                    None => writeln!(writer, "WARNING: the profiler is not running")
+                        .map_err(|err| EmulatorError::StdOut(err))?,
+                },
+                (Some("REPORT"), rest) if rest.len() <= 2 => {
+                    let Some(ref profile) = state.profile else {
+                        writeln!(ewriter, "INVALID COMMAND: the profiler has not been started")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    };
+
+                    let (top, path) = match rest {
+                        [] => (10, None),
+                        [arg] => match parse_u64(arg) {
+                            Ok(top) => (top, None),
+                            Err(_) => (10, Some(parse_path(arg))),
+                        },
+                        [top, path] => match parse_u64(top) {
+                            Ok(top) => (top, Some(parse_path(path))),
+                            Err(_) => {
+                                writeln!(ewriter, "INVALID ARGUMENT: unable to parse count")
+                                    .map_err(|err| EmulatorError::StdOut(err))?;
+                                return Ok(());
+                            }
+                        },
+                        _ => unreachable!(),
+                    };
+
+                    let report = profile.report(top as usize, &state.program, &state.symbols);
+                    match path {
+                        Some(path) => match std::fs::write(&path, report) {
+                            Ok(()) => writeln!(writer, "INFO: saved profile report to {path}")
+                                .map_err(|err| EmulatorError::StdOut(err))?,
+                            Err(err) => {
+                                writeln!(ewriter, "IO ERROR: unable to write to {path}: {err}")
+                                    .map_err(|err| EmulatorError::StdOut(err))?
+                            }
+                        },
+                        None => write!(writer, "{report}").map_err(|err| EmulatorError::StdOut(err))?,
+                    }
+                }
+                (Some("STACKS"), [path]) => {
+                    let Some(ref profile) = state.profile else {
+                        writeln!(ewriter, "INVALID COMMAND: the profiler has not been started")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    };
+
+                    let path = parse_path(path);
+                    match std::fs::write(&path, profile.collapsed()) {
+                        Ok(()) => writeln!(writer, "INFO: saved call stacks to {path}")
+                            .map_err(|err| EmulatorError::StdOut(err))?,
+                        Err(err) => writeln!(ewriter, "IO ERROR: unable to write to {path}: {err}")
+                            .map_err(|err| EmulatorError::StdOut(err))?,
+                    }
+                }
+                _ => writeln!(
+                    ewriter,
+                    "ARGUMENT ERROR: expected `PROFILE START`, `PROFILE STOP`, `PROFILE REPORT [n] [file]`, or `PROFILE STACKS <file>`"
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?,
+            }
+        }
+        VariadicCmd::Back => {
+            let (count, cycles) = match args {
+                [] => (1, false),
+                [count] => match parse_u64(count) {
+                    Ok(count) => (count, false),
+                    Err(_) => {
+                        writeln!(ewriter, "INVALID ARGUMENT: unable to parse count")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    }
+                },
+                [mode, count] if mode.eq_ignore_ascii_case("CYCLES") => match parse_u64(count) {
+                    Ok(count) => (count, true),
+                    Err(_) => {
+                        writeln!(ewriter, "INVALID ARGUMENT: unable to parse cycles")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    }
+                },
+                _ => {
+                    writeln!(
+                        ewriter,
+                        "ARGUMENT ERROR: expected `BACK [n]` or `BACK CYCLES <n>`"
+                    )
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+                    return Ok(());
+                }
+            };
+
+            let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+
+            if state.speed.is_some() {
+                writeln!(
+                    ewriter,
+                    "INVALID COMMAND: BACK can only be used if the CPU is stopped"
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?;
+                return Ok(());
+            }
+
+            let start = state.cycles;
+            for _ in 0..count {
+                let rewound = if cycles {
+                    state.rewind()
+                } else {
+                    state.rewind_instruction()
+                };
+
+                if !rewound {
+                    writeln!(writer, "WARNING: reached the end of the recorded history")
+                        .map_err(|err| EmulatorError::StdOut(err))?;
+                    break;
+                }
+            }
+
+            writeln!(writer, "INFO: rewound {} cycles", start - state.cycles)
+                .map_err(|err| EmulatorError::StdOut(err))?;
+            // The program counter only points to an instruction between instructions
+            if state.ctrl.clock == 0 {
+                let inst = Disassembled::decode(&state.program, state.pc);
+                writeln!(writer, "{}", disassembler::format_line(&inst, &state.symbols))
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+            }
+        }
+        VariadicCmd::Trace => {
+            let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+
+            match args {
+                [mode, path] if mode.eq_ignore_ascii_case("START") => {
+                    let path = parse_path(path);
+                    let trace = match Trace::create(&path) {
+                        Ok(trace) => trace,
+                        Err(err) => {
+                            writeln!(ewriter, "IO ERROR: unable to create {path}: {err}")
+                                .map_err(|err| EmulatorError::StdOut(err))?;
+                            return Ok(());
+                        }
+                    };
+
+                    if let Some(prev) = state.trace.replace(trace) {
+                        writeln!(writer, "WARNING: stopping previous trace")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        if let Err(err) = prev.finish() {
+                            writeln!(ewriter, "IO ERROR: unable to write to trace: {err}")
+                                .map_err(|err| EmulatorError::StdOut(err))?;
+                        }
+                    }
+                }
+                [mode] if mode.eq_ignore_ascii_case("STOP") => match state.trace.take() {
+                    Some(trace) => {
+                        if let Err(err) = trace.finish() {
+                            writeln!(ewriter, "IO ERROR: unable to write to trace: {err}")
+                                .map_err(|err| EmulatorError::StdOut(err))?;
+                        }
+                    }
+                    None => writeln!(writer, "WARNING: no trace is being recorded")
+                        .map_err(|err| EmulatorError::StdOut(err))?,
+                },
+                _ => writeln!(
+                    ewriter,
+                    "ARGUMENT ERROR: expected `TRACE START <file>` or `TRACE STOP`"
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?,
+            }
+        }
+        VariadicCmd::Screen => {
+            if args.len() > 1 {
+                writeln!(
+                    ewriter,
+                    "ARGUMENT ERROR: expected at most `1` argument, found `{}`",
+                    args.len()
+                )
+                .map_err(|err| EmulatorError::StdOut(err))?;
+                return Ok(());
+            }
+
+            let state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.read().await;
+
+            match args.first() {
+                Some(path) => {
+                    let result = std::fs::File::create(path)
+                        .and_then(|file| state.text_buffer.snapshot(std::io::BufWriter::new(file)));
+                    match result {
+                        Ok(()) => writeln!(writer, "INFO: saved text buffer to {path}")
+                            .map_err(|err| EmulatorError::StdOut(err))?,
+                        Err(err) => writeln!(ewriter, "IO ERROR: unable to write to {path}: {err}")
+                            .map_err(|err| EmulatorError::StdOut(err))?,
+                    }
+                }
+                None => write!(writer, "{}", state.text_buffer.render_ansi())
+                    .map_err(|err| EmulatorError::StdOut(err))?,
+            }
+        }
+        VariadicCmd::Delete => {
+            let mut state = STATE.get().ok_or(EmulatorError::OnceEmpty)?.write().await;
+
+            if args.is_empty() {
+                writeln!(writer, "INFO: removing all breakpoints and watchpoints")
+                    .map_err(|err| EmulatorError::StdOut(err))?;
+                state.breakpoints.clear();
+                state.watchpoints.clear();
+                return Ok(());
+            }
+
+            for arg in args {
+                let addr = match parse_u16(arg) {
+                    Ok(addr) => addr,
+                    Err(_) => {
+                        writeln!(ewriter, "INVALID ARGUMENT: unable to parse address {arg}")
+                            .map_err(|err| EmulatorError::StdOut(err))?;
+                        return Ok(());
+                    }
+                };
+
+                let breakpoint = state.breakpoints.remove(&addr);
+                let watchpoint = state.watchpoints.remove(&addr).is_some();
+                if !breakpoint && !watchpoint {
+                    writeln!(
+                        writer,
+                        "WARNING: no breakpoint or watchpoint found at address {addr:#06X}"
+                    )
+                    .map_err(|err| EmulatorError::StdOut(err))?;
                 }
             }
         }
@@ -1734,6 +3033,70 @@ async fn variadic_arg(
     Ok(())
 }
 
+/// Reads the value at the given memory address the same way `PEEK` does.
+async fn peek(addr: u16) -> Result<u8, EmulatorError> {
+    let data: u8 = match addr {
+        0x0000..=0xFFBF => {
+            STATE
+                .get()
+                .ok_or(EmulatorError::OnceEmpty)?
+                .read()
+                .await
+                .mem[addr as usize]
+        }
+        0xFFC0..=0xFFFB => {
+            match STATE
+                .get()
+                .ok_or(EmulatorError::OnceEmpty)?
+                .read()
+                .await
+                .peripherals
+                .get(&((addr - 0xFFC0) as u8))
+            {
+                Some(periph) => periph.read(),
+                None => 0x00,
+            }
+        }
+        STATUS => STATE
+            .get()
+            .ok_or(EmulatorError::OnceEmpty)?
+            .read()
+            .await
+            .status()
+            .bits(),
+        0xFFFD | 0xFFFE => STATE
+            .get()
+            .ok_or(EmulatorError::OnceEmpty)?
+            .read()
+            .await
+            .read_bus(addr),
+        0xFFFF => STATE
+            .get()
+            .ok_or(EmulatorError::OnceEmpty)?
+            .read()
+            .await
+            .sreg
+            .bits(),
+    };
+
+    Ok(data)
+}
+
+/// Maps a register name to its index in the register bank.
+fn parse_register(name: &str) -> Option<u8> {
+    match name.to_uppercase().as_str() {
+        "A" => Some(0),
+        "B" => Some(1),
+        "C" => Some(2),
+        "D" => Some(3),
+        "E" => Some(4),
+        "F" => Some(5),
+        "H" => Some(6),
+        "L" => Some(7),
+        _ => None,
+    }
+}
+
 fn parse_u8(int: &str) -> Result<u8, <u8 as FromStr>::Err> {
     if int.starts_with("0b") {
         u8::from_str_radix(&int[2..], 2)
@@ -1770,11 +3133,49 @@ fn parse_u32(int: &str) -> Result<u32, <u32 as FromStr>::Err> {
     }
 }
 
+fn parse_u64(int: &str) -> Result<u64, <u64 as FromStr>::Err> {
+    if int.starts_with("0b") {
+        u64::from_str_radix(&int[2..], 2)
+    } else if int.starts_with("0o") {
+        u64::from_str_radix(&int[2..], 8)
+    } else if int.starts_with("0x") {
+        u64::from_str_radix(&int[2..], 16)
+    } else {
+        u64::from_str_radix(int, 10)
+    }
+}
+
 fn parse_path(source: &str) -> String {
     source.trim_matches('"').trim_matches('\'').to_owned()
 }
 
+/// Creates the emulator state, connecting the peripherals listed in `machine`.
+///
+/// If the REPL is going to read commands from stdin,
+/// it's reserved before the peripherals are loaded so they don't start reading from it too.
+fn init_state(
+    program: Box<[u8]>,
+    symbols: Symbols,
+    text_buffer: TextBuffer,
+    machine: Option<&Machine>,
+    repl: bool,
+) -> Result<State, EmulatorError> {
+    if repl {
+        peripheral::reserve_stdin();
+    }
+
+    let mut state = State::init(program, symbols, text_buffer);
+    if let Some(machine) = machine {
+        state
+            .load_machine(machine)
+            .map_err(|err| EmulatorError::MachinePeripheral(err))?;
+    }
+
+    Ok(state)
+}
+
 fn spawn_stdin_channel() -> Receiver<String> {
+    peripheral::reserve_stdin();
     let (tx, rx) = channel::unbounded();
     async_std::task::spawn(watch_input(tx));
     rx
@@ -1789,3 +3190,68 @@ async fn watch_input(tx: Sender<String>) {
         tx.send(buffer).await.unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{generator, lex, parse},
+        Verbosity, VERBOSITY,
+    };
+
+    /// Runs a REPL command against the shared state, returning what it printed.
+    fn command(input: &str) -> String {
+        let mut output = Vec::new();
+        async_std::task::block_on(handle_input(input.to_owned(), &mut output, std::io::sink()))
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn breakpoints() {
+        VERBOSITY.get_or_init(|| Verbosity::Error);
+        let source = "mv A, 5\nst [0xE000], A\nmv B, 7\nhalt\n";
+        let tokens = lex::lex_string(None, source).unwrap();
+        let assembled = generator::generate(parse::parse(tokens).unwrap()).unwrap();
+        let program = assembled.program.to_vec().into_boxed_slice();
+        // The REPL commands share this state, so every command is tested here
+        STATE
+            .set(RwLock::new(State::init(
+                program,
+                Symbols::new(),
+                TextBuffer::headless(),
+            )))
+            .ok()
+            .unwrap();
+        let state = || async_std::task::block_on(STATE.get().unwrap().read());
+
+        command("BREAK 0x0000");
+        command("BREAK 0x0005");
+        command("WATCH 0xE000 w");
+        let list = command("LIST");
+        assert!(list.contains("BREAKPOINT: 0x0000"));
+        assert!(list.contains("BREAKPOINT: 0x0005"));
+        assert!(list.contains("WATCHPOINT: 0xE000"));
+
+        // A breakpoint at the entry point stops the CPU before the first instruction
+        assert!(command("RUN CYCLES 100").contains("breakpoint hit at 0x0000"));
+        assert_eq!((state().pc, state().cycles), (0x0000, 0));
+
+        assert!(command("RUN CYCLES 100").contains("watchpoint hit (write to 0xE000)"));
+        assert_eq!(state().bank.a, 5);
+
+        assert!(command("RUN CYCLES 100").contains("breakpoint hit at 0x0005"));
+        assert_eq!((state().pc, state().bank.b), (0x0005, 0));
+
+        command("DELETE 0x0005");
+        let list = command("LIST");
+        assert!(list.contains("BREAKPOINT: 0x0000"));
+        assert!(!list.contains("BREAKPOINT: 0x0005"));
+
+        command("DELETE");
+        assert!(command("LIST").contains("no breakpoints or watchpoints set"));
+
+        assert!(command("RUN CYCLES 100").contains("halt detected"));
+        assert_eq!(state().bank.b, 7);
+    }
+}
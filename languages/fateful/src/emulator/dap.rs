@@ -0,0 +1,847 @@
+//! A Debug Adapter Protocol server, allowing Fate programs to be debugged from VS Code and other editors.
+//!
+//! The adapter communicates over `stdin` and `stdout`, assembling the program named by the `launch` request
+//! and using its source lines to map breakpoints and stack frames back to the source.
+//! Calls are tracked through the `call` and `ret` macros, so stepping over a `call` runs the entire function.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, TryRecvError},
+    thread,
+};
+
+use clio::Input;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use super::{display::TextBuffer, parse_u16, parse_u8, SReg, State};
+use crate::{
+    assembler::{generator, lex, parse},
+    symbols::Symbols,
+};
+
+/// Number of instructions run between checks for new requests while the program is running.
+const CHUNK: usize = 1000;
+/// Variables reference of the register scope.
+const REGISTERS: i64 = 1;
+/// Variables reference of the flags in SREG.
+const FLAGS: i64 = 2;
+/// Variables reference of the data segment variables.
+const VARIABLES: i64 = 3;
+
+#[derive(Debug, Error)]
+pub enum DapError {
+    #[error("unable to communicate with the debugger client")]
+    IO(#[from] io::Error),
+    #[error("malformed message from the debugger client")]
+    Json(#[from] serde_json::Error),
+    #[error("message from the debugger client is missing its `Content-Length` header")]
+    Header,
+}
+
+/// How execution continues until the program stops again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Continue,
+    /// Stops on the next source line, stepping over any calls.
+    Over {
+        depth: usize,
+        location: Option<usize>,
+    },
+    /// Stops on the next source line, including inside calls.
+    In {
+        depth: usize,
+        location: Option<usize>,
+    },
+    /// Stops once the current call returns.
+    Out {
+        depth: usize,
+    },
+}
+
+/// The program being debugged.
+struct Session {
+    state: State,
+    /// Index into `locations` of the source line of each instruction.
+    lines: HashMap<u16, usize>,
+    /// Each unique source location, with canonicalized paths.
+    locations: Vec<(PathBuf, usize)>,
+    /// The address of each `call` the program is currently inside, from the outermost.
+    calls: Vec<u16>,
+    stop_on_entry: bool,
+    running: Option<Step>,
+}
+
+impl Session {
+    fn location(&self, addr: u16) -> Option<usize> {
+        self.lines.get(&addr).copied()
+    }
+
+    /// Finds the first address assembled from the given source line.
+    fn address(&self, path: &Path, line: usize) -> Option<u16> {
+        self.lines
+            .iter()
+            .filter(|(_, location)| {
+                let (file, l) = &self.locations[**location];
+                file == path && *l == line
+            })
+            .map(|(addr, _)| *addr)
+            .min()
+    }
+
+    /// Updates the call stack once the instruction at `pc` has finished.
+    fn track(&mut self, pc: u16) {
+        if let Some((range, name)) = self.state.symbols.expansion(pc) {
+            // A call or return only happens once the CPU jumps out of the expansion
+            if !range.contains(&self.state.pc) {
+                match name {
+                    "call" => self.calls.push(range.start),
+                    "ret" => {
+                        self.calls.pop();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Checks whether a step has finished.
+    fn stepped(&self, step: Step) -> bool {
+        let location = self.location(self.state.pc);
+        let depth = self.calls.len();
+
+        match step {
+            Step::Continue => false,
+            Step::Over {
+                depth: start,
+                location: from,
+            } => location.is_some() && (depth < start || (depth == start && location != from)),
+            Step::In {
+                depth: start,
+                location: from,
+            } => location.is_some() && (depth != start || location != from),
+            Step::Out { depth: start } => depth < start,
+        }
+    }
+
+    fn frame(&self, id: usize, addr: u16) -> Value {
+        let name = self
+            .state
+            .symbols
+            .function(addr)
+            .map(|name| name.to_owned())
+            .unwrap_or_else(|| format!("{addr:#06X}"));
+
+        let mut frame = json!({
+            "id": id,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format!("{addr:#06X}"),
+        });
+        if let Some(location) = self.location(addr) {
+            let (path, line) = &self.locations[location];
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+            frame["source"] = json!({
+                "name": path.file_name().map(|name| name.to_string_lossy()),
+                "path": path,
+            });
+        }
+
+        frame
+    }
+}
+
+struct Adapter<W> {
+    writer: W,
+    seq: i64,
+    session: Option<Session>,
+    /// The source lines of the breakpoints requested in each file.
+    breakpoints: HashMap<PathBuf, Vec<usize>>,
+}
+
+impl<W: Write> Adapter<W> {
+    fn new(writer: W) -> Adapter<W> {
+        Adapter {
+            writer,
+            seq: 1,
+            session: None,
+            breakpoints: HashMap::new(),
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<(), DapError> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        write_message(&mut self.writer, &message)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Result<(), DapError> {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }))
+    }
+
+    fn stopped(&mut self, reason: &str) -> Result<(), DapError> {
+        self.event(
+            "stopped",
+            json!({
+                "reason": reason,
+                "threadId": 1,
+                "allThreadsStopped": true,
+            }),
+        )
+    }
+
+    /// Handles a single request, returning `false` once the client disconnects.
+    fn handle(&mut self, request: Value) -> Result<bool, DapError> {
+        let command = request["command"].as_str().unwrap_or_default().to_owned();
+        let args = &request["arguments"];
+
+        let result = match command.as_str() {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsSetVariable": true,
+                "supportsReadMemoryRequest": true,
+                "supportsWriteMemoryRequest": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "configurationDone" | "threads" | "stackTrace" | "scopes" | "variables"
+            | "setVariable" | "readMemory" | "writeMemory" | "continue" | "next" | "stepIn"
+            | "stepOut" | "pause" => match self.session {
+                Some(ref mut session) => session_request(session, &command, args),
+                None => Err("no program has been launched".to_owned()),
+            },
+            "disconnect" | "terminate" => Ok(json!({})),
+            command => Err(format!("unsupported request `{command}`")),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        let success = response["success"] == json!(true);
+        self.send(response)?;
+
+        if !success {
+            return Ok(true);
+        }
+
+        // Events that have to be sent after the response
+        match command.as_str() {
+            // Breakpoints can only be resolved once the program is assembled
+            "launch" => self.event("initialized", json!({}))?,
+            "configurationDone" => {
+                if let Some(session) = &mut self.session {
+                    if session.stop_on_entry {
+                        self.stopped("entry")?;
+                    } else {
+                        session.running = Some(Step::Continue);
+                    }
+                }
+            }
+            "pause" => self.stopped("pause")?,
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                if let Some(Session { state, .. }) = &self.session {
+                    if state.sreg.contains(SReg::H) {
+                        self.event("terminated", json!({}))?;
+                    }
+                }
+            }
+            "terminate" => self.event("terminated", json!({}))?,
+            "disconnect" => return Ok(false),
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or("`program` must be the path to the source file to debug")?;
+
+        let input =
+            Input::new(program).map_err(|err| format!("unable to open {program}: {err}"))?;
+        let generated = lex::lex(input)
+            .and_then(parse::parse)
+            .and_then(generator::generate)
+            .map_err(|errors| {
+                errors
+                    .iter()
+                    .map(|err| err.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            })?;
+
+        let text_buffer = match args["headless"].as_bool().unwrap_or(false) {
+            true => TextBuffer::headless(),
+            false => TextBuffer::spawn(),
+        };
+
+        let (lines, locations) = locate(&generated.symbols);
+        let session = Session {
+            state: State::init(generated.program.into(), generated.symbols, text_buffer),
+            lines,
+            locations,
+            calls: Vec::new(),
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+            running: None,
+        };
+        self.session = Some(session);
+
+        // Resolve any breakpoints set before the launch
+        for (path, lines) in self.breakpoints.clone() {
+            self.resolve(&path, &lines);
+        }
+
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or("breakpoints must be set in a source file")?;
+        let path = canonicalize(path);
+
+        let lines: Vec<usize> = args["breakpoints"]
+            .as_array()
+            .map(|breakpoints| {
+                breakpoints
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                    .map(|line| line as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let breakpoints = self.resolve(&path, &lines);
+        self.breakpoints.insert(path, lines);
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// Replaces the breakpoints in `path` with breakpoints on each of `lines`.
+    fn resolve(&mut self, path: &Path, lines: &[usize]) -> Vec<Value> {
+        let Some(session) = &mut self.session else {
+            return lines
+                .iter()
+                .map(|line| json!({ "verified": false, "line": line }))
+                .collect();
+        };
+
+        if let Some(previous) = self.breakpoints.get(path) {
+            for line in previous {
+                if let Some(addr) = session.address(path, *line) {
+                    session.state.breakpoints.remove(&addr);
+                }
+            }
+        }
+
+        lines
+            .iter()
+            .map(|line| match session.address(path, *line) {
+                Some(addr) => {
+                    session.state.breakpoints.insert(addr);
+                    json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": format!("{addr:#06X}"),
+                    })
+                }
+                None => json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "no instructions were assembled from this line",
+                }),
+            })
+            .collect()
+    }
+
+    /// Runs the program for a while if it's running, reporting if it stops.
+    fn run(&mut self) -> Result<(), DapError> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        let Some(step) = session.running else {
+            return Ok(());
+        };
+
+        for _ in 0..CHUNK {
+            let pc = session.state.pc;
+            let halted = session.state.step_instruction();
+            session.track(pc);
+
+            let reason = if halted {
+                Some("halt")
+            } else if session.state.trap.take().is_some() {
+                Some("breakpoint")
+            } else if session.stepped(step) {
+                Some("step")
+            } else {
+                None
+            };
+
+            if let Some(reason) = reason {
+                session.running = None;
+                if halted {
+                    let output = format!(
+                        "INFO: halt detected after {} cycles\n",
+                        session.state.cycles
+                    );
+                    self.event("output", json!({ "category": "console", "output": output }))?;
+                }
+                return self.stopped(reason);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Handles the requests that need a launched program.
+fn session_request(session: &mut Session, command: &str, args: &Value) -> Result<Value, String> {
+    let state = &mut session.state;
+
+    match command {
+        "configurationDone" => Ok(json!({})),
+        "threads" => Ok(json!({ "threads": [{ "id": 1, "name": "main" }] })),
+        "stackTrace" => {
+            let mut frames = vec![session.frame(0, session.state.pc)];
+            for (id, call) in session.calls.iter().rev().enumerate() {
+                frames.push(session.frame(id + 1, *call));
+            }
+
+            Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+        }
+        "scopes" => {
+            let mut scopes = vec![json!({
+                "name": "Registers",
+                "variablesReference": REGISTERS,
+                "expensive": false,
+            })];
+            if state.symbols.variables().next().is_some() {
+                scopes.push(json!({
+                    "name": "Variables",
+                    "variablesReference": VARIABLES,
+                    "expensive": false,
+                }));
+            }
+
+            Ok(json!({ "scopes": scopes }))
+        }
+        "variables" => {
+            let variables = match args["variablesReference"].as_i64() {
+                Some(REGISTERS) => {
+                    let mut registers: Vec<Value> = ["A", "B", "C", "D", "E", "F", "H", "L"]
+                        .into_iter()
+                        .enumerate()
+                        .map(|(n, name)| register(name, state.bank.get_reg(n as u8)))
+                        .collect();
+
+                    let mut sreg = register("SREG", state.sreg.bits());
+                    sreg["variablesReference"] = json!(FLAGS);
+                    registers.push(sreg);
+                    registers.push(json!({
+                        "name": "SP",
+                        "value": format!("{:#06X}", state.sp),
+                        "variablesReference": 0,
+                        "memoryReference": format!("{:#06X}", state.sp),
+                    }));
+                    registers.push(json!({
+                        "name": "PC",
+                        "value": format!("{:#06X}", state.pc),
+                        "variablesReference": 0,
+                    }));
+
+                    registers
+                }
+                Some(FLAGS) => SReg::all()
+                    .iter_names()
+                    .map(|(name, flag)| {
+                        json!({
+                            "name": name,
+                            "value": (state.sreg.contains(flag) as u8).to_string(),
+                            "variablesReference": 0,
+                        })
+                    })
+                    .collect(),
+                Some(VARIABLES) => state
+                    .symbols
+                    .variables()
+                    .map(|(addr, name)| {
+                        json!({
+                            "name": format!("${name}"),
+                            "value": format!("{:#04X}", state.mem[addr as usize]),
+                            "variablesReference": 0,
+                            "memoryReference": format!("{addr:#06X}"),
+                        })
+                    })
+                    .collect(),
+                _ => return Err("unknown variables reference".to_owned()),
+            };
+
+            Ok(json!({ "variables": variables }))
+        }
+        "setVariable" => {
+            let name = args["name"].as_str().unwrap_or_default();
+            let value = args["value"].as_str().unwrap_or_default().trim();
+            let invalid = || format!("unable to parse `{value}`");
+
+            let value = match (args["variablesReference"].as_i64(), name) {
+                (Some(REGISTERS), "SP" | "PC") => {
+                    let value = parse_u16(value).map_err(|_| invalid())?;
+                    match name {
+                        "SP" => state.sp = value,
+                        _ => state.pc = value,
+                    }
+                    format!("{value:#06X}")
+                }
+                (Some(REGISTERS), "SREG") => {
+                    let value = parse_u8(value).map_err(|_| invalid())?;
+                    state.sreg = SReg::from_bits_retain(value);
+                    format!("{value:#04X}")
+                }
+                (Some(REGISTERS), name) => {
+                    let n = super::parse_register(name).ok_or("unknown register")?;
+                    let value = parse_u8(value).map_err(|_| invalid())?;
+                    state.bank.set_reg(n, value);
+                    format!("{value:#04X}")
+                }
+                (Some(VARIABLES), name) => {
+                    let addr = state
+                        .symbols
+                        .variable(name.trim_start_matches('$'))
+                        .ok_or("unknown variable")?;
+                    let value = parse_u8(value).map_err(|_| invalid())?;
+                    state.mem[addr as usize] = value;
+                    format!("{value:#04X}")
+                }
+                _ => return Err("variable can't be modified".to_owned()),
+            };
+
+            Ok(json!({ "value": value }))
+        }
+        "readMemory" => {
+            let (addr, count) = memory_range(args)?;
+
+            // Reading from peripherals can have side effects, so they're left unreadable
+            let mut data = Vec::with_capacity(count);
+            for addr in (addr..0xFFC0).take(count) {
+                data.push(state.read_bus(addr as u16));
+            }
+
+            Ok(json!({
+                "address": format!("{addr:#06X}"),
+                "data": base64(&data),
+                "unreadableBytes": count - data.len(),
+            }))
+        }
+        "writeMemory" => {
+            let (addr, _) = memory_range(args)?;
+            let data = args["data"]
+                .as_str()
+                .and_then(unbase64)
+                .ok_or("data must be base64 encoded")?;
+
+            let mut written = 0;
+            for (addr, byte) in (addr..0x10000).zip(data) {
+                state.write_bus(addr as u16, byte);
+                written += 1;
+            }
+
+            Ok(json!({ "bytesWritten": written }))
+        }
+        "continue" | "next" | "stepIn" | "stepOut" => {
+            // A halted program can't be resumed, so the session is terminated instead
+            if state.sreg.contains(SReg::H) {
+                return Ok(json!({ "allThreadsContinued": true }));
+            }
+
+            let depth = session.calls.len();
+            let location = session.location(session.state.pc);
+            session.running = Some(match command {
+                "next" => Step::Over { depth, location },
+                "stepIn" => Step::In { depth, location },
+                "stepOut" => Step::Out { depth },
+                _ => Step::Continue,
+            });
+
+            Ok(json!({ "allThreadsContinued": true }))
+        }
+        "pause" => {
+            session.running = None;
+            Ok(json!({}))
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Runs the debug adapter over `stdin` and `stdout` until the client disconnects.
+pub fn debug_adapter() -> Result<(), DapError> {
+    // Diagnostics are shown in the debug console, which doesn't need colors
+    colored::control::set_override(false);
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        loop {
+            match read_message(&mut stdin) {
+                Ok(Some(message)) => {
+                    if tx.send(Ok(message)).is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(err) => {
+                    let _ = tx.send(Err(err));
+                    return;
+                }
+            }
+        }
+    });
+
+    let mut adapter = Adapter::new(io::stdout());
+    loop {
+        let running = matches!(&adapter.session, Some(session) if session.running.is_some());
+        // Only wait for the next request if the program isn't running
+        let request = match running {
+            true => match rx.try_recv() {
+                Ok(request) => Some(request),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            },
+            false => match rx.recv() {
+                Ok(request) => Some(request),
+                Err(_) => return Ok(()),
+            },
+        };
+
+        if let Some(request) = request {
+            if !adapter.handle(request?)? {
+                return Ok(());
+            }
+        }
+
+        adapter.run()?;
+    }
+}
+
+/// Maps each address in the symbol table to its source location.
+fn locate(symbols: &Symbols) -> (HashMap<u16, usize>, Vec<(PathBuf, usize)>) {
+    let mut lines = HashMap::new();
+    let mut locations = Vec::new();
+    let mut indices: HashMap<(&str, usize), usize> = HashMap::new();
+
+    for (addr, file, line) in symbols.lines() {
+        let index = *indices.entry((file, line)).or_insert_with(|| {
+            locations.push((canonicalize(file), line));
+            locations.len() - 1
+        });
+        lines.insert(addr, index);
+    }
+
+    (lines, locations)
+}
+
+/// Canonicalizes a path so that paths from the client and the assembler can be compared,
+/// falling back to the path as-is if it doesn't exist.
+fn canonicalize(path: &str) -> PathBuf {
+    Path::new(path)
+        .canonicalize()
+        .unwrap_or_else(|_| PathBuf::from(path))
+}
+
+fn register(name: &str, value: u8) -> Value {
+    json!({
+        "name": name,
+        "value": format!("{value:#04X}"),
+        "variablesReference": 0,
+    })
+}
+
+/// Parses the `memoryReference`, `offset`, and `count` arguments shared by the memory requests.
+fn memory_range(args: &Value) -> Result<(u32, usize), String> {
+    let reference = args["memoryReference"].as_str().unwrap_or_default();
+    let addr =
+        parse_u16(reference).map_err(|_| format!("invalid memory reference `{reference}`"))?;
+    let offset = args["offset"].as_i64().unwrap_or(0);
+    let count = args["count"].as_u64().unwrap_or(0) as usize;
+
+    match u32::try_from(addr as i64 + offset) {
+        Ok(addr) if addr <= 0xFFFF => Ok((addr, count)),
+        _ => Err("address is out of range".to_owned()),
+    }
+}
+
+fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<Value>, DapError> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+
+    let mut body = vec![0; length.ok_or(DapError::Header)?];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Value) -> Result<(), DapError> {
+    let body = serde_json::to_string(message)?;
+    write!(writer, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(bits >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+fn unbase64(encoded: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+
+    for c in encoded.bytes().filter(|c| *c != b'=') {
+        let value = BASE64.iter().position(|b| *b == c)? as u32;
+        bits = bits << 6 | value;
+        count += 1;
+
+        if count == 4 {
+            data.extend_from_slice(&bits.to_be_bytes()[1..]);
+            bits = 0;
+            count = 0;
+        }
+    }
+
+    match count {
+        0 => {}
+        2 => data.push((bits >> 4) as u8),
+        3 => data.extend_from_slice(&((bits >> 2) as u16).to_be_bytes()),
+        _ => return None,
+    }
+
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Verbosity, VERBOSITY};
+
+    /// Runs the adapter over a list of requests, returning every message it sent.
+    fn exchange(requests: &[Value]) -> Vec<Value> {
+        let mut output = Vec::new();
+        let mut adapter = Adapter::new(&mut output);
+
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            assert!(adapter.handle(request).unwrap());
+
+            while matches!(&adapter.session, Some(session) if session.running.is_some()) {
+                adapter.run().unwrap();
+            }
+        }
+        drop(adapter);
+
+        let mut reader = output.as_slice();
+        std::iter::from_fn(|| read_message(&mut reader).unwrap()).collect()
+    }
+
+    #[test]
+    fn session() {
+        VERBOSITY.get_or_init(|| Verbosity::Error);
+
+        let program = canonicalize("tests/fib.asm");
+        let messages = exchange(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "launch", "arguments": { "program": "tests/fib.asm", "headless": true } }),
+            json!({
+                "command": "setBreakpoints",
+                "arguments": { "source": { "path": program }, "breakpoints": [{ "line": 17 }] },
+            }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "next", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": REGISTERS } }),
+        ]);
+
+        let responses: Vec<&Value> = messages
+            .iter()
+            .filter(|message| message["type"] == "response")
+            .collect();
+        assert!(responses.iter().all(|response| response["success"] == true));
+
+        let events: Vec<&str> = messages
+            .iter()
+            .filter_map(|message| message["event"].as_str())
+            .collect();
+        assert_eq!(events, ["initialized", "stopped", "stopped"]);
+
+        assert_eq!(responses[2]["body"]["breakpoints"][0]["verified"], true);
+        // Stopped inside `fib`, which was called from line 13
+        let frames = &responses[4]["body"]["stackFrames"];
+        assert_eq!(frames[0]["line"], 17);
+        assert_eq!(frames[0]["name"], "fib");
+        assert_eq!(frames[1]["line"], 13);
+        assert_eq!(frames[1]["name"], "_start");
+        assert_eq!(responses[6]["body"]["stackFrames"][0]["line"], 18);
+        assert_eq!(responses[7]["body"]["variables"][0]["name"], "A");
+    }
+
+    #[test]
+    fn encoding() {
+        for data in [&b""[..], b"f", b"fo", b"foo", b"foob", b"\xFF\x00\x10"] {
+            assert_eq!(unbase64(&base64(data)).as_deref(), Some(data));
+        }
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+    }
+}
@@ -1,14 +1,19 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::{pin::Pin, sync::atomic::AtomicBool};
 use std::str::FromStr;
 
 use async_std::task::JoinHandle;
-use minifb::{Icon, Scale, ScaleMode, WindowOptions};
+use minifb::{Icon, InputCallback, Key, KeyRepeat, Scale, ScaleMode, WindowOptions};
 
 const FONT: &[u8; 1 << 12] = include_bytes!("../vga-font.rom");
 const WIDTH: usize = 640;
 const HEIGHT: usize = 400;
+const COLUMNS: usize = WIDTH / 8;
+const ROWS: usize = HEIGHT / 16;
 
 const COLORS: [u32; 16] = [
     0x000000,
@@ -29,12 +34,236 @@ const COLORS: [u32; 16] = [
     0xffffff,
 ];
 
+/// Unicode equivalents of each character in code-page 737,
+/// used when rendering the buffer to a terminal.
+const CODE_PAGE: [char; 256] = [
+    ' ', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+    ' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_',
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂',
+    'Α', 'Β', 'Γ', 'Δ', 'Ε', 'Ζ', 'Η', 'Θ', 'Ι', 'Κ', 'Λ', 'Μ', 'Ν', 'Ξ', 'Ο', 'Π',
+    'Ρ', 'Σ', 'Τ', 'Υ', 'Φ', 'Χ', 'Ψ', 'Ω', 'α', 'β', 'γ', 'δ', 'ε', 'ζ', 'η', 'θ',
+    'ι', 'κ', 'λ', 'μ', 'ν', 'ξ', 'ο', 'π', 'ρ', 'σ', 'ς', 'τ', 'υ', 'φ', 'χ', 'ψ',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'ω', 'ά', 'έ', 'ή', 'ϊ', 'ί', 'ό', 'ύ', 'ϋ', 'ώ', 'Ά', 'Έ', 'Ή', 'Ί', 'Ό', 'Ύ',
+    'Ώ', '±', '≥', '≤', 'Ϊ', 'Ϋ', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', ' ',
+];
+
+/// The most bytes each keyboard queue holds,
+/// any keys typed once it's full are dropped until the program catches up.
+const KEY_QUEUE: usize = 16;
+
+/// Keys typed into the window, waiting to be read by the built-in keyboard peripheral.
+pub static KEYS: Mutex<Keys> = Mutex::new(Keys::new());
+
+#[derive(Debug)]
+pub struct Keys {
+    /// Characters typed, in code-page 737.
+    pub chars: VecDeque<u8>,
+    /// Scancodes (set 1) of keys pressed and released.
+    pub scancodes: VecDeque<u8>,
+}
+
+impl Keys {
+    const fn new() -> Keys {
+        Keys {
+            chars: VecDeque::new(),
+            scancodes: VecDeque::new(),
+        }
+    }
+
+    /// Queues a typed character, if it can be shown in code-page 737.
+    pub fn type_char(&mut self, c: char) {
+        let byte = match c {
+            '\0'..='\x7F' => c as u8,
+            _ => match CODE_PAGE[0x80..].iter().position(|cp| *cp == c) {
+                Some(idx) => idx as u8 + 0x80,
+                None => return,
+            },
+        };
+
+        if self.chars.len() < KEY_QUEUE {
+            self.chars.push_back(byte);
+        }
+    }
+
+    /// Queues the scancode for a key being pressed or released.
+    pub fn press(&mut self, key: Key, pressed: bool) {
+        let Some((extended, code)) = scancode(key) else {
+            return;
+        };
+        let code = if pressed { code } else { code | 0x80 };
+
+        // Extended keys are prefixed with 0xE0, and the whole sequence has to fit
+        if self.scancodes.len() + extended as usize >= KEY_QUEUE {
+            return;
+        }
+        if extended {
+            self.scancodes.push_back(0xE0);
+        }
+        self.scancodes.push_back(code);
+    }
+}
+
+/// Passes characters typed into the window to [`KEYS`].
+struct CharCallback;
+
+impl InputCallback for CharCallback {
+    fn add_char(&mut self, uni_char: u32) {
+        // Control characters are typed from their keys instead,
+        // since not every platform reports them here
+        match char::from_u32(uni_char) {
+            Some(c) if !c.is_control() => {
+                KEYS.lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .type_char(c);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The control character typed by `key`, if any.
+fn control_char(key: Key) -> Option<char> {
+    match key {
+        Key::Enter | Key::NumPadEnter => Some('\n'),
+        Key::Backspace => Some('\x08'),
+        Key::Tab => Some('\t'),
+        Key::Escape => Some('\x1B'),
+        Key::Delete => Some('\x7F'),
+        _ => None,
+    }
+}
+
+/// The scancode (set 1) sent when `key` is pressed, and whether it's an extended key.
+fn scancode(key: Key) -> Option<(bool, u8)> {
+    let code = match key {
+        Key::Escape => 0x01,
+        Key::Key1 => 0x02,
+        Key::Key2 => 0x03,
+        Key::Key3 => 0x04,
+        Key::Key4 => 0x05,
+        Key::Key5 => 0x06,
+        Key::Key6 => 0x07,
+        Key::Key7 => 0x08,
+        Key::Key8 => 0x09,
+        Key::Key9 => 0x0A,
+        Key::Key0 => 0x0B,
+        Key::Minus => 0x0C,
+        Key::Equal => 0x0D,
+        Key::Backspace => 0x0E,
+        Key::Tab => 0x0F,
+        Key::Q => 0x10,
+        Key::W => 0x11,
+        Key::E => 0x12,
+        Key::R => 0x13,
+        Key::T => 0x14,
+        Key::Y => 0x15,
+        Key::U => 0x16,
+        Key::I => 0x17,
+        Key::O => 0x18,
+        Key::P => 0x19,
+        Key::LeftBracket => 0x1A,
+        Key::RightBracket => 0x1B,
+        Key::Enter => 0x1C,
+        Key::LeftCtrl => 0x1D,
+        Key::A => 0x1E,
+        Key::S => 0x1F,
+        Key::D => 0x20,
+        Key::F => 0x21,
+        Key::G => 0x22,
+        Key::H => 0x23,
+        Key::J => 0x24,
+        Key::K => 0x25,
+        Key::L => 0x26,
+        Key::Semicolon => 0x27,
+        Key::Apostrophe => 0x28,
+        Key::Backquote => 0x29,
+        Key::LeftShift => 0x2A,
+        Key::Backslash => 0x2B,
+        Key::Z => 0x2C,
+        Key::X => 0x2D,
+        Key::C => 0x2E,
+        Key::V => 0x2F,
+        Key::B => 0x30,
+        Key::N => 0x31,
+        Key::M => 0x32,
+        Key::Comma => 0x33,
+        Key::Period => 0x34,
+        Key::Slash => 0x35,
+        Key::RightShift => 0x36,
+        Key::NumPadAsterisk => 0x37,
+        Key::LeftAlt => 0x38,
+        Key::Space => 0x39,
+        Key::CapsLock => 0x3A,
+        Key::F1 => 0x3B,
+        Key::F2 => 0x3C,
+        Key::F3 => 0x3D,
+        Key::F4 => 0x3E,
+        Key::F5 => 0x3F,
+        Key::F6 => 0x40,
+        Key::F7 => 0x41,
+        Key::F8 => 0x42,
+        Key::F9 => 0x43,
+        Key::F10 => 0x44,
+        Key::NumLock => 0x45,
+        Key::ScrollLock => 0x46,
+        Key::NumPad7 => 0x47,
+        Key::NumPad8 => 0x48,
+        Key::NumPad9 => 0x49,
+        Key::NumPadMinus => 0x4A,
+        Key::NumPad4 => 0x4B,
+        Key::NumPad5 => 0x4C,
+        Key::NumPad6 => 0x4D,
+        Key::NumPadPlus => 0x4E,
+        Key::NumPad1 => 0x4F,
+        Key::NumPad2 => 0x50,
+        Key::NumPad3 => 0x51,
+        Key::NumPad0 => 0x52,
+        Key::NumPadDot => 0x53,
+        Key::F11 => 0x57,
+        Key::F12 => 0x58,
+        key => {
+            let code = match key {
+                Key::NumPadEnter => 0x1C,
+                Key::RightCtrl => 0x1D,
+                Key::NumPadSlash => 0x35,
+                Key::RightAlt => 0x38,
+                Key::Home => 0x47,
+                Key::Up => 0x48,
+                Key::PageUp => 0x49,
+                Key::Left => 0x4B,
+                Key::Right => 0x4D,
+                Key::End => 0x4F,
+                Key::Down => 0x50,
+                Key::PageDown => 0x51,
+                Key::Insert => 0x52,
+                Key::Delete => 0x53,
+                Key::LeftSuper => 0x5B,
+                Key::RightSuper => 0x5C,
+                Key::Menu => 0x5D,
+                _ => return None,
+            };
+            return Some((true, code));
+        }
+    };
+
+    Some((false, code))
+}
+
 #[derive(Debug)]
 pub struct TextBuffer {
     chars: Pin<Box<[u8; 1 << 11]>>,
     modifiers: Pin<Box<[u8; 1 << 11]>>,
     modified: Arc<AtomicBool>,
-    handle: JoinHandle<()>,
+    /// The task drawing the window, if the buffer isn't headless.
+    handle: Option<JoinHandle<()>>,
 }
 
 struct BufferPtr {
@@ -57,7 +286,19 @@ impl TextBuffer {
             modified: modified.clone(),
         }));
 
-        TextBuffer { chars, modifiers, modified, handle }
+        TextBuffer { chars, modifiers, modified, handle: Some(handle) }
+    }
+
+    /// Creates a text buffer without a window.
+    /// The contents can still be rendered on demand with [`TextBuffer::render_ansi`]
+    /// and [`TextBuffer::snapshot`].
+    pub fn headless() -> TextBuffer {
+        TextBuffer {
+            chars: Box::pin([0; 1 << 11]),
+            modifiers: Box::pin([0; 1 << 11]),
+            modified: Arc::new(AtomicBool::new(true)),
+            handle: None,
+        }
     }
 
     pub fn get(&self, addr: u16) -> u8 {
@@ -83,9 +324,78 @@ impl TextBuffer {
     }
 
     pub fn reset(&mut self) {
+        self.modified.store(true, Ordering::Relaxed);
         self.chars.fill(0);
         self.modifiers.fill(0);
     }
+
+    /// Renders the buffer as 24-bit colored text for an ANSI terminal.
+    pub fn render_ansi(&self) -> String {
+        let mut out = String::new();
+
+        for row in 0..ROWS {
+            let mut prev = None;
+
+            for column in 0..COLUMNS {
+                let idx = column + row * COLUMNS;
+                let modifier = self.modifiers[idx];
+
+                if prev != Some(modifier) {
+                    let fg = COLORS[(modifier & 0xf) as usize];
+                    let bg = COLORS[(modifier >> 4) as usize];
+                    // Writing to a `String` is infallible
+                    let _ = write!(
+                        out,
+                        "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                        fg >> 16,
+                        (fg >> 8) & 0xff,
+                        fg & 0xff,
+                        bg >> 16,
+                        (bg >> 8) & 0xff,
+                        bg & 0xff,
+                    );
+                    prev = Some(modifier);
+                }
+
+                out.push(CODE_PAGE[self.chars[idx] as usize]);
+            }
+
+            out.push_str("\x1b[0m\n");
+        }
+
+        out
+    }
+
+    /// Writes a PNG image of the rendered buffer to `writer`.
+    pub fn snapshot<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut fb = vec![0x00000000; WIDTH * HEIGHT];
+        render(&self.chars, &self.modifiers, &mut fb);
+        png::write(writer, WIDTH as u32, HEIGHT as u32, &fb)
+    }
+}
+
+/// Renders the characters and modifiers to a 0RGB framebuffer.
+fn render(chars: &[u8; 1 << 11], modifiers: &[u8; 1 << 11], fb: &mut [u32]) {
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let font_x = x % 8;
+            let font_y = y % 16;
+
+            let char_x = x / 8;
+            let char_y = y / 16;
+            let char_idx = char_x + char_y * COLUMNS;
+            let (character, modifier) = (chars[char_idx], modifiers[char_idx]);
+
+            let font_addr = ((character as usize) << 4) + font_y;
+            let lit = FONT[font_addr] & (1 << (7 - font_x)) > 0;
+
+            // This part isn't part of the actual CPU,
+            // the real value will be transmitted via VGA instead of stored.
+            let fg = COLORS[(modifier & 0xf) as usize];
+            let bg = COLORS[(modifier >> 4) as usize];
+            fb[x + y * WIDTH] = if lit { fg } else { bg };
+        }
+    }
 }
 
 async fn run_handle(buffer: BufferPtr) {
@@ -102,34 +412,14 @@ async fn run_handle(buffer: BufferPtr) {
         window.set_icon(icon);
     }
 
+    window.set_input_callback(Box::new(CharCallback));
+
     let mut fb = [0x00000000; WIDTH * HEIGHT];
 
     while window.is_open() {
         if buffer.modified.load(Ordering::Relaxed) {
             buffer.modified.store(false, Ordering::Relaxed);
-            for y in 0..HEIGHT {
-                for x in 0..WIDTH {
-                    let font_x = x % 8;
-                    let font_y = y % 16;
-
-                    let char_x = x / 8;
-                    let char_y = y / 16;
-                    let char_idx = char_x + char_y * WIDTH / 8;
-                    let (character, modifier) = unsafe {(
-                        (*buffer.chars)[char_idx], 
-                        (*buffer.modifiers)[char_idx]
-                    )};
-
-                    let font_addr = ((character as usize) << 4) + font_y;
-                    let lit = FONT[font_addr] & (1 << (7 - font_x)) > 0;
-
-                    // This part isn't part of the actual CPU,
-                    // the real value will be transmitted via VGA instead of stored.
-                    let fg = COLORS[(modifier & 0xf) as usize];
-                    let bg = COLORS[(modifier >> 4) as usize];
-                    fb[x + y * WIDTH] = if lit { fg } else { bg };
-                }
-            }
+            unsafe { render(&*buffer.chars, &*buffer.modifiers, &mut fb) };
 
             window
                 .update_with_buffer(&fb, WIDTH, HEIGHT)
@@ -137,6 +427,17 @@ async fn run_handle(buffer: BufferPtr) {
         } else {
             window.update();
         }
+
+        let mut keys = KEYS.lock().unwrap_or_else(PoisonError::into_inner);
+        for key in window.get_keys_pressed(KeyRepeat::Yes) {
+            keys.press(key, true);
+            if let Some(c) = control_char(key) {
+                keys.type_char(c);
+            }
+        }
+        for key in window.get_keys_released() {
+            keys.press(key, false);
+        }
     }
 }
 
@@ -144,3 +445,93 @@ fn get_icon() -> Option<Icon> {
     // TODO: actually add icon
     None
 }
+
+/// A minimal PNG encoder, since snapshots don't need to be compressed.
+mod png {
+    use std::io::{self, Write};
+
+    const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+    /// Maximum length of an uncompressed deflate block.
+    const BLOCK: usize = 0xFFFF;
+
+    pub fn write<W: Write>(mut writer: W, width: u32, height: u32, fb: &[u32]) -> io::Result<()> {
+        writer.write_all(&SIGNATURE)?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        // 8-bit depth, truecolor, default compression, filter, and no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        chunk(&mut writer, b"IHDR", &header)?;
+
+        // Each scanline begins with a filter type of 0 (none)
+        let mut raw = Vec::with_capacity((width as usize * 3 + 1) * height as usize);
+        for row in fb.chunks(width as usize) {
+            raw.push(0);
+            for pixel in row {
+                raw.extend_from_slice(&pixel.to_be_bytes()[1..]);
+            }
+        }
+
+        let mut zlib = vec![0x78, 0x01];
+        let blocks = raw.chunks(BLOCK).count();
+        for (i, block) in raw.chunks(BLOCK).enumerate() {
+            zlib.push((i + 1 == blocks) as u8);
+            zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+            zlib.extend_from_slice(block);
+        }
+        zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+        chunk(&mut writer, b"IDAT", &zlib)?;
+
+        chunk(&mut writer, b"IEND", &[])
+    }
+
+    fn chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+        writer.write_all(&(data.len() as u32).to_be_bytes())?;
+        writer.write_all(kind)?;
+        writer.write_all(data)?;
+
+        let crc = crc32(&[kind.as_slice(), data].concat());
+        writer.write_all(&crc.to_be_bytes())
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = 0xFFFFFFFFu32;
+
+        for byte in data {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xEDB88320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+
+        !crc
+    }
+
+    fn adler32(data: &[u8]) -> u32 {
+        let (mut a, mut b) = (1u32, 0u32);
+
+        for byte in data {
+            a = (a + *byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+
+        (b << 16) | a
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn checksums() {
+            assert_eq!(crc32(b"IEND"), 0xAE426082);
+            assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        }
+    }
+}
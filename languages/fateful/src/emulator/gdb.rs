@@ -0,0 +1,724 @@
+//! A stub for the GDB remote serial protocol,
+//! allowing programs to be debugged from GDB, LLDB, or any editor that speaks the protocol.
+//!
+//! The registers are described to the debugger with a target description,
+//! in the order A, B, C, D, E, F, H, L, SREG, SP, and PC,
+//! with the 16-bit registers in little-endian order.
+//!
+//! Since program and data memory are separate, data memory
+//! (including the text buffer and memory-mapped I/O) is exposed at `0x0000..=0xFFFF`,
+//! and program memory is exposed at `0x10000..=0x1FFFF`.
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{Access, SReg, State, Trap};
+
+/// Address that program memory is exposed at.
+const PROGRAM: u32 = 0x10000;
+/// Number of clock cycles run between checks for an interrupt while continuing.
+const CHUNK: u64 = 10_000;
+
+const TARGET: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.fateful.f8ful">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="b" bitsize="8" type="uint8"/>
+    <reg name="c" bitsize="8" type="uint8"/>
+    <reg name="d" bitsize="8" type="uint8"/>
+    <reg name="e" bitsize="8" type="uint8"/>
+    <reg name="f" bitsize="8" type="uint8"/>
+    <reg name="h" bitsize="8" type="uint8"/>
+    <reg name="l" bitsize="8" type="uint8"/>
+    <reg name="sreg" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// Why the CPU last stopped, reported to the debugger as a stop reply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stop {
+    /// Stopped after a step, at a breakpoint, or after halting.
+    Trap,
+    /// Hit a watchpoint, where `kind` is `watch`, `rwatch`, or `awatch`.
+    Watch { kind: &'static str, addr: u16 },
+    /// Interrupted by the debugger.
+    Interrupt,
+}
+
+impl Stop {
+    fn reply(&self) -> String {
+        match self {
+            Stop::Trap => "S05".to_owned(),
+            Stop::Watch { kind, addr } => format!("T05{kind}:{addr:x};"),
+            Stop::Interrupt => "S02".to_owned(),
+        }
+    }
+}
+
+/// What to do once a packet has been handled.
+#[derive(Debug)]
+enum Action {
+    Reply(String),
+    /// Resume execution, replying once the CPU stops.
+    Resume(Resume),
+    /// Reply with `OK`, then end the session.
+    Detach,
+    /// End the session without replying.
+    Kill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resume {
+    Step,
+    Continue,
+}
+
+/// Waits for a debugger to connect on `port`,
+/// then serves it until it detaches or kills the program.
+pub(super) fn serve(state: &mut State, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("INFO: waiting for a debugger on port {port}");
+
+    let (stream, addr) = listener.accept()?;
+    println!("INFO: debugger connected from {addr}");
+
+    Session::new(stream).run(state)
+}
+
+struct Session {
+    reader: BufReader<TcpStream>,
+    /// Whether packets are acknowledged, which the debugger can disable with `QStartNoAckMode`.
+    ack: bool,
+    stop: Stop,
+}
+
+impl Session {
+    fn new(stream: TcpStream) -> Session {
+        Session {
+            reader: BufReader::new(stream),
+            ack: true,
+            stop: Stop::Trap,
+        }
+    }
+
+    fn run(&mut self, state: &mut State) -> io::Result<()> {
+        while let Some(packet) = self.receive()? {
+            // The reply to `QStartNoAckMode` is the last one to be acknowledged
+            if packet == "QStartNoAckMode" {
+                self.send("OK")?;
+                self.ack = false;
+                continue;
+            }
+
+            match self.handle(state, &packet) {
+                Action::Reply(reply) => self.send(&reply)?,
+                Action::Resume(resume) => {
+                    self.stop = self.resume(state, resume)?;
+                    self.send(&self.stop.reply())?;
+                }
+                Action::Detach => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                Action::Kill => return Ok(()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, state: &mut State, packet: &str) -> Action {
+        let reply =
+            |reply: Option<String>| Action::Reply(reply.unwrap_or_else(|| "E01".to_owned()));
+
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return reply(features(args));
+        }
+
+        match packet {
+            "?" => return Action::Reply(self.stop.reply()),
+            "g" => return Action::Reply(hex(&registers(state))),
+            "D" | "vKill" => return Action::Detach,
+            "k" => return Action::Kill,
+            "qAttached" => return Action::Reply("1".to_owned()),
+            "qC" => return Action::Reply("QC1".to_owned()),
+            "qfThreadInfo" => return Action::Reply("m1".to_owned()),
+            "qsThreadInfo" => return Action::Reply("l".to_owned()),
+            _ => {}
+        }
+
+        if packet.starts_with("qSupported") {
+            return Action::Reply(
+                "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_owned(),
+            );
+        }
+
+        let Some(cmd) = packet.chars().next() else {
+            return Action::Reply(String::new());
+        };
+        let args = &packet[cmd.len_utf8()..];
+
+        match cmd {
+            // There's only a single thread, so every thread is the same
+            'H' | 'T' => Action::Reply("OK".to_owned()),
+            'G' => reply(set_registers(state, args)),
+            'p' => reply(read_register(state, args)),
+            'P' => reply(write_register(state, args)),
+            'm' => reply(read_memory(state, args)),
+            'M' => reply(write_memory(state, args)),
+            'Z' => reply(insert_point(state, args)),
+            'z' => reply(remove_point(state, args)),
+            's' | 'c' => {
+                // Resuming can optionally jump to a new address first
+                if !args.is_empty() {
+                    match u32::from_str_radix(args, 16) {
+                        Ok(addr) => {
+                            state.pc = addr as u16;
+                            state.ctrl.clock = 0;
+                        }
+                        Err(_) => return reply(None),
+                    }
+                }
+
+                Action::Resume(match cmd {
+                    's' => Resume::Step,
+                    _ => Resume::Continue,
+                })
+            }
+            // An empty reply tells the debugger the packet isn't supported
+            _ => Action::Reply(String::new()),
+        }
+    }
+
+    /// Resumes execution until the CPU stops.
+    fn resume(&mut self, state: &mut State, resume: Resume) -> io::Result<Stop> {
+        match resume {
+            Resume::Step => {
+                state.step_instruction();
+                Ok(state
+                    .trap
+                    .take()
+                    .map_or(Stop::Trap, |trap| stop(state, trap)))
+            }
+            Resume::Continue => loop {
+                let halted = state.run_cycles(CHUNK);
+
+                if let Some(trap) = state.trap.take() {
+                    return Ok(stop(state, trap));
+                }
+                if halted {
+                    return Ok(Stop::Trap);
+                }
+                if self.interrupted()? {
+                    return Ok(Stop::Interrupt);
+                }
+            },
+        }
+    }
+
+    /// Checks whether the debugger has sent an interrupt, without blocking.
+    /// A closed connection also counts as an interrupt, so the CPU doesn't run forever.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.reader.get_ref().set_nonblocking(true)?;
+        let next = self.reader.fill_buf().map(|buf| buf.first().copied());
+        self.reader.get_ref().set_nonblocking(false)?;
+
+        match next {
+            Ok(Some(0x03)) => {
+                self.reader.consume(1);
+                Ok(true)
+            }
+            Ok(Some(_)) => Ok(false),
+            Ok(None) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reads the next valid packet, returning `None` once the debugger disconnects.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Acknowledgements, and interrupts while already stopped, can be skipped
+            match self.byte()? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(sum(&data));
+
+            if self.ack {
+                let ack: &[u8] = if valid { b"+" } else { b"-" };
+                self.reader.get_mut().write_all(ack)?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&unescape(&data)).into_owned()));
+            }
+        }
+    }
+
+    /// Sends a packet, resending it until the debugger acknowledges it.
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let data = escape(data.as_bytes());
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&data);
+        packet.extend_from_slice(format!("#{:02x}", sum(&data)).as_bytes());
+
+        loop {
+            let stream = self.reader.get_mut();
+            stream.write_all(&packet)?;
+            stream.flush()?;
+
+            if !self.ack {
+                return Ok(());
+            }
+            loop {
+                match self.byte()? {
+                    Some(b'+') | None => return Ok(()),
+                    Some(b'-') => break,
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+}
+
+/// Describes a trap as a stop reply.
+fn stop(state: &State, trap: Trap) -> Stop {
+    match trap {
+        Trap::Break(_) => Stop::Trap,
+        Trap::Watch { addr, .. } => {
+            let watched = state
+                .watchpoints
+                .get(&addr)
+                .copied()
+                .unwrap_or(Access::all());
+            let kind = if watched == Access::W {
+                "watch"
+            } else if watched == Access::R {
+                "rwatch"
+            } else {
+                "awatch"
+            };
+
+            Stop::Watch { kind, addr }
+        }
+    }
+}
+
+/// Handles `qXfer:features:read`, with `args` in the form `offset,length`.
+fn features(args: &str) -> Option<String> {
+    let (offset, length) = args.split_once(',')?;
+    let offset = usize::from_str_radix(offset, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+
+    let start = offset.min(TARGET.len());
+    let end = offset.saturating_add(length).min(TARGET.len());
+    // `m` means there's more to read, and `l` means this is the last chunk
+    let prefix = if end < TARGET.len() { 'm' } else { 'l' };
+
+    Some(format!("{prefix}{}", &TARGET[start..end]))
+}
+
+fn registers(state: &State) -> Vec<u8> {
+    let bank = &state.bank;
+    let mut registers = vec![
+        bank.a,
+        bank.b,
+        bank.c,
+        bank.d,
+        bank.e,
+        bank.f,
+        bank.h,
+        bank.l,
+        state.sreg.bits(),
+    ];
+    registers.extend_from_slice(&state.sp.to_le_bytes());
+    registers.extend_from_slice(&state.pc.to_le_bytes());
+
+    registers
+}
+
+/// Sets register `n` from its little-endian bytes, returning `None` if either is invalid.
+fn set_register(state: &mut State, n: usize, bytes: &[u8]) -> Option<()> {
+    match (n, bytes) {
+        (0..=7, [value]) => state.bank.set_reg(n as u8, *value),
+        (8, [value]) => state.sreg = SReg::from_bits_retain(*value),
+        (9, [low, high]) => state.sp = u16::from_le_bytes([*low, *high]),
+        (10, [low, high]) => state.pc = u16::from_le_bytes([*low, *high]),
+        _ => return None,
+    }
+
+    Some(())
+}
+
+fn set_registers(state: &mut State, args: &str) -> Option<String> {
+    let bytes = unhex(args)?;
+    if bytes.len() != 13 {
+        return None;
+    }
+
+    for n in 0..=8 {
+        set_register(state, n, &bytes[n..=n])?;
+    }
+    set_register(state, 9, &bytes[9..11])?;
+    set_register(state, 10, &bytes[11..13])?;
+
+    Some("OK".to_owned())
+}
+
+fn read_register(state: &State, args: &str) -> Option<String> {
+    let registers = registers(state);
+    match usize::from_str_radix(args, 16).ok()? {
+        n @ 0..=8 => Some(hex(&registers[n..=n])),
+        9 => Some(hex(&registers[9..11])),
+        10 => Some(hex(&registers[11..13])),
+        _ => None,
+    }
+}
+
+fn write_register(state: &mut State, args: &str) -> Option<String> {
+    let (n, value) = args.split_once('=')?;
+    let n = usize::from_str_radix(n, 16).ok()?;
+    set_register(state, n, &unhex(value)?)?;
+
+    Some("OK".to_owned())
+}
+
+/// Parses the `addr,length` arguments shared by memory packets.
+fn range(args: &str) -> Option<(u32, u32)> {
+    let (addr, length) = args.split_once(',')?;
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+    let length = u32::from_str_radix(length, 16).ok()?;
+
+    match addr.checked_add(length)? <= PROGRAM * 2 {
+        true => Some((addr, length)),
+        false => None,
+    }
+}
+
+fn read_memory(state: &State, args: &str) -> Option<String> {
+    let (addr, length) = range(args)?;
+
+    let mut bytes = Vec::with_capacity(length as usize);
+    for addr in addr..addr + length {
+        bytes.push(match addr.checked_sub(PROGRAM) {
+            Some(addr) => state.program[addr as usize],
+            // Reading from peripherals can have side effects, so they always read as zero
+            None if (0xFFD0..=0xFFFB).contains(&addr) => 0x00,
+            None => state.read_bus(addr as u16),
+        });
+    }
+
+    Some(hex(&bytes))
+}
+
+fn write_memory(state: &mut State, args: &str) -> Option<String> {
+    let (args, data) = args.split_once(':')?;
+    let (addr, length) = range(args)?;
+    let data = unhex(data)?;
+    if data.len() != length as usize {
+        return None;
+    }
+
+    for (addr, byte) in (addr..addr + length).zip(data) {
+        match addr.checked_sub(PROGRAM) {
+            Some(addr) => state.program[addr as usize] = byte,
+            None => state.write_bus(addr as u16, byte),
+        }
+    }
+
+    Some("OK".to_owned())
+}
+
+/// Parses the `type,addr,kind` arguments shared by breakpoint and watchpoint packets.
+fn point(args: &str) -> Option<(char, u16, u16)> {
+    let mut args = args.split(',');
+    let kind = args.next()?.chars().next()?;
+    // Breakpoints on program memory are always set on the PC
+    let addr = u32::from_str_radix(args.next()?, 16).ok()? as u16;
+    let length = u16::from_str_radix(args.next()?, 16).ok()?;
+
+    Some((kind, addr, length))
+}
+
+/// Maps a watchpoint type to the accesses it watches.
+fn access(kind: char) -> Option<Access> {
+    match kind {
+        '2' => Some(Access::W),
+        '3' => Some(Access::R),
+        '4' => Some(Access::R | Access::W),
+        _ => None,
+    }
+}
+
+/// The addresses covered by a watchpoint, stopping at the end of the address space.
+fn watched(addr: u16, length: u16) -> impl Iterator<Item = u16> {
+    let end = (addr as u32 + length.max(1) as u32).min(0x10000);
+    (addr as u32..end).map(|addr| addr as u16)
+}
+
+fn insert_point(state: &mut State, args: &str) -> Option<String> {
+    let (kind, addr, length) = point(args)?;
+
+    match kind {
+        '0' | '1' => {
+            state.breakpoints.insert(addr);
+        }
+        kind => {
+            let access = access(kind)?;
+            for addr in watched(addr, length) {
+                *state.watchpoints.entry(addr).or_insert(Access::empty()) |= access;
+            }
+        }
+    }
+
+    Some("OK".to_owned())
+}
+
+fn remove_point(state: &mut State, args: &str) -> Option<String> {
+    let (kind, addr, length) = point(args)?;
+
+    match kind {
+        '0' | '1' => {
+            state.breakpoints.remove(&addr);
+        }
+        kind => {
+            let access = access(kind)?;
+            for addr in watched(addr, length) {
+                if let Some(watched) = state.watchpoints.get_mut(&addr) {
+                    watched.remove(access);
+                    if watched.is_empty() {
+                        state.watchpoints.remove(&addr);
+                    }
+                }
+            }
+        }
+    }
+
+    Some("OK".to_owned())
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for byte in data {
+        match byte {
+            b'$' | b'#' | b'}' | b'*' => escaped.extend_from_slice(&[b'}', byte ^ 0x20]),
+            byte => escaped.push(*byte),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'}' => unescaped.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            byte => unescaped.push(*byte),
+        }
+    }
+
+    unescaped
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        // Writing to a `String` is infallible
+        let _ = write!(hex, "{byte:02x}");
+    }
+
+    hex
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{emulator::display::TextBuffer, symbols::Symbols};
+
+    /// Sends a packet from the debugger's side, returning the stub's reply.
+    fn request(stream: &mut TcpStream, data: &str) -> String {
+        write!(stream, "${data}#{:02x}", sum(data.as_bytes())).unwrap();
+
+        let mut bytes = stream
+            .try_clone()
+            .unwrap()
+            .bytes()
+            .map(|byte| byte.unwrap());
+        assert_eq!(bytes.next(), Some(b'+'));
+        assert_eq!(bytes.next(), Some(b'$'));
+        let reply: Vec<u8> = bytes.by_ref().take_while(|byte| *byte != b'#').collect();
+        bytes.nth(1);
+
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    /// Runs a session over `state`, sending each of `packets` in turn and returning the replies.
+    fn replies(state: &mut State, packets: &'static [&'static str]) -> Vec<String> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            packets
+                .iter()
+                .map(|packet| request(&mut stream, packet))
+                .collect::<Vec<String>>()
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        Session::new(stream).run(state).unwrap();
+        client.join().unwrap()
+    }
+
+    fn state() -> State {
+        State::init(
+            vec![0; 1 << 16].into_boxed_slice(),
+            Symbols::new(),
+            TextBuffer::headless(),
+        )
+    }
+
+    #[test]
+    fn session() {
+        // mv A, 5
+        // add A, 1
+        // st [0xE000], A
+        // halt
+        let mut state = state();
+        state.program[..8].copy_from_slice(&[0x78, 0x05, 0x08, 0x01, 0x98, 0xE0, 0x00, 0xF0]);
+
+        let replies = replies(
+            &mut state,
+            &[
+                "qSupported:swbreak+",
+                "Z0,4,1",
+                "c",
+                "g",
+                "s",
+                "me000,1",
+                "Me001,1:2a",
+                "me001,1",
+                "m10000,2",
+                "P0=09",
+                "p0",
+                "c",
+                "p9",
+                "D",
+            ],
+        );
+
+        assert!(replies[0].contains("qXfer:features:read+"));
+        assert_eq!(replies[1], "OK");
+        assert_eq!(replies[2], "S05");
+        assert_eq!(&replies[3][..16], "0600000000000000");
+        assert_eq!(&replies[3][18..], "ffef0400");
+        assert_eq!(replies[4], "S05");
+        assert_eq!(replies[5], "06");
+        assert_eq!(replies[6], "OK");
+        assert_eq!(replies[7], "2a");
+        assert_eq!(replies[8], "7805");
+        assert_eq!(replies[9], "OK");
+        assert_eq!(replies[10], "09");
+        assert_eq!(replies[11], "S05");
+        assert_eq!(replies[12], "ffef");
+        assert_eq!(replies[13], "OK");
+        assert!(state.sreg.contains(SReg::H));
+    }
+
+    #[test]
+    fn watch_end() {
+        let mut state = state();
+        let replies = replies(&mut state, &["Z2,ffff,1", "Z3,fffe,4", "z3,fffe,4", "D"]);
+
+        assert_eq!(replies[..3], ["OK", "OK", "OK"]);
+        assert_eq!(state.watchpoints.len(), 1);
+        assert_eq!(state.watchpoints[&0xFFFF], Access::W);
+    }
+
+    #[test]
+    fn non_ascii() {
+        let mut state = state();
+        let replies = replies(&mut state, &["\u{e9}", "\u{e9}1", "D"]);
+
+        assert_eq!(replies[..2], ["", ""]);
+    }
+
+    #[test]
+    fn peripheral_reads() {
+        let input = std::env::temp_dir().join(format!("fateful-gdb-{}", std::process::id()));
+        std::fs::write(&input, b"ok").unwrap();
+        let mut state = state();
+        state
+            .load(
+                format!("builtin:uart:backend=file,in={}", input.display()),
+                vec![0x10, 0x11],
+            )
+            .unwrap();
+        std::fs::remove_file(&input).unwrap();
+
+        let replies = replies(&mut state, &["mffd0,2", "mffd0,2", "D"]);
+
+        assert_eq!(replies[..2], ["0000", "0000"]);
+        assert_eq!(state.read_bus(0xFFD0), b'o');
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape(b"a$b}"), b"a}\x04b}]");
+        assert_eq!(unescape(&escape(b"#*}$")), b"#*}$");
+        assert_eq!(unhex("0a2B"), Some(vec![0x0A, 0x2B]));
+        assert_eq!(unhex("0a2"), None);
+    }
+}
@@ -0,0 +1,144 @@
+//! A bounded history of CPU state, allowing execution to be rewound.
+//!
+//! Before each clock cycle, the CPU registers are recorded along with
+//! any value in memory or the text buffer that the cycle overwrites.
+//! Peripherals are not recorded, so their state can't be rewound.
+
+use std::collections::VecDeque;
+
+use super::{Alu, Control, RegBank, SReg};
+
+/// The state of the CPU registers before a single clock cycle.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Registers {
+    pub pc: u16,
+    pub sp: u16,
+    pub ctrl: Control,
+    pub sreg: SReg,
+    pub alu: Alu,
+    pub bus: u8,
+    pub bank: RegBank,
+    pub addr: u16,
+    pub cycles: u64,
+}
+
+/// A value overwritten during a single clock cycle.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Overwritten {
+    Memory {
+        addr: u16,
+        data: u8,
+    },
+    /// `addr` is relative to the start of the text buffer.
+    Text {
+        addr: u16,
+        data: u8,
+    },
+}
+
+/// Everything needed to undo a single clock cycle.
+/// The CPU can write to at most one address each cycle.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Delta {
+    pub registers: Registers,
+    pub overwritten: Option<Overwritten>,
+}
+
+#[derive(Debug)]
+pub(super) struct History {
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+}
+
+impl History {
+    /// Creates a history that keeps the last `capacity` clock cycles.
+    pub fn new(capacity: usize) -> History {
+        History {
+            deltas: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Records the registers before a clock cycle,
+    /// dropping the oldest cycle if the history is full.
+    pub fn record(&mut self, registers: Registers) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.deltas.len() == self.capacity {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(Delta {
+            registers,
+            overwritten: None,
+        });
+    }
+
+    /// Records a value overwritten during the current clock cycle.
+    pub fn overwrite(&mut self, overwritten: Overwritten) {
+        if let Some(delta) = self.deltas.back_mut() {
+            delta.overwritten = Some(overwritten);
+        }
+    }
+
+    /// Removes the most recent clock cycle from the history.
+    pub fn pop(&mut self) -> Option<Delta> {
+        self.deltas.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Registers;
+    use crate::{
+        assembler::{generator, lex, parse},
+        emulator::{display::TextBuffer, State},
+        symbols::Symbols,
+        Verbosity, VERBOSITY,
+    };
+
+    #[test]
+    fn rewind() {
+        VERBOSITY.get_or_init(|| Verbosity::Error);
+        let source = "\
+            mv A, 5\n\
+            st [0xE000], A\n\
+            mv B, 7\n\
+            add A, B\n\
+            st [0xE000], A\n\
+            st [0xF000], B\n\
+            push A\n\
+            halt\n\
+        ";
+        let tokens = lex::lex_string(None, source).unwrap();
+        let assembled = generator::generate(parse::parse(tokens).unwrap()).unwrap();
+        let program = assembled.program.to_vec().into_boxed_slice();
+        let mut state = State::init(program, Symbols::new(), TextBuffer::headless());
+
+        for _ in 0..2 {
+            state.step_instruction();
+        }
+        let before = state.registers();
+        let mem = state.mem.clone();
+        let text = state.text_buffer.get(0x0000);
+
+        for _ in 0..5 {
+            assert!(!state.step_instruction());
+        }
+        assert_eq!(state.mem[0xE000], 12);
+        assert_eq!(state.text_buffer.get(0x0000), 7);
+
+        for _ in 0..5 {
+            assert!(state.rewind_instruction());
+        }
+        let key = |r: Registers| (r.pc, r.sp, r.ctrl.clock, r.sreg, r.bank, r.cycles);
+        assert_eq!(key(state.registers()), key(before));
+        assert_eq!(state.mem, mem);
+        assert_eq!(state.text_buffer.get(0x0000), text);
+    }
+}
@@ -0,0 +1,250 @@
+//! Machine files, describing the peripherals attached to the emulator.
+//!
+//! Machine files are written in TOML,
+//! with a `[[peripheral]]` table for each attached peripheral:
+//!
+//! ```toml
+//! [[peripheral]]
+//! builtin = "uart"
+//! ports = [0xFFD0, 0xFFD1]
+//! options = { backend = "pty" }
+//!
+//! [[peripheral]]
+//! path = "peripherals/screen/target/release/libscreen.so"
+//! ports = [0xFFE0]
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Options of built-in peripherals that name files,
+/// which are resolved from the directory containing the machine file.
+const PATH_OPTIONS: &[(&str, &str)] = &[("disk", "path"), ("uart", "in"), ("uart", "out")];
+
+#[derive(Debug, Error)]
+pub enum MachineError {
+    #[error("unable to read machine file: {0}")]
+    Read(std::io::Error),
+    #[error("invalid machine file: {0}")]
+    Parse(toml::de::Error),
+    #[error("invalid machine file (peripheral {peripheral}): {message}")]
+    Invalid { peripheral: usize, message: String },
+}
+
+/// The peripherals to attach to the emulator.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Machine {
+    #[serde(default, rename = "peripheral")]
+    pub peripherals: Vec<Peripheral>,
+}
+
+/// A single `[[peripheral]]` table,
+/// giving either a library or a built-in peripheral and the addresses it's connected to.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Peripheral {
+    pub path: Option<PathBuf>,
+    pub builtin: Option<String>,
+    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub options: BTreeMap<String, OptionValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionValue::Str(value) => f.write_str(value),
+            OptionValue::Int(value) => write!(f, "{value}"),
+            OptionValue::Bool(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+impl Machine {
+    /// Reads the machine file at `path`.
+    /// Relative paths are resolved from the directory containing the file.
+    pub fn read(path: &Path) -> Result<Machine, MachineError> {
+        let source = fs::read_to_string(path).map_err(|err| MachineError::Read(err))?;
+        Machine::parse(&source, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// Parses a machine file, resolving relative paths from `base`.
+    pub fn parse(source: &str, base: &Path) -> Result<Machine, MachineError> {
+        let mut machine: Machine = toml::from_str(source).map_err(MachineError::Parse)?;
+
+        let mut used: Vec<u16> = Vec::new();
+        for (i, peripheral) in machine.peripherals.iter_mut().enumerate() {
+            let error = |message: String| MachineError::Invalid {
+                peripheral: i + 1,
+                message,
+            };
+
+            match (&mut peripheral.path, &peripheral.builtin) {
+                (Some(_), Some(_)) | (None, None) => {
+                    return Err(error(
+                        "each peripheral needs either a `path` or a `builtin`".to_owned(),
+                    ))
+                }
+                (Some(_), None) if !peripheral.options.is_empty() => {
+                    return Err(error(
+                        "`options` can only be given to built-in peripherals".to_owned(),
+                    ))
+                }
+                (Some(path), None) => *path = base.join(&*path),
+                (None, Some(name)) => {
+                    for (key, value) in peripheral.options.iter_mut() {
+                        if let OptionValue::Str(value) = value {
+                            if PATH_OPTIONS.contains(&(name.as_str(), key.as_str())) {
+                                *value = base.join(&value).display().to_string();
+                            }
+                            if value.contains([',', '=']) {
+                                return Err(error(format!(
+                                    "option `{key}` can't contain `,` or `=`"
+                                )));
+                            }
+                        }
+                    }
+                }
+            }
+
+            for &addr in &peripheral.ports {
+                if !(0xFFC0..=0xFFFB).contains(&addr) {
+                    return Err(error(format!(
+                        "peripherals can only be connected to 0xFFC0 through 0xFFFB, found {addr:#06X}"
+                    )));
+                }
+                if used.contains(&addr) {
+                    return Err(error(format!("port {addr:#06X} is already in use")));
+                }
+                used.push(addr);
+            }
+        }
+
+        Ok(machine)
+    }
+}
+
+impl Peripheral {
+    /// The path passed to `LOAD` to attach this peripheral.
+    pub fn spec(&self) -> String {
+        match (&self.path, &self.builtin) {
+            (Some(path), _) => path.display().to_string(),
+            (None, name) => {
+                let mut spec = format!("builtin:{}", name.as_deref().unwrap_or_default());
+                for (i, (key, value)) in self.options.iter().enumerate() {
+                    spec.push(if i == 0 { ':' } else { ',' });
+                    spec.push_str(&format!("{key}={value}"));
+                }
+                spec
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specs(machine: &Machine) -> Vec<(String, Vec<u16>)> {
+        machine
+            .peripherals
+            .iter()
+            .map(|peripheral| (peripheral.spec(), peripheral.ports.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn parse() {
+        let machine = Machine::parse(
+            r#"
+                # A serial console, a disk, and a timer
+                [[peripheral]]
+                builtin = "uart"
+                ports = [0xFFD0, 0xFFD1] # data and status
+                options = { backend = "file", in = "input.txt" }
+
+                [[peripheral]]
+                builtin = "disk"
+                ports = [0xFFD2, 0xFFD3, 0xFFD4, 0xFFD5]
+                options = { path = "disk.img", readonly = true }
+
+                [[peripheral]]
+                builtin = "timer"
+                ports = [
+                    0xFFE0, 0xFFE1, 0xFFE2, 0xFFE3,
+                    0xFFE4, 0xFFE5, 0xFFE6,
+                ]
+
+                [[peripheral]]
+                path = "libscreen.so"
+                ports = [65_520]
+            "#,
+            Path::new("boards"),
+        )
+        .unwrap();
+
+        let boards = |file: &str| Path::new("boards").join(file).display().to_string();
+        assert_eq!(
+            specs(&machine),
+            vec![
+                (
+                    format!("builtin:uart:backend=file,in={}", boards("input.txt")),
+                    vec![0xFFD0, 0xFFD1]
+                ),
+                (
+                    format!("builtin:disk:path={},readonly=true", boards("disk.img")),
+                    (0xFFD2..=0xFFD5).collect()
+                ),
+                ("builtin:timer".to_owned(), (0xFFE0..=0xFFE6).collect()),
+                (boards("libscreen.so"), vec![0xFFF0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source: &str| match Machine::parse(source, Path::new("")) {
+            Err(MachineError::Parse(_)) => None,
+            Err(MachineError::Invalid { peripheral, .. }) => Some(peripheral),
+            other => panic!("expected an error, found {other:?}"),
+        };
+
+        assert_eq!(error("builtin = \"latch\""), None);
+        assert_eq!(error("[[peripheral]]\nbuiltin = \"latch\"\n"), None);
+        assert_eq!(
+            error("[[peripheral]]\nbuiltin = \"latch\"\nports = [0xFFFC]"),
+            Some(1)
+        );
+        assert_eq!(error("[[peripheral]]\n\nports = [0xFFD0\n"), None);
+        assert_eq!(
+            error("[[peripheral]]\nbuiltin = \"rng\"\nports = [0xFFD0]\n[[peripheral]]\nbuiltin = \"rng\"\nports = [0xFFD0]"),
+            Some(2)
+        );
+        assert_eq!(
+            error("[[peripheral]]\npath = \"a\"\nports = []\noptions = { a = 1 }"),
+            Some(1)
+        );
+        assert_eq!(error("[[board]]"), None);
+        assert_eq!(error("[[peripheral]] ports = []"), None);
+        assert_eq!(error("[[peripheral]]\nbuiltin = \"latch\nports = []"), None);
+        assert_eq!(
+            error("[[peripheral]]\nbuiltin = \"latch\"\nports = [0x1FFFF]"),
+            None
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! Memory-mapped peripherals, either built into the emulator or loaded from shared libraries.
+//!
+//! Built-in peripherals are selected with a path of the form `builtin:<name>[:<options>]`,
+//! where the options are a comma-separated list of `key=value` pairs.
+//! Any other path is loaded as a shared library.
+
+mod disk;
+mod dylib;
+mod keyboard;
+mod latch;
+mod rng;
+mod timer;
+mod uart;
+
+use std::{
+    fmt::{self, Write as _},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use disk::Disk;
+use dylib::Dylib;
+use keyboard::Keyboard;
+use latch::Latch;
+use rng::Rng;
+use timer::Timer;
+use uart::Uart;
+
+pub use disk::{mkdisk, DiskArgs, DiskError};
+
+/// Set once the REPL starts reading commands from stdin,
+/// so peripherals know not to read from it themselves.
+pub static STDIN_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Reserves stdin for the REPL.
+/// This has to happen before any peripherals are loaded, since they may start reading straight away.
+pub fn reserve_stdin() {
+    STDIN_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// A device connected to one or more ports in the memory-mapped I/O range.
+/// Each port is identified by its index in the list of ports the device was loaded with.
+pub trait Peripheral: fmt::Debug + Send {
+    /// The name displayed when the emulator is `DUMP`ed.
+    fn name(&self) -> &str;
+
+    /// Called whenever the CPU reads from port `n`, or it is `PEEK`ed at.
+    fn read(&mut self, n: u8) -> u8;
+
+    /// Called whenever the CPU writes `data` to port `n`, or it is `POKE`ed.
+    fn write(&mut self, n: u8, data: u8);
+
+    /// Called on the falling edge of every `divisor` clock cycles.
+    fn tick(&mut self) {}
+
+    /// The number of clock cycles between each call to `tick`,
+    /// or `0` if the peripheral doesn't need to be ticked at all.
+    /// This is only queried once, when the peripheral is loaded.
+    fn divisor(&self) -> u32 {
+        1
+    }
+
+    /// Whether the peripheral needs attention from the CPU,
+    /// which is reported through the emulator status port.
+    fn pending(&self) -> bool {
+        false
+    }
+
+    /// Extra state to display for the peripheral when the emulator is `DUMP`ed.
+    fn debug_info(&self) -> Option<String> {
+        None
+    }
+
+    /// Called whenever the emulator resets the CPU.
+    fn reset(&mut self) {}
+}
+
+/// The `key=value` options given to a built-in peripheral.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Options {
+    pairs: Vec<(String, String)>,
+}
+
+impl Options {
+    /// Parses the value of `key`, returning `None` if it wasn't given.
+    pub fn get<T: FromStr>(&self, key: &str) -> Result<Option<T>, String> {
+        match self.pairs.iter().find(|(k, _)| k == key) {
+            Some((_, value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid value `{value}` for option `{key}`")),
+            None => Ok(None),
+        }
+    }
+}
+
+impl FromStr for Options {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pairs = Vec::new();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected `key=value`, found `{pair}`"))?;
+            pairs.push((key.trim().to_owned(), value.trim().to_owned()));
+        }
+
+        Ok(Options { pairs })
+    }
+}
+
+/// Creates a built-in peripheral from its options and the number of ports it's connected to.
+type Create = fn(&Options, u8) -> Result<Box<dyn Peripheral>, String>;
+
+struct Builtin {
+    name: &'static str,
+    description: &'static str,
+    /// The options accepted by the peripheral, and what each one does.
+    options: &'static [(&'static str, &'static str)],
+    create: Create,
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "disk",
+        description: "a block storage device backed by a disk image",
+        options: &[
+            ("path", "the disk image, created with `fateful mkdisk`"),
+            ("readonly", "`true` to prevent writes (default `false`)"),
+        ],
+        create: Disk::create,
+    },
+    Builtin {
+        name: "keyboard",
+        description:
+            "keys typed into the text buffer window, connected to a data port and a status port",
+        options: &[("mode", "`ascii` or `scancode` (default `ascii`)")],
+        create: Keyboard::create,
+    },
+    Builtin {
+        name: "latch",
+        description: "stores the last value written to each port",
+        options: &[(
+            "initial",
+            "the value each port holds after a reset (default 0)",
+        )],
+        create: Latch::create,
+    },
+    Builtin {
+        name: "rng",
+        description: "reads return random bytes, and writes reseed the generator",
+        options: &[("seed", "the initial seed (default is based on the time)")],
+        create: Rng::create,
+    },
+    Builtin {
+        name: "timer",
+        description: "a countdown timer with a prescaler, compare value, and overflow flag",
+        options: &[],
+        create: Timer::create,
+    },
+    Builtin {
+        name: "uart",
+        description: "a serial port, connected to a data port and a status port",
+        options: &[
+            ("backend", "`stdio`, `file`, or `pty` (default `stdio`)"),
+            ("in", "file to receive input from, with `backend=file`"),
+            ("out", "file to transmit output to, with `backend=file`"),
+        ],
+        create: Uart::create,
+    },
+];
+
+/// Creates the peripheral at `path`, connected to `ports` ports.
+/// Paths starting with `builtin:` select a built-in peripheral, while anything else is loaded as a shared library.
+pub fn load(path: &str, ports: u8) -> Result<Box<dyn Peripheral>, String> {
+    let Some(spec) = path.strip_prefix("builtin:") else {
+        return Ok(Box::new(Dylib::load(path, ports)?));
+    };
+
+    let (name, options) = spec.split_once(':').unwrap_or((spec, ""));
+    let name = name.trim_end_matches(',');
+    let builtin = BUILTINS
+        .iter()
+        .find(|builtin| builtin.name == name)
+        .ok_or_else(|| format!("unknown built-in peripheral `{name}`"))?;
+
+    let options: Options = options.parse()?;
+    for (key, _) in options.pairs.iter() {
+        if !builtin.options.iter().any(|(option, _)| option == key) {
+            return Err(format!("unknown option `{key}` for `builtin:{name}`"));
+        }
+    }
+
+    (builtin.create)(&options, ports)
+}
+
+/// Lists the built-in peripherals and their options, for `HELP LOAD`.
+pub fn builtins() -> String {
+    // Writing to a `String` is infallible
+    let mut help = String::new();
+    for builtin in BUILTINS {
+        let _ = writeln!(
+            help,
+            "builtin:{:<16}: {}",
+            builtin.name, builtin.description
+        );
+        for (option, description) in builtin.options {
+            let _ = writeln!(help, "    {option:<20}: {description}");
+        }
+    }
+
+    help
+}
+
+#[derive(Debug)]
+pub struct Device {
+    pub peripheral: Box<dyn Peripheral>,
+    /// Path the peripheral was loaded from.
+    pub path: String,
+    divisor: u32,
+    /// Clock cycles left until the peripheral is next ticked.
+    countdown: u32,
+}
+
+impl Device {
+    pub fn new(peripheral: Box<dyn Peripheral>, path: String) -> Device {
+        let divisor = peripheral.divisor();
+
+        Device {
+            peripheral,
+            path,
+            divisor,
+            countdown: divisor,
+        }
+    }
+
+    /// Counts a single clock cycle, ticking the peripheral once every `divisor` cycles.
+    pub fn tick(&mut self) {
+        if self.divisor == 0 {
+            return;
+        }
+
+        self.countdown -= 1;
+        if self.countdown == 0 {
+            self.countdown = self.divisor;
+            self.peripheral.tick();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.countdown = self.divisor;
+        self.peripheral.reset();
+    }
+}
+
+/// A single port of a device, which may be connected to several ports.
+#[derive(Debug, Clone)]
+pub struct Port {
+    pub device: Arc<Mutex<Device>>,
+    /// Index of the port in the device.
+    pub n: u8,
+}
+
+impl Port {
+    pub fn device(&self) -> MutexGuard<'_, Device> {
+        // A panicking peripheral can't leave the emulator in an invalid state
+        self.device.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn read(&self) -> u8 {
+        self.device().peripheral.read(self.n)
+    }
+
+    pub fn write(&self, data: u8) {
+        self.device().peripheral.write(self.n, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emulator::{display::TextBuffer, init_state, Machine, State, Status, STATUS},
+        symbols::Symbols,
+    };
+
+    #[test]
+    fn options() {
+        let options: Options = "seed=42, mode=fast,".parse().unwrap();
+        assert_eq!(options.get::<u64>("seed"), Ok(Some(42)));
+        assert_eq!(options.get::<String>("mode"), Ok(Some("fast".to_owned())));
+        assert_eq!(options.get::<u8>("missing"), Ok(None));
+        assert!(options.get::<u8>("mode").is_err());
+        assert!("seed".parse::<Options>().is_err());
+    }
+
+    #[test]
+    fn load_builtins() {
+        let mut latch = load("builtin:latch:initial=3", 2).unwrap();
+        assert_eq!(latch.name(), "latch");
+        assert_eq!(latch.read(1), 3);
+        latch.write(1, 9);
+        assert_eq!((latch.read(0), latch.read(1)), (3, 9));
+        latch.reset();
+        assert_eq!(latch.read(1), 3);
+
+        assert_eq!(latch.divisor(), 0);
+
+        assert!(load("builtin:rng:seed=1", 1).is_ok());
+        assert!(load("builtin:rng:speed=1", 1).is_err());
+        assert!(load("builtin:missing", 1).is_err());
+    }
+
+    /// Counts its ticks, and needs attention once it's been ticked 5 times.
+    #[derive(Debug)]
+    struct Counter {
+        ticks: u8,
+        divisor: u32,
+    }
+
+    impl Peripheral for Counter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn read(&mut self, _n: u8) -> u8 {
+            self.ticks
+        }
+
+        fn write(&mut self, _n: u8, _data: u8) {}
+
+        fn tick(&mut self) {
+            self.ticks += 1;
+        }
+
+        fn divisor(&self) -> u32 {
+            self.divisor
+        }
+
+        fn pending(&self) -> bool {
+            self.ticks >= 5
+        }
+    }
+
+    #[test]
+    fn divisor() {
+        let counter = Counter {
+            ticks: 0,
+            divisor: 3,
+        };
+        let mut device = Device::new(Box::new(counter), "counter".to_owned());
+        for _ in 0..10 {
+            device.tick();
+        }
+        assert_eq!(device.peripheral.read(0), 3);
+
+        // Resetting starts the count over
+        device.tick();
+        device.reset();
+        device.tick();
+        device.tick();
+        assert_eq!(device.peripheral.read(0), 3);
+        device.tick();
+        assert_eq!(device.peripheral.read(0), 4);
+    }
+
+    #[test]
+    fn shared_device() {
+        let mut state = State::init(
+            vec![0; 1 << 16].into_boxed_slice(),
+            Symbols::new(),
+            TextBuffer::headless(),
+        );
+        let counter = Counter {
+            ticks: 0,
+            divisor: 1,
+        };
+        let device = Arc::new(Mutex::new(Device::new(Box::new(counter), String::new())));
+        for n in 0..4 {
+            let port = Port {
+                device: device.clone(),
+                n,
+            };
+            state.peripherals.insert(0x10 + n, port);
+        }
+        state.devices.push(device);
+
+        for _ in 0..4 {
+            state.tick();
+        }
+        // Devices are ticked once per clock, no matter how many ports they're connected to
+        assert_eq!(state.read_bus(0xFFD3), 4);
+        assert_eq!(state.read_bus(STATUS), 0x00);
+
+        state.tick();
+        assert_eq!(state.read_bus(STATUS), Status::ATTN.bits());
+
+        assert!(state.drop_port(0x10));
+        assert_eq!(state.devices.len(), 1);
+        state.drop_port(0x11);
+        state.drop_port(0x12);
+        state.drop_port(0x13);
+        assert!(state.devices.is_empty());
+    }
+
+    #[test]
+    fn reload() {
+        let mut state = State::init(
+            vec![0; 1 << 16].into_boxed_slice(),
+            Symbols::new(),
+            TextBuffer::headless(),
+        );
+        state
+            .load("builtin:latch:initial=7".to_owned(), vec![0x12, 0x10])
+            .unwrap();
+        state.write_bus(0xFFD0, 1);
+        state.write_bus(0xFFD2, 2);
+        assert!(state.to_string().contains("\"latch\": DATA: [0x02, 0x01]"));
+
+        // Reloading starts the peripheral over on the same ports, in the same order
+        assert_eq!(
+            state.reload(0x10),
+            Ok(Some("builtin:latch:initial=7".to_owned()))
+        );
+        assert_eq!((state.read_bus(0xFFD0), state.read_bus(0xFFD2)), (7, 7));
+        assert_eq!(state.devices.len(), 1);
+        state.write_bus(0xFFD2, 2);
+        assert_eq!(state.peripherals[&0x12].n, 0);
+        assert_eq!(state.devices[0].lock().unwrap().peripheral.read(0), 2);
+
+        assert_eq!(state.reload(0x11), Ok(None));
+    }
+
+    #[test]
+    fn reserved_stdin() {
+        let machine = Machine::parse(
+            "[[peripheral]]\nbuiltin = \"uart\"\nports = [0xFFD0, 0xFFD1]\n",
+            std::path::Path::new(""),
+        )
+        .unwrap();
+        let state = init_state(
+            vec![0; 1 << 16].into_boxed_slice(),
+            Symbols::new(),
+            TextBuffer::headless(),
+            Some(&machine),
+            true,
+        )
+        .unwrap();
+
+        // The UART is connected, but leaves stdin to the REPL
+        assert_eq!(state.devices.len(), 1);
+        assert!(!uart::reading_stdin());
+    }
+}
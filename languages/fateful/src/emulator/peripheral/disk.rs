@@ -0,0 +1,284 @@
+//! A block storage device, backed by a disk image on the host.
+//!
+//! Disk images are plain files made of 256 byte sectors, and can be created with `fateful mkdisk`.
+//! The disk is connected to four ports, in order:
+//!
+//! | Port | Register      | Description                                                          |
+//! |------|---------------|----------------------------------------------------------------------|
+//! | 0    | SECTOR high   | Selects the sector used by the next command                          |
+//! | 1    | SECTOR low    |                                                                      |
+//! | 2    | DATA          | Reads or writes the next byte of the sector buffer                   |
+//! | 3    | COMMAND       | Writes run a command, while reads return the status of the last one |
+//!
+//! The sector buffer is accessed one byte at a time through the DATA port,
+//! automatically moving to the next byte after each access.
+//! The position is moved back to the start of the buffer whenever a command is run.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use clap::Args;
+use thiserror::Error;
+
+use super::{Options, Peripheral};
+
+pub const SECTOR_SIZE: usize = 256;
+/// The largest disk a 16-bit sector number can address.
+const MAX_SECTORS: u32 = 1 << 16;
+
+const SECTOR_HIGH: u8 = 0;
+const SECTOR_LOW: u8 = 1;
+const DATA: u8 = 2;
+const COMMAND: u8 = 3;
+
+/// Reads the selected sector into the sector buffer.
+const READ: u8 = 0x01;
+/// Writes the sector buffer to the selected sector.
+const WRITE: u8 = 0x02;
+/// Moves back to the start of the sector buffer without accessing the disk.
+const REWIND: u8 = 0x03;
+
+/// Set in the status once the disk is ready for a command.
+const READY: u8 = 1 << 0;
+/// Set in the status if the last command failed.
+const ERROR: u8 = 1 << 1;
+/// Set in the status if the disk can't be written to.
+const READ_ONLY: u8 = 1 << 2;
+
+#[derive(Debug)]
+pub(super) struct Disk {
+    file: File,
+    sectors: u32,
+    read_only: bool,
+    sector: u16,
+    buffer: [u8; SECTOR_SIZE],
+    /// Position of the next DATA access in the sector buffer.
+    position: u8,
+    error: bool,
+}
+
+impl Disk {
+    pub fn create(options: &Options, ports: u8) -> Result<Box<dyn Peripheral>, String> {
+        if ports != 4 {
+            return Err(format!("expected `4` connected ports, found `{ports}`"));
+        }
+
+        let path = options
+            .get::<String>("path")?
+            .ok_or("expected a disk image, given with the `path` option")?;
+        let read_only = options.get::<bool>("readonly")?.unwrap_or(false);
+
+        let file = File::options()
+            .read(true)
+            .write(!read_only)
+            .open(&path)
+            .map_err(|err| format!("unable to open disk image {path}: {err}"))?;
+        let len = file
+            .metadata()
+            .map_err(|err| format!("unable to open disk image {path}: {err}"))?
+            .len();
+        if len % SECTOR_SIZE as u64 != 0 {
+            return Err(format!(
+                "disk image {path} is not a whole number of {SECTOR_SIZE} byte sectors"
+            ));
+        }
+
+        Ok(Box::new(Disk {
+            file,
+            sectors: (len / SECTOR_SIZE as u64).min(MAX_SECTORS as u64) as u32,
+            read_only,
+            sector: 0,
+            buffer: [0; SECTOR_SIZE],
+            position: 0,
+            error: false,
+        }))
+    }
+
+    fn run(&mut self, command: u8) -> io::Result<bool> {
+        self.position = 0;
+
+        match command {
+            READ | WRITE if self.sector as u32 >= self.sectors => Ok(false),
+            READ => {
+                self.file
+                    .seek(SeekFrom::Start(self.sector as u64 * SECTOR_SIZE as u64))?;
+                self.file.read_exact(&mut self.buffer)?;
+                Ok(true)
+            }
+            WRITE if self.read_only => Ok(false),
+            WRITE => {
+                self.file
+                    .seek(SeekFrom::Start(self.sector as u64 * SECTOR_SIZE as u64))?;
+                self.file.write_all(&self.buffer)?;
+                Ok(true)
+            }
+            REWIND => Ok(true),
+            _ => Ok(false),
+        }
+    }
+}
+
+impl Peripheral for Disk {
+    fn name(&self) -> &str {
+        "disk"
+    }
+
+    fn divisor(&self) -> u32 {
+        0
+    }
+
+    fn read(&mut self, n: u8) -> u8 {
+        match n {
+            SECTOR_HIGH => (self.sector >> 8) as u8,
+            SECTOR_LOW => (self.sector & 0xFF) as u8,
+            DATA => {
+                let data = self.buffer[self.position as usize];
+                self.position = self.position.wrapping_add(1);
+                data
+            }
+            COMMAND => {
+                let mut status = READY;
+                if self.error {
+                    status |= ERROR;
+                }
+                if self.read_only {
+                    status |= READ_ONLY;
+                }
+                status
+            }
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, n: u8, data: u8) {
+        match n {
+            SECTOR_HIGH => self.sector = (self.sector & 0x00FF) | ((data as u16) << 8),
+            SECTOR_LOW => self.sector = (self.sector & 0xFF00) | (data as u16),
+            DATA => {
+                self.buffer[self.position as usize] = data;
+                self.position = self.position.wrapping_add(1);
+            }
+            COMMAND => self.error = !self.run(data).unwrap_or(false),
+            _ => {}
+        }
+    }
+
+    fn debug_info(&self) -> Option<String> {
+        Some(format!(
+            "SECTOR: {:#06X} (of {}), POSITION: {:#04X}, ERROR: {}",
+            self.sector, self.sectors, self.position, self.error,
+        ))
+    }
+
+    fn reset(&mut self) {
+        self.sector = 0;
+        self.buffer = [0; SECTOR_SIZE];
+        self.position = 0;
+        self.error = false;
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct DiskArgs {
+    /// Path to create the disk image at
+    output: PathBuf,
+    /// Number of 256 byte sectors in the disk
+    #[clap(short, long, default_value = "256", value_parser = clap::value_parser!(u32).range(1..=MAX_SECTORS as i64))]
+    sectors: u32,
+    /// File to copy to the start of the disk, such as an assembled program
+    #[clap(short, long)]
+    input: Option<PathBuf>,
+    /// Overwrite the disk image if it already exists
+    #[clap(short, long)]
+    force: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum DiskError {
+    #[error("unable to read provided input: {0}")]
+    Input(io::Error),
+    #[error("unable to write disk image: {0}")]
+    Output(io::Error),
+    #[error("{0} already exists (use `--force` to overwrite it)")]
+    Exists(String),
+    #[error("input is {0} bytes, which doesn't fit on the disk")]
+    TooLarge(usize),
+}
+
+/// Creates a zeroed disk image, optionally starting with the contents of a file.
+pub fn mkdisk(args: DiskArgs) -> Result<(), DiskError> {
+    if args.output.exists() && !args.force {
+        return Err(DiskError::Exists(args.output.display().to_string()));
+    }
+
+    let mut image = vec![0; args.sectors as usize * SECTOR_SIZE];
+    if let Some(input) = args.input {
+        let contents = fs::read(input).map_err(|err| DiskError::Input(err))?;
+        if contents.len() > image.len() {
+            return Err(DiskError::TooLarge(contents.len()));
+        }
+        image[..contents.len()].copy_from_slice(&contents);
+    }
+
+    fs::write(&args.output, image).map_err(|err| DiskError::Output(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sectors() {
+        let dir = std::env::temp_dir().join(format!("fateful-disk-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("disk.img");
+        mkdisk(DiskArgs {
+            output: image.clone(),
+            sectors: 2,
+            input: None,
+            force: false,
+        })
+        .unwrap();
+
+        let options = format!("path={}", image.display()).parse().unwrap();
+        let mut disk = Disk::create(&options, 4).unwrap();
+
+        // Write a pattern to the second sector
+        disk.write(SECTOR_LOW, 1);
+        for byte in 0..=255 {
+            disk.write(DATA, byte);
+        }
+        disk.write(COMMAND, WRITE);
+        assert_eq!(disk.read(COMMAND), READY);
+
+        // Reading it back starts from the beginning of the buffer
+        disk.reset();
+        disk.write(SECTOR_LOW, 1);
+        disk.write(COMMAND, READ);
+        assert_eq!((disk.read(DATA), disk.read(DATA)), (0, 1));
+
+        // Sectors past the end of the disk can't be accessed
+        disk.write(SECTOR_LOW, 2);
+        disk.write(COMMAND, READ);
+        assert_eq!(disk.read(COMMAND), READY | ERROR);
+        drop(disk);
+
+        let contents = fs::read(&image).unwrap();
+        assert_eq!(contents.len(), 2 * SECTOR_SIZE);
+        assert_eq!(contents[SECTOR_SIZE + 0x2A], 0x2A);
+
+        assert!(matches!(
+            mkdisk(DiskArgs {
+                output: image,
+                sectors: 1,
+                input: None,
+                force: false,
+            }),
+            Err(DiskError::Exists(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
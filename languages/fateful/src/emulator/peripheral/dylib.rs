@@ -0,0 +1,249 @@
+//! Peripherals loaded from shared libraries through the C ABI described in the README.
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+
+use libloading::Library;
+
+use super::Peripheral;
+
+type NameFn = unsafe extern "C" fn() -> *const c_char;
+type InitFn = unsafe extern "C" fn(u8) -> c_int;
+type StatefulInitFn = unsafe extern "C" fn(u8) -> *mut c_void;
+type ReadFn = unsafe extern "C" fn(u8) -> u8;
+type StatefulReadFn = unsafe extern "C" fn(*mut c_void, u8) -> u8;
+type WriteFn = unsafe extern "C" fn(u8, u8);
+type StatefulWriteFn = unsafe extern "C" fn(*mut c_void, u8, u8);
+type TickFn = unsafe extern "C" fn();
+type StatefulTickFn = unsafe extern "C" fn(*mut c_void);
+type TickDivisorFn = unsafe extern "C" fn() -> u32;
+type StatefulTickDivisorFn = unsafe extern "C" fn(*mut c_void) -> u32;
+type PendingFn = unsafe extern "C" fn() -> u8;
+type StatefulPendingFn = unsafe extern "C" fn(*mut c_void) -> u8;
+type DebugInfoFn = unsafe extern "C" fn() -> *const c_char;
+type StatefulDebugInfoFn = unsafe extern "C" fn(*mut c_void) -> *const c_char;
+type DropFn = unsafe extern "C" fn();
+type StatefulDropFn = unsafe extern "C" fn(*mut c_void);
+type ResetFn = unsafe extern "C" fn();
+type StatefulResetFn = unsafe extern "C" fn(*mut c_void);
+type LastErrLen = unsafe extern "C" fn() -> c_int;
+type GetLastErr = unsafe extern "C" fn(*mut c_char, c_int) -> c_int;
+
+#[derive(Debug)]
+pub(super) struct Dylib {
+    name: String,
+    library: Library,
+    state: Option<*mut c_void>,
+    divisor: u32,
+}
+
+impl Dylib {
+    /// Loads and initializes the library at `path`, connected to `ports` ports.
+    pub fn load(path: &str, ports: u8) -> Result<Dylib, String> {
+        unsafe {
+            let library = Library::new(path)
+                .map_err(|err| format!("unable to load peripheral library: {err}"))?;
+
+            let name = match library.get::<NameFn>(b"name") {
+                Ok(name) => CStr::from_ptr(name()).to_str().ok().map(|s| s.to_owned()),
+                Err(_) => None,
+            }
+            .unwrap_or_else(|| path.to_owned());
+
+            let (state, err) =
+                if let Ok(stateful_init) = library.get::<StatefulInitFn>(b"stateful_init") {
+                    let state = stateful_init(ports);
+                    (Some(state), if state.is_null() { -1 } else { 0 })
+                } else if let Ok(init) = library.get::<InitFn>(b"init") {
+                    let err = init(ports);
+                    (None, err)
+                } else {
+                    (None, 0)
+                };
+
+            if err != 0 {
+                let msg: Result<(Vec<u8>, c_int), libloading::Error> = library
+                    .get::<LastErrLen>(b"last_error_length")
+                    .map(|lel| vec![0; lel() as usize])
+                    .and_then(|mut buf| {
+                        let written = library.get::<GetLastErr>(b"get_last_error")?(
+                            buf.as_mut_ptr() as *mut c_char,
+                            buf.len() as c_int,
+                        );
+                        Ok((buf, written))
+                    });
+
+                if let Ok((buffer, 1..)) = msg {
+                    if let Ok(msg) = CStr::from_bytes_with_nul_unchecked(&buffer).to_str() {
+                        return Err(msg.to_owned());
+                    }
+                }
+
+                return Err(match state {
+                    Some(_) => {
+                        "initialization failed (`stateful_init` returned a null pointer)".to_owned()
+                    }
+                    None => format!("initialization failed (`init` returned with exit code {err})"),
+                });
+            }
+
+            let divisor = match state {
+                Some(state) => library
+                    .get::<StatefulTickDivisorFn>(b"stateful_tick_divisor")
+                    .map(|divisor| divisor(state)),
+                None => library
+                    .get::<TickDivisorFn>(b"tick_divisor")
+                    .map(|divisor| divisor()),
+            }
+            .unwrap_or(1);
+
+            Ok(Dylib {
+                name,
+                library,
+                state,
+                divisor,
+            })
+        }
+    }
+}
+
+impl Peripheral for Dylib {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read(&mut self, n: u8) -> u8 {
+        unsafe {
+            if let Ok(stateful_read) = self.library.get::<StatefulReadFn>(b"stateful_read") {
+                match self.state {
+                    Some(state) => stateful_read(state, n),
+                    None => {
+                        eprintln!("PERIPHERAL ERROR: unable to call `stateful_read` (state was not initialized)");
+                        0x00
+                    }
+                }
+            } else if let Ok(read) = self.library.get::<ReadFn>(b"read") {
+                read(n)
+            } else {
+                eprintln!("PERIPHERAL ERROR: `read` and `stateful_read` not present in peripheral (peripherals must implement one of these)");
+                0x00
+            }
+        }
+    }
+
+    fn write(&mut self, n: u8, data: u8) {
+        unsafe {
+            if let Ok(stateful_write) = self.library.get::<StatefulWriteFn>(b"stateful_write") {
+                match self.state {
+                    Some(state) => stateful_write(state, n, data),
+                    None => {
+                        eprintln!("PERIPHERAL ERROR: unable to call `stateful_write` (state was not initialized)");
+                    }
+                }
+            } else if let Ok(write) = self.library.get::<WriteFn>(b"write") {
+                write(n, data);
+            } else {
+                eprintln!("PERIPHERAL ERROR: `write` and `stateful_write` not present in peripheral (peripherals must implement one of these)");
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        unsafe {
+            if let Ok(stateful_tick) = self.library.get::<StatefulTickFn>(b"stateful_tick") {
+                match self.state {
+                    Some(state) => stateful_tick(state),
+                    None => {
+                        eprintln!("PERIPHERAL ERROR: unable to call `stateful_tick` (state was not initialized)");
+                    }
+                }
+            } else if let Ok(stateless_tick) = self.library.get::<TickFn>(b"tick") {
+                stateless_tick();
+            }
+        }
+    }
+
+    fn divisor(&self) -> u32 {
+        self.divisor
+    }
+
+    fn pending(&self) -> bool {
+        unsafe {
+            if let Ok(stateful_pending) = self.library.get::<StatefulPendingFn>(b"stateful_pending")
+            {
+                match self.state {
+                    Some(state) => stateful_pending(state) != 0,
+                    None => {
+                        eprintln!("PERIPHERAL ERROR: unable to call `stateful_pending` (state was not initialized)");
+                        false
+                    }
+                }
+            } else if let Ok(pending) = self.library.get::<PendingFn>(b"pending") {
+                pending() != 0
+            } else {
+                false
+            }
+        }
+    }
+
+    fn debug_info(&self) -> Option<String> {
+        unsafe {
+            let info = if let Ok(stateful_debug_info) = self
+                .library
+                .get::<StatefulDebugInfoFn>(b"stateful_debug_info")
+            {
+                match self.state {
+                    Some(state) => stateful_debug_info(state),
+                    None => {
+                        eprintln!("PERIPHERAL ERROR: unable to call `stateful_debug_info` (state was not initialized)");
+                        return None;
+                    }
+                }
+            } else if let Ok(debug_info) = self.library.get::<DebugInfoFn>(b"debug_info") {
+                debug_info()
+            } else {
+                return None;
+            };
+
+            if info.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(info).to_string_lossy().into_owned())
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        unsafe {
+            if let Ok(stateful_reset) = self.library.get::<StatefulResetFn>(b"stateful_reset") {
+                match self.state {
+                    Some(state) => stateful_reset(state),
+                    None => {
+                        eprintln!("PERIPHERAL ERROR: unable to call `stateful_reset` (state was not initialized)");
+                    }
+                }
+            } else if let Ok(reset) = self.library.get::<ResetFn>(b"reset") {
+                reset();
+            }
+        }
+    }
+}
+
+impl Drop for Dylib {
+    fn drop(&mut self) {
+        unsafe {
+            if let Ok(stateful_drop) = self.library.get::<StatefulDropFn>(b"stateful_drop") {
+                match self.state {
+                    Some(state) => stateful_drop(state),
+                    None => {
+                        eprintln!("PERIPHERAL ERROR: unable to call `stateful_drop` (state was not initialized)");
+                    }
+                }
+            } else if let Ok(stateless_drop) = self.library.get::<DropFn>(b"drop") {
+                stateless_drop();
+            }
+        }
+    }
+}
+
+// We can do this since `state` is only ever accessed by one thread at a time.
+unsafe impl Send for Dylib {}
@@ -0,0 +1,156 @@
+//! A keyboard, reading the keys typed into the emulator's text buffer window.
+//!
+//! The keyboard is connected to two ports.
+//! Reading the data port (the first port) pops the next key, or `0x00` if there isn't one.
+//! The status port (the second port) has bit 0 set while there is a key waiting.
+//!
+//! Keys are either read as characters in code-page 737 (the default),
+//! or as the set 1 scancodes sent when each key is pressed and released.
+
+use std::{
+    collections::VecDeque,
+    sync::{MutexGuard, PoisonError},
+};
+
+use super::{Options, Peripheral};
+use crate::emulator::display::{Keys, KEYS};
+
+const DATA: u8 = 0;
+const STATUS: u8 = 1;
+
+/// Set in the status port while a key is waiting.
+const READY: u8 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Ascii,
+    Scancode,
+}
+
+#[derive(Debug)]
+pub(super) struct Keyboard {
+    mode: Mode,
+}
+
+impl Keyboard {
+    pub fn create(options: &Options, ports: u8) -> Result<Box<dyn Peripheral>, String> {
+        if ports != 2 {
+            return Err(format!(
+                "expected `2` connected ports (data and status), found `{ports}`"
+            ));
+        }
+
+        let mode = match options.get::<String>("mode")?.as_deref() {
+            None | Some("ascii") => Mode::Ascii,
+            Some("scancode") => Mode::Scancode,
+            Some(mode) => {
+                return Err(format!(
+                    "unknown mode `{mode}` (expected `ascii` or `scancode`)"
+                ))
+            }
+        };
+
+        let mut keyboard = Keyboard { mode };
+        // Anything typed before the keyboard was plugged in is lost
+        keyboard.reset();
+        Ok(Box::new(keyboard))
+    }
+
+    fn keys(&self) -> MutexGuard<'static, Keys> {
+        KEYS.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn queue<'a>(&self, keys: &'a mut Keys) -> &'a mut VecDeque<u8> {
+        match self.mode {
+            Mode::Ascii => &mut keys.chars,
+            Mode::Scancode => &mut keys.scancodes,
+        }
+    }
+}
+
+impl Peripheral for Keyboard {
+    fn name(&self) -> &str {
+        "keyboard"
+    }
+
+    fn divisor(&self) -> u32 {
+        0
+    }
+
+    fn read(&mut self, n: u8) -> u8 {
+        let mut keys = self.keys();
+        let queue = self.queue(&mut keys);
+
+        match n {
+            DATA => queue.pop_front().unwrap_or(0x00),
+            STATUS => match queue.is_empty() {
+                true => 0x00,
+                false => READY,
+            },
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, _n: u8, _data: u8) {}
+
+    fn pending(&self) -> bool {
+        !self.queue(&mut self.keys()).is_empty()
+    }
+
+    fn debug_info(&self) -> Option<String> {
+        Some(format!(
+            "MODE: {:?}, BUFFERED: {} byte(s) waiting",
+            self.mode,
+            self.queue(&mut self.keys()).len()
+        ))
+    }
+
+    fn reset(&mut self) {
+        self.queue(&mut self.keys()).clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use minifb::Key;
+
+    use super::*;
+
+    #[test]
+    fn typing() {
+        let mut ascii = Keyboard::create(&Options::default(), 2).unwrap();
+        let mut scancodes = Keyboard::create(&"mode=scancode".parse().unwrap(), 2).unwrap();
+        assert!(!ascii.pending());
+        assert_eq!(ascii.read(STATUS), 0x00);
+
+        {
+            let mut keys = KEYS.lock().unwrap();
+            keys.type_char('h');
+            keys.type_char('λ');
+            // Characters outside of the code page are dropped
+            keys.type_char('€');
+            keys.press(Key::H, true);
+            keys.press(Key::H, false);
+            keys.press(Key::Up, true);
+        }
+
+        assert!(ascii.pending());
+        assert_eq!(ascii.read(STATUS), READY);
+        assert_eq!((ascii.read(DATA), ascii.read(DATA)), (b'h', 0xA2));
+        assert_eq!(ascii.read(DATA), 0x00);
+        assert!(!ascii.pending());
+
+        let codes: Vec<u8> = (0..4).map(|_| scancodes.read(DATA)).collect();
+        assert_eq!(codes, [0x23, 0xA3, 0xE0, 0x48]);
+        assert_eq!(scancodes.read(STATUS), 0x00);
+
+        // Keys typed faster than they're read are dropped
+        for _ in 0..32 {
+            KEYS.lock().unwrap().type_char('x');
+        }
+        assert_eq!((0..32).filter(|_| ascii.read(DATA) == b'x').count(), 16);
+
+        assert!(Keyboard::create(&"mode=utf8".parse().unwrap(), 2).is_err());
+        assert!(Keyboard::create(&Options::default(), 1).is_err());
+    }
+}
@@ -0,0 +1,50 @@
+use super::{Options, Peripheral};
+
+/// A byte of storage behind each port, holding the last value written to it.
+#[derive(Debug)]
+pub(super) struct Latch {
+    data: Vec<u8>,
+    initial: u8,
+}
+
+impl Latch {
+    pub fn create(options: &Options, ports: u8) -> Result<Box<dyn Peripheral>, String> {
+        let initial = options.get::<u8>("initial")?.unwrap_or(0);
+
+        Ok(Box::new(Latch {
+            data: vec![initial; ports as usize],
+            initial,
+        }))
+    }
+}
+
+impl Peripheral for Latch {
+    fn name(&self) -> &str {
+        "latch"
+    }
+
+    fn divisor(&self) -> u32 {
+        0
+    }
+
+    fn read(&mut self, n: u8) -> u8 {
+        self.data[n as usize]
+    }
+
+    fn write(&mut self, n: u8, data: u8) {
+        self.data[n as usize] = data;
+    }
+
+    fn debug_info(&self) -> Option<String> {
+        let data: Vec<String> = self
+            .data
+            .iter()
+            .map(|data| format!("{data:#04X}"))
+            .collect();
+        Some(format!("DATA: [{}]", data.join(", ")))
+    }
+
+    fn reset(&mut self) {
+        self.data.fill(self.initial);
+    }
+}
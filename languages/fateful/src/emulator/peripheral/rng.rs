@@ -0,0 +1,92 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Options, Peripheral};
+
+/// A pseudo-random number generator.
+/// Reading any port returns the next random byte,
+/// and writing to any port reseeds the generator with the written byte.
+#[derive(Debug)]
+pub(super) struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn create(options: &Options, _ports: u8) -> Result<Box<dyn Peripheral>, String> {
+        let seed = match options.get::<u64>("seed")? {
+            Some(seed) => seed,
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_nanos() as u64)
+                .unwrap_or_default(),
+        };
+
+        Ok(Box::new(Rng::new(seed)))
+    }
+
+    fn new(seed: u64) -> Rng {
+        Rng {
+            seed,
+            state: scramble(seed),
+        }
+    }
+}
+
+impl Peripheral for Rng {
+    fn name(&self) -> &str {
+        "rng"
+    }
+
+    fn divisor(&self) -> u32 {
+        0
+    }
+
+    fn read(&mut self, _n: u8) -> u8 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn write(&mut self, _n: u8, data: u8) {
+        self.state = scramble(data as u64);
+    }
+
+    fn reset(&mut self) {
+        self.state = scramble(self.seed);
+    }
+}
+
+/// Spreads the bits of `seed` out, since xorshift gets stuck on a state of zero.
+fn scramble(seed: u64) -> u64 {
+    // splitmix64
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    match z ^ (z >> 31) {
+        0 => 1,
+        z => z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded() {
+        let mut lhs = Rng::new(42);
+        let mut rhs = Rng::new(42);
+        let bytes: Vec<u8> = (0..16).map(|_| lhs.read(0)).collect();
+        assert_eq!(bytes, (0..16).map(|_| rhs.read(0)).collect::<Vec<u8>>());
+        assert!(bytes.iter().any(|byte| *byte != bytes[0]));
+
+        lhs.reset();
+        assert_eq!(bytes[0], lhs.read(0));
+
+        lhs.write(0, 7);
+        rhs.write(0, 7);
+        assert_eq!(lhs.read(0), rhs.read(0));
+    }
+}
@@ -0,0 +1,184 @@
+//! A programmable countdown timer.
+//!
+//! The timer is connected to seven ports, in order:
+//!
+//! | Port | Register     | Description                                                              |
+//! |------|--------------|--------------------------------------------------------------------------|
+//! | 0    | CTRL         | Bit 0 enables the timer, bit 1 reloads it on overflow, bit 2 raises ATTN |
+//! | 1    | STATUS       | Bit 0 is the overflow flag, bit 1 is the compare flag (write 1 to clear) |
+//! | 2    | PRESCALE     | The counter steps once every `PRESCALE + 1` clock cycles                 |
+//! | 3    | COUNT high   | Reads the counter, writes set both the counter and the reload value      |
+//! | 4    | COUNT low    |                                                                          |
+//! | 5    | COMPARE high | The compare flag is set when the counter steps to this value             |
+//! | 6    | COMPARE low  |                                                                          |
+
+use super::{Options, Peripheral};
+
+const CTRL: u8 = 0;
+const STATUS: u8 = 1;
+const PRESCALE: u8 = 2;
+const COUNT_HIGH: u8 = 3;
+const COUNT_LOW: u8 = 4;
+const COMPARE_HIGH: u8 = 5;
+const COMPARE_LOW: u8 = 6;
+
+/// Set in CTRL to enable counting.
+const ENABLE: u8 = 1 << 0;
+/// Set in CTRL to reload the counter when it overflows, instead of stopping.
+const RELOAD: u8 = 1 << 1;
+/// Set in CTRL to raise the attention bit while a flag is set.
+const IRQ: u8 = 1 << 2;
+
+/// Set in STATUS once the counter reaches zero.
+const OVERFLOW: u8 = 1 << 0;
+/// Set in STATUS once the counter reaches the compare value.
+const COMPARE: u8 = 1 << 1;
+
+#[derive(Debug, Default)]
+pub(super) struct Timer {
+    ctrl: u8,
+    status: u8,
+    prescale: u8,
+    /// Clock cycles since the counter last stepped.
+    cycles: u8,
+    count: u16,
+    reload: u16,
+    compare: u16,
+}
+
+impl Timer {
+    pub fn create(_options: &Options, ports: u8) -> Result<Box<dyn Peripheral>, String> {
+        if ports != 7 {
+            return Err(format!("expected `7` connected ports, found `{ports}`"));
+        }
+
+        Ok(Box::<Timer>::default())
+    }
+
+    fn step(&mut self) {
+        self.count = self.count.wrapping_sub(1);
+
+        if self.count == self.compare {
+            self.status |= COMPARE;
+        }
+
+        if self.count == 0 {
+            self.status |= OVERFLOW;
+            if self.ctrl & RELOAD != 0 {
+                self.count = self.reload;
+            } else {
+                self.ctrl &= !ENABLE;
+            }
+        }
+    }
+}
+
+impl Peripheral for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn read(&mut self, n: u8) -> u8 {
+        match n {
+            CTRL => self.ctrl,
+            STATUS => self.status,
+            PRESCALE => self.prescale,
+            COUNT_HIGH => (self.count >> 8) as u8,
+            COUNT_LOW => (self.count & 0xFF) as u8,
+            COMPARE_HIGH => (self.compare >> 8) as u8,
+            COMPARE_LOW => (self.compare & 0xFF) as u8,
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, n: u8, data: u8) {
+        match n {
+            CTRL => self.ctrl = data,
+            STATUS => self.status &= !data,
+            PRESCALE => {
+                self.prescale = data;
+                self.cycles = 0;
+            }
+            COUNT_HIGH => {
+                self.count = (self.count & 0x00FF) | ((data as u16) << 8);
+                self.reload = (self.reload & 0x00FF) | ((data as u16) << 8);
+            }
+            COUNT_LOW => {
+                self.count = (self.count & 0xFF00) | (data as u16);
+                self.reload = (self.reload & 0xFF00) | (data as u16);
+            }
+            COMPARE_HIGH => self.compare = (self.compare & 0x00FF) | ((data as u16) << 8),
+            COMPARE_LOW => self.compare = (self.compare & 0xFF00) | (data as u16),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.ctrl & ENABLE == 0 {
+            return;
+        }
+
+        if self.cycles < self.prescale {
+            self.cycles += 1;
+        } else {
+            self.cycles = 0;
+            self.step();
+        }
+    }
+
+    fn pending(&self) -> bool {
+        self.ctrl & IRQ != 0 && self.status & (OVERFLOW | COMPARE) != 0
+    }
+
+    fn debug_info(&self) -> Option<String> {
+        Some(format!(
+            "CTRL: {:#04X}, STATUS: {:#04X}, PRESCALE: {:#04X}, COUNT: {:#06X}, RELOAD: {:#06X}, COMPARE: {:#06X}",
+            self.ctrl, self.status, self.prescale, self.count, self.reload, self.compare,
+        ))
+    }
+
+    fn reset(&mut self) {
+        *self = Timer::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn countdown() {
+        let mut timer = Timer::default();
+        timer.write(PRESCALE, 1);
+        timer.write(COUNT_LOW, 3);
+        timer.write(COMPARE_LOW, 1);
+        timer.write(CTRL, ENABLE | RELOAD | IRQ);
+
+        // The counter steps every other cycle
+        for _ in 0..4 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(COUNT_LOW), 1);
+        assert_eq!(timer.read(STATUS), COMPARE);
+        assert!(timer.pending());
+
+        timer.write(STATUS, COMPARE);
+        assert!(!timer.pending());
+
+        for _ in 0..2 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(STATUS), OVERFLOW);
+        assert_eq!(timer.read(COUNT_LOW), 3);
+
+        // Without reloading, the timer stops once it overflows
+        timer.write(STATUS, OVERFLOW);
+        timer.write(CTRL, ENABLE);
+        for _ in 0..10 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(STATUS), OVERFLOW | COMPARE);
+        assert_eq!(timer.read(CTRL), 0);
+        assert_eq!(timer.read(COUNT_LOW), 0);
+    }
+}
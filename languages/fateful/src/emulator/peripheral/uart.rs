@@ -0,0 +1,353 @@
+//! A serial port, backed by stdio, files, or a pseudo-terminal.
+//!
+//! The UART is connected to two ports.
+//! Reading the data port (the first port) pops the next received byte, or `0x00` if there isn't one,
+//! and writing to it transmits a byte.
+//! The status port (the second port) has bit 0 set while there is a received byte waiting,
+//! and bit 1 set while the UART is ready to transmit.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, Receiver},
+        Mutex, OnceLock, PoisonError,
+    },
+    thread,
+};
+
+use super::{Options, Peripheral, STDIN_RESERVED};
+
+const DATA: u8 = 0;
+const STATUS: u8 = 1;
+
+/// Set in the status port while a received byte is waiting.
+const RX_READY: u8 = 1 << 0;
+/// Set in the status port while the UART is ready to transmit.
+const TX_READY: u8 = 1 << 1;
+
+/// Clock cycles between each check for new input.
+const POLL_DIVISOR: u32 = 64;
+
+enum Source {
+    None,
+    Stdin,
+    #[cfg(unix)]
+    Pty(pty::Pty),
+}
+
+pub(super) struct Uart {
+    rx: VecDeque<u8>,
+    /// Bytes read from the `in` file, received again each time the UART is reset.
+    input: Vec<u8>,
+    source: Source,
+    output: Option<Box<dyn Write + Send>>,
+}
+
+impl Uart {
+    pub fn create(options: &Options, ports: u8) -> Result<Box<dyn Peripheral>, String> {
+        if ports != 2 {
+            return Err(format!(
+                "expected `2` connected ports (data and status), found `{ports}`"
+            ));
+        }
+
+        let backend = options
+            .get::<String>("backend")?
+            .unwrap_or_else(|| "stdio".to_owned());
+        let input = options.get::<String>("in")?;
+        let output = options.get::<String>("out")?;
+        if backend != "file" && (input.is_some() || output.is_some()) {
+            return Err("`in` and `out` can only be used with `backend=file`".to_owned());
+        }
+
+        let uart = match backend.as_str() {
+            "stdio" => {
+                let source = if STDIN_RESERVED.load(Ordering::Relaxed) {
+                    println!("WARNING: stdin is used by the REPL, so the UART will not receive any input (try `--script` or `backend=pty`)");
+                    Source::None
+                } else {
+                    // Start reading straight away, so input isn't missed by a short run
+                    stdin();
+                    Source::Stdin
+                };
+
+                Uart {
+                    rx: VecDeque::new(),
+                    input: Vec::new(),
+                    source,
+                    output: Some(Box::new(io::stdout())),
+                }
+            }
+            "file" => {
+                let input = match input {
+                    Some(path) => {
+                        fs::read(&path).map_err(|err| format!("unable to read {path}: {err}"))?
+                    }
+                    None => Vec::new(),
+                };
+                let output: Option<Box<dyn Write + Send>> = match output {
+                    Some(path) => Some(Box::new(
+                        File::create(&path)
+                            .map_err(|err| format!("unable to create {path}: {err}"))?,
+                    )),
+                    None => None,
+                };
+
+                Uart {
+                    rx: input.iter().copied().collect(),
+                    input,
+                    source: Source::None,
+                    output,
+                }
+            }
+            #[cfg(unix)]
+            "pty" => {
+                let pty = pty::Pty::open()
+                    .map_err(|err| format!("unable to open a pseudo-terminal: {err}"))?;
+                println!("INFO: UART connected to {}", pty.name);
+                let output = pty
+                    .master
+                    .try_clone()
+                    .map_err(|err| format!("unable to open a pseudo-terminal: {err}"))?;
+
+                Uart {
+                    rx: VecDeque::new(),
+                    input: Vec::new(),
+                    source: Source::Pty(pty),
+                    output: Some(Box::new(output)),
+                }
+            }
+            #[cfg(not(unix))]
+            "pty" => return Err("pseudo-terminals are only supported on unix".to_owned()),
+            _ => {
+                return Err(format!(
+                    "unknown backend `{backend}` (expected `stdio`, `file`, or `pty`)"
+                ))
+            }
+        };
+
+        Ok(Box::new(uart))
+    }
+
+    /// Moves any newly received bytes into the receive buffer.
+    fn poll(&mut self) {
+        match &mut self.source {
+            Source::None => {}
+            Source::Stdin => {
+                let stdin = stdin().lock().unwrap_or_else(PoisonError::into_inner);
+                self.rx.extend(stdin.try_iter());
+            }
+            #[cfg(unix)]
+            Source::Pty(pty) => {
+                let mut buffer = [0; 64];
+                // The master is non-blocking, so this stops once there's nothing left to read
+                while let Ok(read @ 1..) = pty.master.read(&mut buffer) {
+                    self.rx.extend(&buffer[..read]);
+                }
+            }
+        }
+    }
+}
+
+impl Peripheral for Uart {
+    fn name(&self) -> &str {
+        "uart"
+    }
+
+    fn read(&mut self, n: u8) -> u8 {
+        self.poll();
+
+        match n {
+            DATA => self.rx.pop_front().unwrap_or(0x00),
+            STATUS => match self.rx.is_empty() {
+                true => TX_READY,
+                false => TX_READY | RX_READY,
+            },
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, n: u8, data: u8) {
+        if n != DATA {
+            return;
+        }
+
+        if let Some(output) = &mut self.output {
+            // A disconnected terminal drops anything sent to it, just like real hardware
+            let _ = output.write_all(&[data]).and_then(|_| output.flush());
+        }
+    }
+
+    fn tick(&mut self) {
+        self.poll();
+    }
+
+    fn divisor(&self) -> u32 {
+        match self.source {
+            Source::None => 0,
+            _ => POLL_DIVISOR,
+        }
+    }
+
+    fn pending(&self) -> bool {
+        !self.rx.is_empty()
+    }
+
+    fn debug_info(&self) -> Option<String> {
+        Some(format!("RECEIVED: {} byte(s) waiting", self.rx.len()))
+    }
+
+    fn reset(&mut self) {
+        self.rx.clear();
+        self.rx.extend(&self.input);
+    }
+}
+
+impl fmt::Debug for Uart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Uart")
+            .field("rx", &self.rx)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Bytes read from stdin, shared between every UART using it.
+static STDIN: OnceLock<Mutex<Receiver<u8>>> = OnceLock::new();
+
+/// Stdin can only be read by blocking, so it's read on a separate thread,
+/// started the first time a UART uses it.
+fn stdin() -> &'static Mutex<Receiver<u8>> {
+    STDIN.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                match byte {
+                    Ok(byte) if tx.send(byte).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+
+        Mutex::new(rx)
+    })
+}
+
+/// Whether a UART has started reading from stdin.
+#[cfg(test)]
+pub(super) fn reading_stdin() -> bool {
+    STDIN.get().is_some()
+}
+
+#[cfg(unix)]
+mod pty {
+    use std::{
+        ffi::CStr,
+        fs::File,
+        io,
+        os::{
+            fd::{AsRawFd, FromRawFd},
+            unix::fs::OpenOptionsExt,
+        },
+    };
+
+    pub struct Pty {
+        pub master: File,
+        /// Keeping the slave open stops reads from failing while no terminal is connected.
+        _slave: File,
+        pub name: String,
+    }
+
+    impl Pty {
+        /// Opens a new pseudo-terminal in raw mode, with a non-blocking master.
+        pub fn open() -> io::Result<Pty> {
+            unsafe {
+                let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let master = File::from_raw_fd(fd);
+
+                if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                let name = libc::ptsname(fd);
+                if name.is_null() {
+                    return Err(io::Error::last_os_error());
+                }
+                let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+                let slave = File::options()
+                    .read(true)
+                    .write(true)
+                    .custom_flags(libc::O_NOCTTY)
+                    .open(&name)?;
+
+                // Without raw mode, the terminal would echo everything we send straight back to us
+                let mut termios = std::mem::zeroed::<libc::termios>();
+                if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                libc::cfmakeraw(&mut termios);
+                if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                Ok(Pty {
+                    master,
+                    _slave: slave,
+                    name,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file() {
+        let dir = std::env::temp_dir().join(format!("fateful-uart-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in");
+        let output = dir.join("out");
+        fs::write(&input, b"ok").unwrap();
+
+        let options = format!(
+            "backend=file,in={},out={}",
+            input.display(),
+            output.display()
+        );
+        let mut uart = Uart::create(&options.parse().unwrap(), 2).unwrap();
+        assert!(uart.pending());
+        assert_eq!(uart.read(STATUS), TX_READY | RX_READY);
+        assert_eq!((uart.read(DATA), uart.read(DATA)), (b'o', b'k'));
+        assert_eq!(uart.read(STATUS), TX_READY);
+        assert!(!uart.pending());
+
+        // Resetting receives the file over again
+        uart.reset();
+        assert_eq!((uart.read(DATA), uart.read(DATA)), (b'o', b'k'));
+        assert!(!uart.pending());
+
+        for byte in b"hello" {
+            uart.write(DATA, *byte);
+        }
+        drop(uart);
+        assert_eq!(fs::read(&output).unwrap(), b"hello");
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(Uart::create(&"backend=stdio,in=x".parse().unwrap(), 2).is_err());
+        assert!(Uart::create(&Options::default(), 1).is_err());
+    }
+}
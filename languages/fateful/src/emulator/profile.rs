@@ -0,0 +1,224 @@
+//! A profiler, counting the executions and clock cycles spent at each program address.
+//!
+//! Call stacks are reconstructed from the `call` and `ret` expansions
+//! recorded in the symbol file, and can be exported in the collapsed-stack
+//! format used by flamegraph tools (e.g. `inferno` or `flamegraph.pl`).
+
+use std::{collections::HashMap, fmt::Write as _};
+
+use crate::{disassembler::Disassembled, symbols::Symbols};
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Count {
+    /// Number of times the instruction was executed.
+    hits: u64,
+    /// Number of clock cycles spent executing the instruction.
+    cycles: u64,
+}
+
+#[derive(Debug)]
+pub(super) struct Profile {
+    counts: Box<[Count]>,
+    /// Whether the profiler is currently recording.
+    pub running: bool,
+    /// Address of the instruction currently being executed.
+    pc: Option<u16>,
+    /// Cycles spent on the current instruction.
+    cycles: u64,
+    /// The current call stack, from the outermost function.
+    stack: Vec<String>,
+    /// Cycles spent in each unique call stack.
+    stacks: HashMap<String, u64>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            counts: vec![Count::default(); 1 << 16].into_boxed_slice(),
+            running: true,
+            pc: None,
+            cycles: 0,
+            stack: Vec::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    /// Marks the beginning of the instruction at `pc`.
+    pub fn begin(&mut self, pc: u16, symbols: &Symbols) {
+        if self.stack.is_empty() {
+            self.stack.push(frame(pc, symbols));
+        }
+
+        self.pc = Some(pc);
+        self.counts[pc as usize].hits += 1;
+    }
+
+    /// Counts a single clock cycle towards the current instruction.
+    pub fn tick(&mut self) {
+        if let Some(pc) = self.pc {
+            self.counts[pc as usize].cycles += 1;
+            self.cycles += 1;
+        }
+    }
+
+    /// Finishes the current instruction, with `next` as the address of the next instruction.
+    pub fn retire(&mut self, next: u16, symbols: &Symbols) {
+        let Some(pc) = self.pc.take() else {
+            return;
+        };
+
+        *self.stacks.entry(self.stack.join(";")).or_default() += self.cycles;
+        self.cycles = 0;
+
+        // A call or return only happens once the CPU jumps out of the expansion
+        if let Some((range, name)) = symbols.expansion(pc) {
+            if !range.contains(&next) {
+                match name {
+                    "call" => self.stack.push(frame(next, symbols)),
+                    "ret" if self.stack.len() > 1 => {
+                        self.stack.pop();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Formats a report of the `top` hottest addresses, along with the cycles spent under each label.
+    pub fn report(&self, top: usize, program: &[u8], symbols: &Symbols) -> String {
+        let total: u64 = self.counts.iter().map(|count| count.cycles).sum();
+        let percent = |cycles: u64| match total {
+            0 => 0.0,
+            total => cycles as f64 * 100.0 / total as f64,
+        };
+
+        let mut hottest: Vec<(u16, Count)> = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| count.hits > 0)
+            .map(|(addr, count)| (addr as u16, *count))
+            .collect();
+        hottest.sort_by(|(lhs_addr, lhs), (rhs_addr, rhs)| {
+            rhs.cycles.cmp(&lhs.cycles).then(lhs_addr.cmp(rhs_addr))
+        });
+
+        let mut labels: Vec<(&str, Count)> = Vec::new();
+        for (addr, count) in hottest.iter() {
+            let label = symbols
+                .nearest(*addr)
+                .map(|(_, name)| name)
+                .unwrap_or("???");
+            match labels.iter_mut().find(|(name, _)| *name == label) {
+                Some((_, total)) => {
+                    total.hits += count.hits;
+                    total.cycles += count.cycles;
+                }
+                None => labels.push((label, *count)),
+            }
+        }
+        labels.sort_by(|(lhs_name, lhs), (rhs_name, rhs)| {
+            rhs.cycles.cmp(&lhs.cycles).then(lhs_name.cmp(rhs_name))
+        });
+
+        // Writing to a `String` is infallible
+        let mut report = String::new();
+        let _ = writeln!(report, "TOTAL CYCLES: {total}");
+        let _ = writeln!(report, "HOTTEST ADDRESSES:");
+        let _ = writeln!(
+            report,
+            "{:>10} {:>7} {:>8}  INSTRUCTION",
+            "CYCLES", "%", "HITS"
+        );
+        for (addr, count) in hottest.iter().take(top) {
+            let inst = Disassembled::decode(program, *addr);
+            let _ = write!(
+                report,
+                "{:>10} {:>6.2}% {:>8}  {addr:#06X}  {:<20}",
+                count.cycles,
+                percent(count.cycles),
+                count.hits,
+                inst.to_string(),
+            );
+            match symbols.program(*addr) {
+                Some(symbol) => {
+                    let _ = writeln!(report, " ; {symbol}");
+                }
+                None => report.push('\n'),
+            }
+        }
+
+        let _ = writeln!(report, "LABELS:");
+        let _ = writeln!(report, "{:>10} {:>7} {:>8}  LABEL", "CYCLES", "%", "HITS");
+        for (label, count) in labels {
+            let _ = writeln!(
+                report,
+                "{:>10} {:>6.2}% {:>8}  {label}",
+                count.cycles,
+                percent(count.cycles),
+                count.hits,
+            );
+        }
+
+        report
+    }
+
+    /// Formats the cycles spent in each call stack in the collapsed-stack format,
+    /// with one `outer;inner <cycles>` line per unique stack.
+    pub fn collapsed(&self) -> String {
+        let mut stacks: Vec<(&String, &u64)> = self
+            .stacks
+            .iter()
+            .filter(|(_, cycles)| **cycles > 0)
+            .collect();
+        stacks.sort();
+
+        stacks
+            .into_iter()
+            .map(|(stack, cycles)| format!("{stack} {cycles}\n"))
+            .collect()
+    }
+}
+
+/// Names the function containing `addr`,
+/// falling back to the address itself if there are no symbols.
+fn frame(addr: u16, symbols: &Symbols) -> String {
+    symbols
+        .function(addr)
+        .map(|name| name.to_owned())
+        .unwrap_or_else(|| format!("{addr:#06X}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stacks() {
+        let mut symbols = Symbols::new();
+        symbols.insert_label("main", 0x0000);
+        symbols.insert_label("mul", 0x0010);
+        symbols.insert_macro("call", 0x0002..0x0004);
+        symbols.insert_macro("ret", 0x0012..0x0014);
+
+        let mut profile = Profile::new();
+        // `main` runs into a `call`, which jumps to `mul` before returning
+        for (pc, next, cycles) in [
+            (0x0000, 0x0002, 2),
+            (0x0002, 0x0010, 3),
+            (0x0010, 0x0012, 4),
+            (0x0012, 0x0004, 5),
+            (0x0004, 0x0006, 1),
+        ] {
+            profile.begin(pc, &symbols);
+            for _ in 0..cycles {
+                profile.tick();
+            }
+            profile.retire(next, &symbols);
+        }
+
+        assert_eq!(profile.collapsed(), "main 6\nmain;mul 9\n");
+        assert_eq!(profile.counts[0x0010].cycles, 4);
+        assert_eq!(profile.counts[0x0010].hits, 1);
+    }
+}
@@ -0,0 +1,249 @@
+//! Machine snapshots, saved with `SAVE` and loaded with `RESTORE`.
+//!
+//! Snapshots are stored in a simple binary format, with all integers in big-endian order:
+//!
+//! | Field       | Size                                                  |
+//! |-------------|-------------------------------------------------------|
+//! | Magic       | 4 bytes (`F8SS`)                                      |
+//! | Version     | 1 byte                                                |
+//! | Registers   | 28 bytes                                              |
+//! | Memory      | 65536 bytes                                           |
+//! | Program     | 65536 bytes                                           |
+//! | Text buffer | 4096 bytes                                            |
+//! | Peripherals | 1 byte count, then each path and its ports            |
+//!
+//! The registers are stored in the order PC, SP, control clock, instruction header, SREG,
+//! ALU primary, ALU secondary, bus, register bank (A through L), address register, and cycle count.
+//!
+//! Each peripheral is stored as a 2 byte path length, the UTF-8 path,
+//! a 1 byte port count, and each port relative to `0xFFC0`.
+//! Only the list of peripherals is stored, not their internal state,
+//! so peripherals are reloaded from scratch when a snapshot is restored.
+
+use std::io::{self, Read, Write};
+
+use thiserror::Error;
+
+use super::{history::Registers, Alu, Control, InstructionHeader, RegBank, SReg};
+
+const MAGIC: &[u8; 4] = b"F8SS";
+const VERSION: u8 = 2;
+/// Size of the address range backing the text buffer.
+pub(super) const TEXT_SIZE: usize = 1 << 12;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error("file is not a fateful snapshot")]
+    Magic,
+    #[error("unsupported snapshot version {0} (expected {VERSION})")]
+    Version(u8),
+    #[error("peripheral path is not valid UTF-8")]
+    Path,
+}
+
+#[derive(Debug)]
+pub(super) struct Snapshot {
+    pub registers: Registers,
+    pub mem: Box<[u8]>,
+    pub program: Box<[u8]>,
+    /// Contents of the text buffer, in the same layout as VRAM.
+    pub text: Box<[u8]>,
+    /// The path of each loaded peripheral, along with the ports it is connected to.
+    pub peripherals: Vec<(String, Vec<u8>)>,
+}
+
+impl Snapshot {
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let registers = &self.registers;
+        let bank = &registers.bank;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&registers.pc.to_be_bytes())?;
+        writer.write_all(&registers.sp.to_be_bytes())?;
+        writer.write_all(&[
+            registers.ctrl.clock,
+            registers.ctrl.head.bits(),
+            registers.sreg.bits(),
+        ])?;
+        writer.write_all(&[
+            registers.alu.primary,
+            registers.alu.secondary,
+            registers.bus,
+            bank.a,
+            bank.b,
+            bank.c,
+            bank.d,
+            bank.e,
+            bank.f,
+            bank.h,
+            bank.l,
+        ])?;
+        writer.write_all(&registers.addr.to_be_bytes())?;
+        writer.write_all(&registers.cycles.to_be_bytes())?;
+
+        writer.write_all(&self.mem)?;
+        writer.write_all(&self.program)?;
+        writer.write_all(&self.text)?;
+
+        writer.write_all(&[self.peripherals.len() as u8])?;
+        for (path, ports) in self.peripherals.iter() {
+            writer.write_all(&(path.len() as u16).to_be_bytes())?;
+            writer.write_all(path.as_bytes())?;
+            writer.write_all(&[ports.len() as u8])?;
+            writer.write_all(ports)?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Snapshot, SnapshotError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::Magic);
+        }
+
+        let version = read_u8(&mut reader)?;
+        if version != VERSION {
+            return Err(SnapshotError::Version(version));
+        }
+
+        let pc = read_u16(&mut reader)?;
+        let sp = read_u16(&mut reader)?;
+        let clock = read_u8(&mut reader)?;
+        let head = read_u8(&mut reader)?;
+        let sreg = read_u8(&mut reader)?;
+
+        let mut bytes = [0; 11];
+        reader.read_exact(&mut bytes)?;
+        let [primary, secondary, bus, a, b, c, d, e, f, h, l] = bytes;
+
+        let addr = read_u16(&mut reader)?;
+        let mut cycles = [0; 8];
+        reader.read_exact(&mut cycles)?;
+
+        let registers = Registers {
+            pc,
+            sp,
+            ctrl: Control {
+                head: InstructionHeader::from_bytes([head]),
+                clock,
+            },
+            sreg: SReg::from_bits_retain(sreg),
+            alu: Alu { primary, secondary },
+            bus,
+            bank: RegBank {
+                a,
+                b,
+                c,
+                d,
+                e,
+                f,
+                h,
+                l,
+            },
+            addr,
+            cycles: u64::from_be_bytes(cycles),
+        };
+
+        let mut mem = vec![0; 1 << 16].into_boxed_slice();
+        reader.read_exact(&mut mem)?;
+        let mut program = vec![0; 1 << 16].into_boxed_slice();
+        reader.read_exact(&mut program)?;
+        let mut text = vec![0; TEXT_SIZE].into_boxed_slice();
+        reader.read_exact(&mut text)?;
+
+        let count = read_u8(&mut reader)?;
+        let mut peripherals = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut path = vec![0; read_u16(&mut reader)? as usize];
+            reader.read_exact(&mut path)?;
+            let path = String::from_utf8(path).map_err(|_| SnapshotError::Path)?;
+
+            let mut ports = vec![0; read_u8(&mut reader)? as usize];
+            reader.read_exact(&mut ports)?;
+
+            peripherals.push((path, ports));
+        }
+
+        Ok(Snapshot {
+            registers,
+            mem,
+            program,
+            text,
+            peripherals,
+        })
+    }
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut mem = vec![0; 1 << 16].into_boxed_slice();
+        mem[0xE000] = 0x2A;
+        let mut program = vec![0; 1 << 16].into_boxed_slice();
+        program[0x0000] = 0xF0;
+        let mut text = vec![0; TEXT_SIZE].into_boxed_slice();
+        text[0x0001] = 0x1F;
+
+        let snapshot = Snapshot {
+            registers: Registers {
+                pc: 0x0012,
+                sp: 0xEFFD,
+                ctrl: Control {
+                    head: InstructionHeader::from_bytes([0x73]),
+                    clock: 2,
+                },
+                sreg: SReg::Z,
+                alu: Alu {
+                    primary: 0x01,
+                    secondary: 0x02,
+                },
+                bus: 0x03,
+                bank: RegBank {
+                    c: 0x07,
+                    l: 0x0C,
+                    ..Default::default()
+                },
+                addr: 0xE000,
+                cycles: 100,
+            },
+            mem,
+            program,
+            text,
+            peripherals: vec![("libscreen.so".to_owned(), vec![0x10, 0x11])],
+        };
+
+        let mut buffer = Vec::new();
+        snapshot.write(&mut buffer).unwrap();
+        let restored = Snapshot::read(buffer.as_slice()).unwrap();
+
+        assert_eq!(restored.registers.pc, 0x0012);
+        assert_eq!(restored.registers.ctrl.head, snapshot.registers.ctrl.head);
+        assert_eq!(restored.registers.ctrl.clock, 2);
+        assert_eq!(restored.registers.bank, snapshot.registers.bank);
+        assert_eq!(restored.registers.cycles, 100);
+        assert_eq!(restored.mem, snapshot.mem);
+        assert_eq!(restored.program, snapshot.program);
+        assert_eq!(restored.text, snapshot.text);
+        assert_eq!(restored.peripherals, snapshot.peripherals);
+    }
+}
@@ -0,0 +1,112 @@
+//! Execution traces, recording every instruction retired by the CPU.
+//!
+//! Each retired instruction is written as a single line containing the
+//! address and disassembly of the instruction, the register bank, SREG,
+//! and bus after it finished, followed by any memory writes it made:
+//!
+//! ```text
+//! 0x0012 mv D, A               A=00 B=01 C=07 D=00 E=00 F=00 H=00 L=0C SREG=00 BUS=00
+//! 0x0024 st [0xE000], A        A=05 B=01 C=07 D=00 E=00 F=00 H=00 L=0C SREG=00 BUS=05 W[E000]=05
+//! ```
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use super::RegBank;
+use crate::disassembler::Disassembled;
+
+#[derive(Debug)]
+pub struct Trace {
+    writer: BufWriter<File>,
+    /// Address of the instruction currently being executed,
+    /// or `None` if tracing started partway through an instruction.
+    pc: Option<u16>,
+    /// Memory writes made by the current instruction.
+    writes: Vec<(u16, u8)>,
+}
+
+impl Trace {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Trace> {
+        Ok(Trace {
+            writer: BufWriter::new(File::create(path)?),
+            pc: None,
+            writes: Vec::new(),
+        })
+    }
+
+    /// Marks the beginning of the instruction at `pc`.
+    pub fn begin(&mut self, pc: u16) {
+        self.pc = Some(pc);
+        self.writes.clear();
+    }
+
+    /// Records a memory write made by the current instruction.
+    pub fn write(&mut self, addr: u16, data: u8) {
+        self.writes.push((addr, data));
+    }
+
+    /// Writes the current instruction to the trace once it has finished executing.
+    pub fn retire(&mut self, program: &[u8], bank: &RegBank, sreg: u8, bus: u8) -> io::Result<()> {
+        if let Some(pc) = self.pc.take() {
+            let inst = Disassembled::decode(program, pc);
+            writeln!(
+                self.writer,
+                "{}",
+                line(&inst, bank, sreg, bus, &self.writes)
+            )?;
+        }
+        self.writes.clear();
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn line(inst: &Disassembled, bank: &RegBank, sreg: u8, bus: u8, writes: &[(u16, u8)]) -> String {
+    let mut line = format!(
+        "{:#06X} {:<21} A={:02X} B={:02X} C={:02X} D={:02X} E={:02X} F={:02X} H={:02X} L={:02X} SREG={sreg:02X} BUS={bus:02X}",
+        inst.address,
+        inst.to_string(),
+        bank.a,
+        bank.b,
+        bank.c,
+        bank.d,
+        bank.e,
+        bank.f,
+        bank.h,
+        bank.l,
+    );
+
+    for (addr, data) in writes {
+        // Writing to a `String` is infallible
+        let _ = write!(line, " W[{addr:04X}]={data:02X}");
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format() {
+        let inst = Disassembled::decode(&[0x99, 0xE0, 0x00], 0x0000);
+        let bank = RegBank {
+            b: 0x05,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            line(&inst, &bank, 0x01, 0x05, &[(0xE000, 0x05)]),
+            "0x0000 st [0xE000], B        A=00 B=05 C=00 D=00 E=00 F=00 H=00 L=00 SREG=01 BUS=05 W[E000]=05"
+        );
+    }
+}
@@ -4,16 +4,19 @@ use std::{
     sync::OnceLock,
 };
 
-use emulator::{EmulatorArgs, EmulatorError};
+use emulator::{DapError, DiskArgs, DiskError, EmulatorArgs, EmulatorError};
 mod deploy;
 use deploy::{DeployArgs, DeployError};
 mod assembler;
-use assembler::{AssemblerArgs, AssemblerError};
+use assembler::{AssemblerArgs, AssemblerError, LinkerArgs, LinkerError};
 mod tests;
 use tests::TestArgs;
+mod disassembler;
+use disassembler::{DisassemblerArgs, DisassemblerError};
 
 mod diagnostic;
 use diagnostic::ResultScream;
+mod symbols;
 
 use clap::{Parser, Subcommand};
 use clap_verbosity_flag::{Level, WarnLevel};
@@ -42,8 +45,17 @@ enum Command {
     /// Assemble a Fate program
     #[clap(alias = "asm")]
     Assemble(AssemblerArgs),
+    /// Link object files from `fateful asm -c` into a program
+    Link(LinkerArgs),
     /// Quickly test Fate assembly programs
     Test(TestArgs),
+    /// Disassemble a program ROM into Fate assembly
+    #[clap(alias = "disasm")]
+    Disassemble(DisassemblerArgs),
+    /// Run a Debug Adapter Protocol server over stdio
+    Dap,
+    /// Create a disk image for the built-in disk peripheral
+    Mkdisk(DiskArgs),
 }
 
 #[derive(Debug)]
@@ -51,6 +63,10 @@ enum Return {
     Emulator(EmulatorError),
     Deploy(DeployError),
     Assembler(AssemblerError),
+    Linker(LinkerError),
+    Disassembler(DisassemblerError),
+    Dap(DapError),
+    Disk(DiskError),
     Test,
     Ok,
 }
@@ -60,6 +76,10 @@ impl Termination for Return {
         match self {
             Return::Emulator(err) => error!("{err}").emit(),
             Return::Deploy(err) => error!("{err}").emit(),
+            Return::Linker(err) => error!("{err}").emit(),
+            Return::Disassembler(err) => error!("{err}").emit(),
+            Return::Dap(err) => error!("{err}").emit(),
+            Return::Disk(err) => error!("{err}").emit(),
             Return::Test => {}
             Return::Assembler(AssemblerError::Assembly(errors)) => {
                 for err in errors {
@@ -106,10 +126,26 @@ fn main() -> Return {
             Ok(_) => Return::Ok,
             Err(err) => Return::Assembler(err),
         },
+        Command::Link(args) => match assembler::link(args) {
+            Ok(_) => Return::Ok,
+            Err(err) => Return::Linker(err),
+        },
         Command::Test(args) => match tests::test_all(args) {
             Ok(_) => Return::Ok,
             Err(_) => Return::Test,
         },
+        Command::Disassemble(args) => match disassembler::disassemble(args) {
+            Ok(_) => Return::Ok,
+            Err(err) => Return::Disassembler(err),
+        },
+        Command::Dap => match emulator::debug_adapter() {
+            Ok(_) => Return::Ok,
+            Err(err) => Return::Dap(err),
+        },
+        Command::Mkdisk(args) => match emulator::mkdisk(args) {
+            Ok(_) => Return::Ok,
+            Err(err) => Return::Disk(err),
+        },
     }
 }
 
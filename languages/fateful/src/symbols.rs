@@ -0,0 +1,314 @@
+//! Symbol files, mapping program and memory addresses back to their source.
+//!
+//! Symbol files are emitted by the assembler with `--symbols`,
+//! and can be loaded by the emulator to annotate addresses.
+//! Each line contains a single entry of one of the following forms:
+//!
+//! ```text
+//! label 0x0004 main.loop
+//! variable 0xE000 counter
+//! line 0x0004 examples/fib.asm:14
+//! macro 0x0010 0x0019 call
+//! ```
+//!
+//! `macro` entries mark the address range of each `call` and `ret` expansion,
+//! which the profiler uses to reconstruct the call stack.
+
+use std::{fmt, ops::Range, str::FromStr};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("malformed symbol file (line {line}): {message}")]
+pub struct SymbolError {
+    line: usize,
+    message: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    address: u16,
+    file: String,
+    line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expansion {
+    range: Range<u16>,
+    name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    /// Program labels, sorted by address.
+    labels: Vec<(u16, String)>,
+    /// Data segment variables, sorted by address.
+    variables: Vec<(u16, String)>,
+    /// Source lines of each instruction, sorted by address.
+    lines: Vec<Line>,
+    /// Address ranges of macro expansions, sorted by address.
+    macros: Vec<Expansion>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    pub fn insert_label<T: Into<String>>(&mut self, name: T, address: u16) {
+        let index = self.labels.partition_point(|(addr, _)| *addr <= address);
+        self.labels.insert(index, (address, name.into()));
+    }
+
+    pub fn insert_variable<T: Into<String>>(&mut self, name: T, address: u16) {
+        let index = self.variables.partition_point(|(addr, _)| *addr <= address);
+        self.variables.insert(index, (address, name.into()));
+    }
+
+    pub fn insert_line<T: Into<String>>(&mut self, address: u16, file: T, line: usize) {
+        let index = self.lines.partition_point(|l| l.address <= address);
+        self.lines.insert(
+            index,
+            Line {
+                address,
+                file: file.into(),
+                line,
+            },
+        );
+    }
+
+    pub fn insert_macro<T: Into<String>>(&mut self, name: T, range: Range<u16>) {
+        let index = self
+            .macros
+            .partition_point(|m| m.range.start <= range.start);
+        self.macros.insert(
+            index,
+            Expansion {
+                range,
+                name: name.into(),
+            },
+        );
+    }
+
+    /// Finds the address of the label with the given name.
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(_, label)| label == name)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Finds the most specific label located exactly at the given address.
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .filter(|(addr, _)| *addr == address)
+            .max_by_key(|(_, name)| name.len())
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Finds the closest label at or before the given address,
+    /// preferring the most specific label if several share an address.
+    pub fn nearest(&self, address: u16) -> Option<(u16, &str)> {
+        self.labels
+            .iter()
+            .filter(|(addr, _)| *addr <= address)
+            .max_by_key(|(addr, name)| (*addr, name.len()))
+            .map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    /// Finds the closest top-level (non-local) label at or before the given address.
+    pub fn function(&self, address: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .filter(|(addr, name)| *addr <= address && !name.contains('.'))
+            .max_by_key(|(addr, _)| *addr)
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Finds the macro expansion containing the given address.
+    pub fn expansion(&self, address: u16) -> Option<(Range<u16>, &str)> {
+        self.macros
+            .iter()
+            .find(|m| m.range.contains(&address))
+            .map(|m| (m.range.clone(), m.name.as_str()))
+    }
+
+    /// Finds the address of the variable with the given name.
+    pub fn variable(&self, name: &str) -> Option<u16> {
+        self.variables
+            .iter()
+            .find(|(_, var)| var == name)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Describes a program address relative to the closest preceding label,
+    /// along with the source line it was assembled from (e.g. `main.loop+3 (fib.asm:14)`).
+    pub fn program(&self, address: u16) -> Option<String> {
+        let label = self
+            .nearest(address)
+            .map(|(addr, name)| match address - addr {
+                0 => name.to_owned(),
+                offset => format!("{name}+{offset}"),
+            });
+
+        let line = self
+            .lines
+            .iter()
+            .find(|l| l.address == address)
+            .map(|l| format!("({}:{})", l.file, l.line));
+
+        match (label, line) {
+            (Some(label), Some(line)) => Some(format!("{label} {line}")),
+            (Some(label), None) => Some(label),
+            (None, Some(line)) => Some(line),
+            (None, None) => None,
+        }
+    }
+
+    /// Iterates over the source file and line of each instruction, in address order.
+    pub fn lines(&self) -> impl Iterator<Item = (u16, &str, usize)> {
+        self.lines
+            .iter()
+            .map(|l| (l.address, l.file.as_str(), l.line))
+    }
+
+    /// Iterates over each data segment variable, in address order.
+    pub fn variables(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.variables
+            .iter()
+            .map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    /// Finds the name of the variable located at the given memory address.
+    pub fn memory(&self, address: u16) -> Option<&str> {
+        self.variables
+            .iter()
+            .find(|(addr, _)| *addr == address)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (address, name) in self.labels.iter() {
+            writeln!(f, "label {address:#06X} {name}")?;
+        }
+
+        for (address, name) in self.variables.iter() {
+            writeln!(f, "variable {address:#06X} {name}")?;
+        }
+
+        for line in self.lines.iter() {
+            writeln!(f, "line {:#06X} {}:{}", line.address, line.file, line.line)?;
+        }
+
+        for m in self.macros.iter() {
+            writeln!(
+                f,
+                "macro {:#06X} {:#06X} {}",
+                m.range.start, m.range.end, m.name
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Symbols {
+    type Err = SymbolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut symbols = Symbols::new();
+
+        for (n, line) in s.lines().enumerate() {
+            let error = |message| SymbolError {
+                line: n + 1,
+                message,
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let mut parts = line.splitn(3, ' ');
+            let kind = parts.next().ok_or_else(|| error("missing entry kind"))?;
+            let address = parts
+                .next()
+                .and_then(|addr| addr.strip_prefix("0x"))
+                .and_then(|addr| u16::from_str_radix(addr, 16).ok())
+                .ok_or_else(|| error("expected a hexadecimal address"))?;
+            let value = parts
+                .next()
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| error("missing entry value"))?;
+
+            match kind {
+                "label" => symbols.insert_label(value, address),
+                "variable" => symbols.insert_variable(value, address),
+                "line" => {
+                    let (file, line) = value
+                        .rsplit_once(':')
+                        .and_then(|(file, line)| Some((file, line.parse().ok()?)))
+                        .ok_or_else(|| error("expected a `<file>:<line>` location"))?;
+                    symbols.insert_line(address, file, line);
+                }
+                "macro" => {
+                    let (end, name) = value
+                        .split_once(' ')
+                        .and_then(|(end, name)| {
+                            let end = u16::from_str_radix(end.strip_prefix("0x")?, 16).ok()?;
+                            Some((end, name.trim()))
+                        })
+                        .ok_or_else(|| error("expected an `<end> <name>` expansion"))?;
+                    symbols.insert_macro(name, address..end);
+                }
+                _ => return Err(error("unknown entry kind")),
+            }
+        }
+
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut symbols = Symbols::new();
+        symbols.insert_label("main", 0x0000);
+        symbols.insert_label("main.loop", 0x0004);
+        symbols.insert_variable("counter", 0xE000);
+        symbols.insert_line(0x0004, "fib.asm", 14);
+        symbols.insert_line(0x0006, "fib.asm", 15);
+        symbols.insert_macro("call", 0x0010..0x0019);
+
+        let parsed: Symbols = symbols.to_string().parse().unwrap();
+        assert_eq!(parsed, symbols);
+    }
+
+    #[test]
+    fn describe() {
+        let symbols: Symbols = "\
+            label 0x0000 main\n\
+            label 0x0004 main.loop\n\
+            variable 0xE000 counter\n\
+            line 0x0007 fib.asm:14\n\
+        "
+        .parse()
+        .unwrap();
+
+        assert_eq!(symbols.program(0x0004).as_deref(), Some("main.loop"));
+        assert_eq!(
+            symbols.program(0x0007).as_deref(),
+            Some("main.loop+3 (fib.asm:14)")
+        );
+        assert_eq!(symbols.memory(0xE000), Some("counter"));
+        assert_eq!(symbols.label("main.loop"), Some(0x0004));
+        assert_eq!(symbols.function(0x0007), Some("main"));
+    }
+}
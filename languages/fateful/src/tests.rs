@@ -6,15 +6,15 @@ use crate::assembler::tests::{
     lex::{self, Token, TokenInner},
     parse,
 };
-use crate::emulator::test_emulate;
+use crate::emulator::{test_emulate, Machine};
 use crate::{diagnostic::Diagnostic, error, spanned_error};
 use crate::{Verbosity, VERBOSITY};
 
 use std::{
     io::{stdout, Write},
     num::ParseIntError,
+    path::Path,
     thread,
-    time::Duration,
 };
 
 use colored::Colorize;
@@ -22,8 +22,9 @@ use colored::Colorize;
 #[derive(Debug, Args)]
 pub struct TestArgs {
     inputs: Vec<Input>,
-    #[clap(short, long, default_value = "500ms")]
-    timeout: humantime::Duration,
+    /// Maximum number of clock cycles each test can run before failing
+    #[clap(short, long, default_value = "1000000")]
+    max_cycles: u64,
 }
 
 pub fn test_all(args: TestArgs) -> Result<(), ()> {
@@ -37,7 +38,7 @@ pub fn test_all(args: TestArgs) -> Result<(), ()> {
             thread::spawn(move || {
                 let mut output = Vec::new();
 
-                test_file(input, args.timeout.into(), &mut output).map_err(|err| {
+                test_file(input, args.max_cycles, &mut output).map_err(|err| {
                     writeln!(output, "{err}").unwrap();
                     output
                 })
@@ -63,12 +64,11 @@ pub fn test_all(args: TestArgs) -> Result<(), ()> {
     }
 
     for handle in joined {
-        let status = match handle.1 {
-            Ok(_) => "success".green(),
-            Err(_) => "failure".red(),
-        };
-
-        println!("{} - {status}", handle.0);
+        match handle.1 {
+            Ok(Some(cycles)) => println!("{} - {} ({cycles} cycles)", handle.0, "success".green()),
+            Ok(None) => println!("{} - {}", handle.0, "success".green()),
+            Err(_) => println!("{} - {}", handle.0, "failure".red()),
+        }
     }
 
     Ok(())
@@ -110,11 +110,23 @@ fn parse_expected(input: &str) -> Result<u8, ParseIntError> {
     }
 }
 
+/// Assembles and runs a single test, returning the number of cycles it took to halt.
 fn test_file(
     input: Input,
-    timeout: Duration,
+    max_cycles: u64,
+    out: impl std::io::Write,
+) -> Result<Option<u64>, Diagnostic> {
+    test_machine(input, &[], max_cycles, out)
+}
+
+/// Assembles and runs a single test with the given peripherals attached (see `test_emulate`),
+/// returning the number of cycles it took to halt.
+fn test_machine(
+    input: Input,
+    peripherals: &[(&str, &[u16])],
+    max_cycles: u64,
     mut out: impl std::io::Write,
-) -> Result<(), Diagnostic> {
+) -> Result<Option<u64>, Diagnostic> {
     VERBOSITY.get_or_init(|| Verbosity::Error);
 
     let mut a = None;
@@ -125,7 +137,14 @@ fn test_file(
     let mut f = None;
     let mut h = None;
     let mut l = None;
+    let mut machine = None;
 
+    // Machine files are found relative to the test
+    let dir = input
+        .path()
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
     let lexed = lex::lex(input).map_err(|errors| emit_errors(errors, &mut out))?;
     let mut run = true;
 
@@ -168,6 +187,11 @@ fn test_file(
             l = Some(parse_expected(val.trim()).map_err(|err| {
                 spanned_error!(span.clone(), "unable to parse 8-bit integer: {err}")
             })?);
+        } else if let Some(path) = trimmed.strip_prefix("machine:") {
+            machine = Some(
+                Machine::read(&dir.join(path.trim()))
+                    .map_err(|err| spanned_error!(span.clone(), "{err}"))?,
+            );
         } else if trimmed == "no-run" {
             run = false;
         }
@@ -177,8 +201,16 @@ fn test_file(
     let assembled = generator::generate(parsed).map_err(|errors| emit_errors(errors, &mut out))?;
 
     if run {
-        let bank = test_emulate(assembled.into(), timeout)
-            .map_err(|_| error!("emulator exceeded timeout"))?;
+        let specs: Vec<(String, &[u16])> = machine
+            .iter()
+            .flat_map(|machine| machine.peripherals.iter())
+            .map(|peripheral| (peripheral.spec(), peripheral.ports.as_slice()))
+            .collect();
+        let mut peripherals = peripherals.to_vec();
+        peripherals.extend(specs.iter().map(|(path, addrs)| (path.as_str(), *addrs)));
+
+        let (bank, cycles) = test_emulate(assembled.program.into(), &peripherals, max_cycles)
+            .map_err(|err| error!("{err}"))?;
 
         bank_assert(bank.a, "A", a)?;
         bank_assert(bank.b, "B", b)?;
@@ -188,17 +220,35 @@ fn test_file(
         bank_assert(bank.f, "F", f)?;
         bank_assert(bank.h, "H", h)?;
         bank_assert(bank.l, "L", l)?;
-    }
 
-    Ok(())
+        Ok(Some(cycles))
+    } else {
+        Ok(None)
+    }
 }
 
 #[cfg(test)]
 #[test]
 fn fib() {
+    if let Err(err) = test_file(Input::new("tests/fib.asm").unwrap(), 100_000, stdout()) {
+        err.scream();
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn mul_example() {
+    if let Err(err) = test_file(Input::new("examples/mul.asm").unwrap(), 100_000, stdout()) {
+        err.scream();
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn box_example() {
     if let Err(err) = test_file(
-        Input::new("tests/fib.asm").unwrap(),
-        Duration::from_millis(250),
+        Input::new("examples/box.asm").unwrap(),
+        10_000_000,
         stdout(),
     ) {
         err.scream();
@@ -210,7 +260,7 @@ fn fib() {
 fn arithmetic() {
     if let Err(err) = test_file(
         Input::new("tests/arithmetic.asm").unwrap(),
-        Duration::from_millis(250),
+        100_000,
         stdout(),
     ) {
         err.scream();
@@ -220,9 +270,26 @@ fn arithmetic() {
 #[cfg(test)]
 #[test]
 fn mem() {
-    if let Err(err) = test_file(
-        Input::new("tests/mem.asm").unwrap(),
-        Duration::from_millis(250),
+    if let Err(err) = test_file(Input::new("tests/mem.asm").unwrap(), 100_000, stdout()) {
+        err.scream()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn comments() {
+    if let Err(err) = test_file(Input::new("tests/comments.asm").unwrap(), 100_000, stdout()) {
+        err.scream()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn latch() {
+    if let Err(err) = test_machine(
+        Input::new("tests/latch.asm").unwrap(),
+        &[("builtin:latch", &[0xFFD0, 0xFFD1])],
+        100_000,
         stdout(),
     ) {
         err.scream()
@@ -231,10 +298,14 @@ fn mem() {
 
 #[cfg(test)]
 #[test]
-fn comments() {
-    if let Err(err) = test_file(
-        Input::new("tests/comments.asm").unwrap(),
-        Duration::from_millis(250),
+fn uart() {
+    if let Err(err) = test_machine(
+        Input::new("tests/uart.asm").unwrap(),
+        &[(
+            "builtin:uart:backend=file,in=tests/uart.in",
+            &[0xFFD0, 0xFFD1],
+        )],
+        100_000,
         stdout(),
     ) {
         err.scream()
@@ -243,13 +314,33 @@ fn comments() {
 
 #[cfg(test)]
 #[test]
-#[should_panic]
-fn timeout() {
-    if let Err(err) = test_file(
-        Input::new("tests/timeout.asm").unwrap(),
-        Duration::from_millis(250),
+fn timer() {
+    if let Err(err) = test_machine(
+        Input::new("tests/timer.asm").unwrap(),
+        &[(
+            "builtin:timer",
+            &[0xFFE0, 0xFFE1, 0xFFE2, 0xFFE3, 0xFFE4, 0xFFE5, 0xFFE6],
+        )],
+        100_000,
         stdout(),
     ) {
         err.scream()
     }
 }
+
+#[cfg(test)]
+#[test]
+fn machine() {
+    if let Err(err) = test_file(Input::new("tests/machine.asm").unwrap(), 100_000, stdout()) {
+        err.scream()
+    }
+}
+
+#[cfg(test)]
+#[test]
+#[should_panic]
+fn timeout() {
+    if let Err(err) = test_file(Input::new("tests/timeout.asm").unwrap(), 100_000, stdout()) {
+        err.scream()
+    }
+}
@@ -0,0 +1,14 @@
+/// reads back values stored in a `builtin:latch` peripheral at 0xFFD0 and 0xFFD1
+///
+/// a: 0x2A
+/// b: 0x07
+
+mv E, 0x2A
+mv F, 0x07
+st [0xFFD0], E
+st [0xFFD1], F
+
+ld A, [0xFFD0]
+ld B, [0xFFD1]
+
+halt
@@ -0,0 +1,22 @@
+/// uses the peripherals attached by a machine file
+/// machine: board.toml
+///
+/// a: 0x05
+/// b: 0x2A
+/// c: 0x01
+
+    ld A, [0xFFD0]  ; initial latch value
+    mv E, 0x2A
+    st [0xFFD1], E
+    ld B, [0xFFD1]
+
+    mv E, 3
+    st [0xFFE4], E  ; COUNT low
+    mv E, 0b001
+    st [0xFFE0], E  ; CTRL: enable
+.wait:
+    ld C, [0xFFE1]  ; STATUS
+    and C, 0b01     ; overflow
+    jz C, [.wait]
+
+    halt
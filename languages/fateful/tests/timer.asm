@@ -0,0 +1,19 @@
+/// waits for a one-shot `builtin:timer` at 0xFFE0 through 0xFFE6 to overflow
+///
+/// a: 0x01
+/// b: 0x03
+/// c: 0x04
+
+    mv E, 10
+    st [0xFFE4], E  ; COUNT low
+    mv E, 0b101
+    st [0xFFE0], E  ; CTRL: enable and raise ATTN
+
+.wait:
+    ld A, [0xFFFC]
+    jz A, [.wait]
+
+    ld B, [0xFFE1]  ; STATUS: overflowed, and matched the default compare value of 0
+    ld C, [0xFFE0]  ; CTRL
+
+    halt
@@ -0,0 +1,17 @@
+/// reads `tests/uart.in` through a `builtin:uart` peripheral at 0xFFD0 (data) and 0xFFD1 (status)
+///
+/// a: 0x01
+/// b: 0x03
+/// c: 0x48
+/// d: 0x69
+/// e: 0x02
+/// f: 0x00
+
+ld A, [0xFFFC]
+ld B, [0xFFD1]
+ld C, [0xFFD0]
+ld D, [0xFFD0]
+ld E, [0xFFD1]
+ld F, [0xFFFC]
+
+halt